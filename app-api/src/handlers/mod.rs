@@ -1,9 +1,15 @@
+// actix 处理函数按提取器注入依赖，参数较多
+#![allow(clippy::too_many_arguments)]
 pub mod common;
 mod download;
+pub mod s3;
+pub mod s3_sign;
 pub mod upload;
 use actix_web::web;
 use ::common::AppState;
 pub fn configure(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
     common::configure(cfg);
     upload::configure(cfg, state.clone());
+    // S3 路由兜底匹配，必须最后注册
+    s3::configure(cfg, state.clone());
 }
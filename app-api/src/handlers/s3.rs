@@ -0,0 +1,971 @@
+use crate::handlers::s3_sign::{query_pairs, AwsChunkedDecoder, PayloadCheck, SigV4Auth, URI_ENCODE_SET};
+use crate::handlers::upload::{cache_path, check_and_save_path, file_full_path, insert_file_name, CHUNK_SIZE};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use bytes::Bytes;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use common::{build_snow_id, build_time, chunk_stream, parse_range, AppError, AppState, ByteRange, ExpectedDigest, FileDigest, FileHasher, RangeRequest, StorageBackend};
+use std::sync::Arc;
+use futures_util::StreamExt;
+use log::error;
+use model::*;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use sanitize_filename::sanitize;
+use std::collections::HashMap;
+use std::fmt;
+use web::Data;
+
+const XML_NS: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+const MAX_KEYS: usize = 1000;
+const LIST_BATCH: i64 = 1000;
+const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+/// S3 兼容接口，路径风格：`/{bucket}/{key}`
+///
+/// 必须最后注册，`/status`、`/upload/...` 等已有路由优先匹配，
+/// 因此 `upload`、`status` 不能作为 S3 的 bucket 名使用。
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(
+        web::resource(["/", "/{bucket}", "/{bucket}/{key:.*}"])
+            .route(web::get().to(s3_get))
+            .route(web::head().to(s3_head))
+            .route(web::put().to(s3_put))
+            .route(web::post().to(s3_post))
+            .route(web::delete().to(s3_delete)),
+    );
+}
+
+/// S3 规范的错误响应
+#[derive(Debug)]
+pub struct S3Error {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl S3Error {
+    pub fn new(status: StatusCode, code: &'static str, message: &str) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+    pub fn access_denied(message: &str) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "AccessDenied", message)
+    }
+    pub fn signature_mismatch() -> Self {
+        Self::new(
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch",
+            "The request signature we calculated does not match the signature you provided.",
+        )
+    }
+    pub fn request_time_too_skewed() -> Self {
+        Self::new(StatusCode::FORBIDDEN, "RequestTimeTooSkewed", "The difference between the request time and the server's time is too large.")
+    }
+    pub fn invalid_argument(message: &str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidArgument", message)
+    }
+    pub fn incomplete_body(message: &str) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "IncompleteBody", message)
+    }
+    pub fn invalid_digest() -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "InvalidDigest", "The Content-MD5 or checksum value that you specified is not valid.")
+    }
+    pub fn bad_digest() -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "BadDigest", "The Content-MD5 or checksum value that you specified did not match what the server received.")
+    }
+    fn no_such_bucket() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchBucket", "The specified bucket does not exist.")
+    }
+    fn no_such_key() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "NoSuchKey", "The specified key does not exist.")
+    }
+    fn invalid_range() -> Self {
+        Self::new(StatusCode::RANGE_NOT_SATISFIABLE, "InvalidRange", "The requested range is not satisfiable")
+    }
+    fn not_implemented() -> Self {
+        Self::new(StatusCode::NOT_IMPLEMENTED, "NotImplemented", "A header or operation you provided implies functionality that is not implemented.")
+    }
+}
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for S3Error {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?><Error><Code>{}</Code><Message>{}</Message></Error>"#,
+            self.code,
+            xml_escape(&self.message)
+        );
+        HttpResponse::build(self.status).content_type("application/xml").body(body)
+    }
+}
+
+impl From<AppError> for S3Error {
+    fn from(e: AppError) -> Self {
+        match e {
+            AppError::NotFound(_) => S3Error::no_such_key(),
+            AppError::NoRight(msg) => S3Error::access_denied(&msg),
+            AppError::BizError(msg) if msg == "quota.exceeded" => {
+                S3Error::new(StatusCode::FORBIDDEN, "QuotaExceeded", "The bucket quota has been exceeded.")
+            }
+            AppError::InvalidInput(msg) | AppError::BizError(msg) => S3Error::invalid_argument(&msg),
+            e => {
+                error!("S3 Internal Error: {}", e);
+                S3Error::new(StatusCode::INTERNAL_SERVER_ERROR, "InternalError", "We encountered an internal error. Please try again.")
+            }
+        }
+    }
+}
+
+struct S3Context {
+    user: Option<Credential>,
+    payload: PayloadCheck,
+}
+
+async fn authenticate(req: &HttpRequest, api_key_rep: &ApiKeyRepository) -> Result<S3Context, S3Error> {
+    let auth = match SigV4Auth::from_request(req)? {
+        Some(auth) => auth,
+        None => {
+            return Ok(S3Context {
+                user: None,
+                payload: PayloadCheck::Unsigned,
+            });
+        }
+    };
+    let credential = match api_key_rep.find_credential(&auth.access_key).await {
+        Ok(credential) => credential,
+        Err(AppError::NotFound(_)) => {
+            return Err(S3Error::new(StatusCode::FORBIDDEN, "InvalidAccessKeyId", "The AWS access key Id you provided does not exist in our records."));
+        }
+        Err(AppError::NoRight(_)) => return Err(S3Error::access_denied("The AWS access key Id you provided has been revoked or has expired.")),
+        Err(e) => return Err(e.into()),
+    };
+    let payload = auth.verify(req, &credential.secret_key)?;
+    api_key_rep.mark_used(&credential).await;
+    Ok(S3Context { user: Some(credential), payload })
+}
+
+async fn find_bucket(name: &str, bucket_rep: &BucketRepository) -> Result<Bucket, S3Error> {
+    match bucket_rep.find_by_name(&name.to_string()).await {
+        Ok(bucket) => Ok(bucket),
+        Err(AppError::NotFound(_)) => Err(S3Error::no_such_bucket()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl S3Context {
+    fn audit(&self, audit: &mut AuditEntry) {
+        if let Some(credential) = &self.user {
+            audit.actor(credential.user.id);
+        }
+    }
+}
+
+/// 签名错误与无权限记为 denied
+async fn record_audit(audit_rep: &AuditLogRepository, audit: &mut AuditEntry, response: &Result<HttpResponse, S3Error>) {
+    match response {
+        Ok(_) => audit.succeeded(),
+        Err(e) => audit.failed(e.status == StatusCode::FORBIDDEN, &e.to_string()),
+    };
+    audit_rep.record(audit).await;
+}
+
+/// 按 key 所在目录校验权限，目录授权对其下全部对象生效
+async fn check_access(ctx: &S3Context, bucket: &Bucket, key: &str, action: Action, auth_service: &AuthService) -> Result<(), S3Error> {
+    let user = ctx.user.as_ref().map(|credential| credential.subject);
+    match auth_service.authorize_path(user, bucket, key_dir(key), action).await {
+        Ok(()) => Ok(()),
+        Err(AppError::NoRight(_)) => Err(S3Error::access_denied("Access Denied")),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// key 所在目录，以 / 结尾的 key 为目录本身
+fn key_dir(key: &str) -> &str {
+    match key.strip_suffix('/') {
+        Some(dir) => dir,
+        None => key.rsplit_once('/').map(|(dir, _)| dir).unwrap_or(""),
+    }
+}
+
+/// 从原始路径解析 bucket 与 key，key 按 S3 规则解码
+fn split_path(req: &HttpRequest) -> (String, String) {
+    let path = req.uri().path().trim_start_matches('/');
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    (
+        percent_decode_str(bucket).decode_utf8_lossy().to_string(),
+        percent_decode_str(key).decode_utf8_lossy().to_string(),
+    )
+}
+
+/// key 拆分为目录与文件名，a/b/c.txt => (a/b, c.txt)
+fn split_key(key: &str) -> Result<(String, String), S3Error> {
+    let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+    for segment in dir.split('/').filter(|s| !s.is_empty()).chain(std::iter::once(name)) {
+        if segment.is_empty() || sanitize(segment) != segment {
+            return Err(S3Error::invalid_argument("Object key contains unsupported characters."));
+        }
+    }
+    if name.len() > 64 || dir.len() > 128 {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "KeyTooLongError", "Your key is too long."));
+    }
+    Ok((dir.to_string(), name.to_string()))
+}
+
+async fn find_object(file_rep: &FileRepository, bucket: &Bucket, key: &str) -> Result<FileInfo, S3Error> {
+    let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+    let full_path = match dir {
+        "" => String::new(),
+        dir => format!("{}/", dir),
+    };
+    match file_rep.find_by_key(bucket.id, &full_path, name).await? {
+        Some(file) => Ok(file),
+        None => Err(S3Error::no_such_key()),
+    }
+}
+
+fn local_to_utc(time: &NaiveDateTime) -> DateTime<Utc> {
+    match Local.from_local_datetime(time).earliest() {
+        Some(time) => time.with_timezone(&Utc),
+        None => time.and_utc(),
+    }
+}
+
+fn object_etag(file: &FileInfo) -> String {
+    // S3 客户端按 MD5 校验 ETag；旧数据没有摘要时使用带 `-` 的 ETag 表示非 MD5
+    match file.md5.is_empty() {
+        true => format!("\"{:x}-{}\"", file.id, file.items.len()),
+        false => format!("\"{}\"", file.md5),
+    }
+}
+
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn xml_response(body: String) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/xml")
+        .body(format!(r#"<?xml version="1.0" encoding="UTF-8"?>{}"#, body))
+}
+
+async fn s3_get(
+    req: HttpRequest,
+    app_state: Data<AppState>,
+    api_key_rep: Data<ApiKeyRepository>,
+    bucket_rep: Data<BucketRepository>,
+    user_bucket_rep: Data<UserBucketRepository>,
+    auth_service: Data<AuthService>,
+    file_rep: Data<FileRepository>,
+    path_rep: Data<PathRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = split_path(&req);
+    if bucket_name.is_empty() || key.is_empty() {
+        let ctx = authenticate(&req, &api_key_rep).await?;
+        if bucket_name.is_empty() {
+            return list_buckets(&ctx, &bucket_rep, &user_bucket_rep).await;
+        }
+        let bucket = find_bucket(&bucket_name, &bucket_rep).await?;
+        let query: HashMap<String, String> = query_pairs(req.query_string()).into_iter().collect();
+        // 列表按 prefix 所在目录校验，只有子目录授权的用户需要带上 prefix
+        check_access(&ctx, &bucket, query.get("prefix").map(String::as_str).unwrap_or(""), Action::Read, &auth_service).await?;
+        if query.contains_key("location") {
+            return Ok(xml_response(format!(r#"<LocationConstraint xmlns="{}"></LocationConstraint>"#, XML_NS)));
+        }
+        let mut permissions = auth_service.path_permission_cache(ctx.user.as_ref().map(|credential| credential.subject), &bucket);
+        return list_objects(&bucket, &query, &mut permissions, &file_rep, &path_rep).await;
+    }
+    let mut audit = AuditEntry::new(&req, AuditAction::Download);
+    audit.target(0, &key);
+    let response: Result<HttpResponse, S3Error> = async {
+        let ctx = authenticate(&req, &api_key_rep).await?;
+        ctx.audit(&mut audit);
+        let bucket = find_bucket(&bucket_name, &bucket_rep).await?;
+        audit.bucket(bucket.id);
+        check_access(&ctx, &bucket, &key, Action::Read, &auth_service).await?;
+        let file = find_object(&file_rep, &bucket, &key).await?;
+        audit.target(file.id, &key);
+        object_response(&req, file, Some(app_state.storage.clone()))
+    }.await;
+    record_audit(&audit_rep, &mut audit, &response).await;
+    response
+}
+
+async fn s3_head(
+    req: HttpRequest,
+    api_key_rep: Data<ApiKeyRepository>,
+    bucket_rep: Data<BucketRepository>,
+    auth_service: Data<AuthService>,
+    file_rep: Data<FileRepository>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = split_path(&req);
+    if bucket_name.is_empty() {
+        return Err(S3Error::not_implemented());
+    }
+    let ctx = authenticate(&req, &api_key_rep).await?;
+    let bucket = find_bucket(&bucket_name, &bucket_rep).await?;
+    check_access(&ctx, &bucket, &key, Action::Read, &auth_service).await?;
+    if key.is_empty() {
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let file = find_object(&file_rep, &bucket, &key).await?;
+    object_response(&req, file, None)
+}
+
+/// storage 为空时只返回响应头（HEAD）
+///
+/// 与 S3 一致只支持单个区间，多个区间时返回整个对象
+fn object_response(req: &HttpRequest, file: FileInfo, storage: Option<Arc<dyn StorageBackend>>) -> Result<HttpResponse, S3Error> {
+    let size = file.size as u64;
+    let range_header = req.headers().get(header::RANGE).and_then(|value| value.to_str().ok());
+    let range = match parse_range(range_header, size) {
+        RangeRequest::Unsatisfiable => return Err(S3Error::invalid_range()),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => Some(ranges[0]),
+        _ => None,
+    };
+    let last_modified = local_to_utc(&file.create_time).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let mut builder = match range {
+        Some(range) => {
+            let mut builder = HttpResponse::PartialContent();
+            builder.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end, size)));
+            builder
+        }
+        None => HttpResponse::Ok(),
+    };
+    builder
+        .insert_header((header::CONTENT_TYPE, "application/octet-stream"))
+        .insert_header((header::ETAG, object_etag(&file)))
+        .insert_header((header::LAST_MODIFIED, last_modified))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .no_chunking(range.map_or(size, |range| range.length()));
+    // Digest 为整个对象的摘要，部分响应不返回
+    if let (None, Some(digest)) = (range, file.digest().digest_header()) {
+        builder.insert_header(("Digest", digest));
+    }
+    let storage = match storage {
+        Some(storage) if size > 0 => storage,
+        _ => return Ok(builder.streaming(futures_util::stream::empty::<Result<Bytes, AppError>>())),
+    };
+    let range = range.unwrap_or(ByteRange { start: 0, end: size - 1 });
+    // 逐个分片校验 sha256，分片损坏时中断响应
+    let body = chunk_stream(storage, file.chunk_refs(), range).inspect(|data| {
+        if let Err(e) = data {
+            error!("read object error: {}", e);
+        }
+    });
+    Ok(builder.streaming(body))
+}
+
+async fn s3_put(
+    req: HttpRequest,
+    payload: web::Payload,
+    app_state: Data<AppState>,
+    api_key_rep: Data<ApiKeyRepository>,
+    bucket_rep: Data<BucketRepository>,
+    auth_service: Data<AuthService>,
+    file_rep: Data<FileRepository>,
+    path_rep: Data<PathRepository>,
+    chunk_rep: Data<ChunkRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = split_path(&req);
+    if bucket_name.is_empty() {
+        return Err(S3Error::not_implemented());
+    }
+    if req.headers().contains_key("x-amz-copy-source") || req.query_string().contains("uploadId") {
+        return Err(S3Error::not_implemented());
+    }
+    let action = match key.as_str() {
+        "" => AuditAction::BucketSave,
+        key if key.ends_with('/') => AuditAction::Mkdir,
+        _ => AuditAction::Upload,
+    };
+    let mut audit = AuditEntry::new(&req, action);
+    audit.target(0, &key);
+    let response = put_object(req, payload, &bucket_name, &key, &app_state, &api_key_rep, &bucket_rep, &auth_service, &file_rep, &path_rep, &chunk_rep, &mut audit).await;
+    record_audit(&audit_rep, &mut audit, &response).await;
+    response
+}
+
+/// 不支持分片上传（`POST ?uploads`）、批量删除等 POST 操作，返回 NotImplemented
+///
+/// aws-cli 等客户端需要把分片上传阈值调到单个对象的大小以上
+async fn s3_post() -> Result<HttpResponse, S3Error> {
+    Err(S3Error::not_implemented())
+}
+
+/// 上传对象、创建目录（key 以 / 结尾）或创建 bucket（key 为空）
+async fn put_object(
+    req: HttpRequest,
+    payload: web::Payload,
+    bucket_name: &str,
+    key: &str,
+    app_state: &Data<AppState>,
+    api_key_rep: &ApiKeyRepository,
+    bucket_rep: &BucketRepository,
+    auth_service: &AuthService,
+    file_rep: &FileRepository,
+    path_rep: &PathRepository,
+    chunk_rep: &ChunkRepository,
+    audit: &mut AuditEntry,
+) -> Result<HttpResponse, S3Error> {
+    let ctx = authenticate(&req, api_key_rep).await?;
+    ctx.audit(audit);
+    if key.is_empty() {
+        audit.target(0, bucket_name);
+        return create_bucket(&ctx, bucket_name, bucket_rep).await;
+    }
+    let bucket = find_bucket(bucket_name, bucket_rep).await?;
+    audit.bucket(bucket.id);
+    check_access(&ctx, &bucket, key, Action::Write, auth_service).await?;
+
+    // 以 / 结尾的 key 视为目录
+    if let Some(dir) = key.strip_suffix('/') {
+        split_key(&format!("{}/_", dir))?;
+        let dir = dir.to_string();
+        let (path_id, full_path) = {
+            let (bucket_id, dir, db_path_cache) = (bucket.id, &dir, &app_state.db_path_cache);
+            with_transaction(&path_rep.dao.pool, |mut tx| async move {
+                let saved = check_and_save_path(&mut tx, &bucket_id, dir, db_path_cache, path_rep).await?;
+                Ok((saved, tx))
+            }).await?
+        };
+        cache_path(&app_state.db_path_cache, &bucket.id, &full_path, path_id).await;
+        return Ok(HttpResponse::Ok().insert_header((header::ETAG, format!("\"{}\"", EMPTY_MD5))).finish());
+    }
+    let (dir, name) = split_key(key)?;
+    let expected = ExpectedDigest::from_headers(req.headers()).map_err(|_| S3Error::invalid_digest())?;
+    let mut items: Vec<FileItemDto> = Vec::new();
+    let (size, digest) = match write_body(payload, ctx.payload, app_state, bucket_rep, bucket.id, chunk_rep, &mut items).await {
+        Ok(result) => result,
+        Err(e) => {
+            remove_chunks(&*app_state.storage, chunk_rep, &items).await;
+            return Err(e);
+        }
+    };
+    if expected.verify(&digest).is_err() {
+        remove_chunks(&*app_state.storage, chunk_rep, &items).await;
+        return Err(S3Error::bad_digest());
+    }
+    let file_id = build_snow_id();
+    audit.target(file_id, key);
+    // 目录、覆盖写时旧文件的删除（归还配额）、配额占用与新文件记录在同一事务中提交
+    let saved = {
+        let (bucket_id, dir, name, items, digest, db_path_cache) = (bucket.id, &dir, &name, &items, &digest, &app_state.db_path_cache);
+        with_transaction(&file_rep.dao.pool, |mut tx| async move {
+            let (path_id, dir_full_path) = check_and_save_path(&mut tx, &bucket_id, dir, db_path_cache, path_rep).await?;
+            let old_file = file_rep.find_by_key_with(&mut *tx, bucket_id, &file_full_path(path_id, &dir_full_path), name).await?;
+            let mut replaced = None;
+            if let Some(old_file) = old_file
+                && file_rep.delete_file_with(&mut tx, &old_file).await?
+            {
+                replaced = Some(old_file);
+            }
+            // 先归还旧文件的配额，覆盖写只需要差额
+            bucket_rep.reserve_quota_with(&mut *tx, bucket_id, size as i64).await?;
+            insert_file_name(&mut tx, &bucket_id, file_rep, file_id, path_id, name, &dir_full_path, items.clone(), &size, digest, true).await?;
+            Ok(((path_id, dir_full_path, replaced), tx))
+        }).await
+    };
+    let (path_id, dir_full_path, replaced) = match saved {
+        Ok(result) => result,
+        Err(e) => {
+            remove_chunks(&*app_state.storage, chunk_rep, &items).await;
+            return Err(e.into());
+        }
+    };
+    cache_path(&app_state.db_path_cache, &bucket.id, &dir_full_path, path_id).await;
+    if let Some(old_file) = replaced {
+        release_file_chunks(&*app_state.storage, chunk_rep, &old_file).await;
+    }
+    Ok(HttpResponse::Ok().insert_header((header::ETAG, format!("\"{}\"", digest.md5))).finish())
+}
+
+/// 按 4MB 分片写入请求体，每个分片写入前检查配额，返回 (大小, 文件摘要)
+async fn write_body(
+    mut payload: web::Payload,
+    check: PayloadCheck,
+    app_state: &AppState,
+    bucket_rep: &BucketRepository,
+    bucket_id: i64,
+    chunk_rep: &ChunkRepository,
+    items: &mut Vec<FileItemDto>,
+) -> Result<(usize, FileDigest), S3Error> {
+    let (mut decoder, expected_sha256) = match check {
+        PayloadCheck::Unsigned => (None, None),
+        PayloadCheck::Sha256(hash) => (None, Some(hash)),
+        PayloadCheck::Chunked(signer) => (Some(AwsChunkedDecoder::new(signer)), None),
+    };
+    let mut hasher = FileHasher::new();
+    let mut buffer: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
+    let mut size: usize = 0;
+    while let Some(bytes) = payload.next().await {
+        let bytes = bytes.map_err(|e| S3Error::incomplete_body(&e.to_string()))?;
+        let data = match decoder.as_mut() {
+            Some(decoder) => decoder.push(&bytes)?,
+            None => bytes.to_vec(),
+        };
+        hasher.update(&data);
+        size += data.len();
+        if size > u32::MAX as usize {
+            return Err(S3Error::new(StatusCode::BAD_REQUEST, "EntityTooLarge", "Your proposed upload exceeds the maximum allowed object size."));
+        }
+        buffer.extend_from_slice(&data);
+        while buffer.len() >= CHUNK_SIZE {
+            let rest = buffer.split_off(CHUNK_SIZE);
+            items.push(chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_id, size, &buffer).await?);
+            buffer = rest;
+        }
+    }
+    if let Some(decoder) = &decoder {
+        decoder.finish()?;
+    }
+    if !buffer.is_empty() {
+        items.push(chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_id, size, &buffer).await?);
+    }
+    let digest = hasher.finish();
+    if let Some(expected) = expected_sha256
+        && digest.sha256 != expected
+    {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "XAmzContentSHA256Mismatch", "The provided 'x-amz-content-sha256' header does not match what was computed."));
+    }
+    Ok((size, digest))
+}
+
+/// 释放未入库的分片，配额在入库事务中占用，这里不需要归还
+async fn remove_chunks(storage: &dyn StorageBackend, chunk_rep: &ChunkRepository, items: &[FileItemDto]) {
+    if let Err(e) = chunk_rep.release_items(storage, items).await {
+        error!("release chunks error: {}", e);
+    }
+}
+
+/// 删除文件记录（同时归还配额）后释放分片
+async fn delete_file(storage: &dyn StorageBackend, file_rep: &FileRepository, chunk_rep: &ChunkRepository, file: &FileInfo) -> Result<(), S3Error> {
+    if file_rep.delete_file(file).await? {
+        release_file_chunks(storage, chunk_rep, file).await;
+    }
+    Ok(())
+}
+
+/// 释放已删除记录的文件分片，失败只记录日志
+async fn release_file_chunks(storage: &dyn StorageBackend, chunk_rep: &ChunkRepository, file: &FileInfo) {
+    if let Err(e) = chunk_rep.release_items(storage, &file.stored_items()).await {
+        error!("release chunks error: {}", e);
+    }
+}
+
+async fn s3_delete(
+    req: HttpRequest,
+    app_state: Data<AppState>,
+    api_key_rep: Data<ApiKeyRepository>,
+    bucket_rep: Data<BucketRepository>,
+    auth_service: Data<AuthService>,
+    file_rep: Data<FileRepository>,
+    path_rep: Data<PathRepository>,
+    chunk_rep: Data<ChunkRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<HttpResponse, S3Error> {
+    let (bucket_name, key) = split_path(&req);
+    if bucket_name.is_empty() || key.is_empty() {
+        return Err(S3Error::not_implemented());
+    }
+    let action = match key.ends_with('/') {
+        true => AuditAction::PathDelete,
+        false => AuditAction::FileDelete,
+    };
+    let mut audit = AuditEntry::new(&req, action);
+    audit.target(0, &key);
+    let response: Result<HttpResponse, S3Error> = async {
+        let ctx = authenticate(&req, &api_key_rep).await?;
+        ctx.audit(&mut audit);
+        let bucket = find_bucket(&bucket_name, &bucket_rep).await?;
+        audit.bucket(bucket.id);
+        check_access(&ctx, &bucket, &key, Action::Delete, &auth_service).await?;
+        delete_object(&bucket, &key, &app_state, &file_rep, &path_rep, &chunk_rep, &mut audit).await
+    }.await;
+    record_audit(&audit_rep, &mut audit, &response).await;
+    response
+}
+
+/// 删除对象，key 以 / 结尾时只删除空目录
+async fn delete_object(
+    bucket: &Bucket,
+    key: &str,
+    app_state: &AppState,
+    file_rep: &FileRepository,
+    path_rep: &PathRepository,
+    chunk_rep: &ChunkRepository,
+    audit: &mut AuditEntry,
+) -> Result<HttpResponse, S3Error> {
+    if let Some(dir) = key.strip_suffix('/') {
+        let paths = path_rep
+            .dao
+            .query_by_params(vec![QueryParam::eq("bucket_id", bucket.id.to_string().as_str()), QueryParam::eq("full_path", dir)])
+            .await?;
+        for path in paths {
+            let children = path_rep.dao.query_by_count(vec![QueryParam::eq("parent", path.id.to_string().as_str())]).await?;
+            let files = file_rep.dao.query_by_count(vec![QueryParam::eq("path_ref", path.id.to_string().as_str())]).await?;
+            if children == 0 && files == 0 {
+                audit.target(path.id, key);
+                path_rep.dao.del_by_id(path.id).await?;
+            }
+        }
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    match find_object(file_rep, bucket, key).await {
+        Ok(file) => {
+            audit.target(file.id, key);
+            delete_file(&*app_state.storage, file_rep, chunk_rep, &file).await?
+        }
+        Err(e) if e.code == "NoSuchKey" => {}
+        Err(e) => return Err(e),
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn list_buckets(ctx: &S3Context, bucket_rep: &BucketRepository, user_bucket_rep: &UserBucketRepository) -> Result<HttpResponse, S3Error> {
+    let (user, subject) = match &ctx.user {
+        Some(credential) => (&credential.user, credential.subject),
+        None => return Err(S3Error::access_denied("Access Denied")),
+    };
+    let mut buckets = if user.is_admin {
+        bucket_rep.dao.get_all().await?
+    } else {
+        let mut buckets = Vec::new();
+        let rights = user_bucket_rep.dao.query_by_params(vec![QueryParam::eq("user_id", user.id.to_string().as_str())]).await?;
+        for right in rights {
+            buckets.push(bucket_rep.dao.find_by_id(right.bucket_id).await?);
+        }
+        buckets
+    };
+    // 限定 bucket 的密钥只列出该 bucket
+    if let Some(scope) = subject.scope.filter(|scope| scope.bucket_id != 0) {
+        buckets.retain(|bucket| bucket.id == scope.bucket_id);
+    }
+    let mut body = format!(
+        r#"<ListAllMyBucketsResult xmlns="{}"><Owner><ID>{}</ID><DisplayName>{}</DisplayName></Owner><Buckets>"#,
+        XML_NS,
+        user.id,
+        xml_escape(&user.user_name)
+    );
+    for bucket in buckets {
+        body.push_str(&format!(
+            "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+            xml_escape(&bucket.name),
+            local_to_utc(&bucket.create_time).format("%Y-%m-%dT%H:%M:%S%.3fZ")
+        ));
+    }
+    body.push_str("</Buckets></ListAllMyBucketsResult>");
+    Ok(xml_response(body))
+}
+
+async fn create_bucket(ctx: &S3Context, bucket_name: &str, bucket_rep: &BucketRepository) -> Result<HttpResponse, S3Error> {
+    // 限定范围的密钥不能创建 bucket
+    match &ctx.user {
+        Some(credential) if credential.user.is_admin && credential.subject.scope.is_none_or(|scope| scope.bucket_id == 0 && !scope.read_only) => {}
+        _ => return Err(S3Error::access_denied("Access Denied")),
+    }
+    let valid = (3..=32).contains(&bucket_name.len())
+        && bucket_name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.');
+    if !valid {
+        return Err(S3Error::new(StatusCode::BAD_REQUEST, "InvalidBucketName", "The specified bucket is not valid."));
+    }
+    match bucket_rep.find_by_name(&bucket_name.to_string()).await {
+        Ok(_) => {
+            return Err(S3Error::new(StatusCode::CONFLICT, "BucketAlreadyOwnedByYou", "Your previous request to create the named bucket succeeded and you already own it."));
+        }
+        Err(AppError::NotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let mut params: HashMap<&str, String> = HashMap::new();
+    params.insert("id", build_snow_id().to_string());
+    params.insert("name", bucket_name.to_string());
+    params.insert("quota", "0".to_owned());
+    params.insert("current_quota", "0".to_owned());
+    params.insert("pub_read", "0".to_owned());
+    params.insert("pub_write", "0".to_owned());
+    params.insert("create_time", build_time().await);
+    bucket_rep.dao.insert(params).await?;
+    Ok(HttpResponse::Ok().insert_header((header::LOCATION, format!("/{}", bucket_name))).finish())
+}
+
+#[derive(Default)]
+struct ListResult {
+    contents: Vec<FileInfo>,
+    prefixes: Vec<String>,
+    truncated: bool,
+    cursor: String,
+    next_marker: String,
+}
+
+/// ListObjects / ListObjectsV2
+async fn list_objects(
+    bucket: &Bucket,
+    query: &HashMap<String, String>,
+    permissions: &mut PathPermissionCache<'_>,
+    file_rep: &FileRepository,
+    path_rep: &PathRepository,
+) -> Result<HttpResponse, S3Error> {
+    let empty = String::new();
+    let v2 = query.get("list-type").map(|v| v == "2").unwrap_or(false);
+    let prefix = query.get("prefix").unwrap_or(&empty);
+    let delimiter = query.get("delimiter").unwrap_or(&empty);
+    let url_encode = query.get("encoding-type").map(|v| v == "url").unwrap_or(false);
+    let max_keys = match query.get("max-keys") {
+        Some(v) => v.parse::<usize>().map_err(|_| S3Error::invalid_argument("max-keys is invalid"))?.min(MAX_KEYS),
+        None => MAX_KEYS,
+    };
+    // marker / start-after 为不包含的下界，continuation-token 为包含的下界
+    let marker = match v2 {
+        true => query.get("start-after").unwrap_or(&empty),
+        false => query.get("marker").unwrap_or(&empty),
+    };
+    let token = match query.get("continuation-token").filter(|_| v2) {
+        Some(token) => {
+            let bytes = hex::decode(token).map_err(|_| S3Error::invalid_argument("The continuation token provided is incorrect"))?;
+            Some(String::from_utf8(bytes).map_err(|_| S3Error::invalid_argument("The continuation token provided is incorrect"))?)
+        }
+        None => None,
+    };
+    let mut start = match &token {
+        Some(token) => token.clone(),
+        None if !marker.is_empty() => format!("{}\0", marker),
+        None => String::new(),
+    };
+    if start.as_str() < prefix.as_str() {
+        start = prefix.clone();
+    }
+    let result = list_entries(bucket.id, prefix, delimiter, marker, start, max_keys, permissions, file_rep, path_rep).await?;
+
+    let encode = |value: &str| match url_encode {
+        true => utf8_percent_encode(value, URI_ENCODE_SET).to_string().replace("%2F", "/"),
+        false => xml_escape(value),
+    };
+    let mut body = format!(
+        r#"<ListBucketResult xmlns="{}"><Name>{}</Name><Prefix>{}</Prefix><MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>"#,
+        XML_NS,
+        xml_escape(&bucket.name),
+        encode(prefix),
+        max_keys,
+        result.truncated
+    );
+    if !delimiter.is_empty() {
+        body.push_str(&format!("<Delimiter>{}</Delimiter>", encode(delimiter)));
+    }
+    if url_encode {
+        body.push_str("<EncodingType>url</EncodingType>");
+    }
+    if v2 {
+        body.push_str(&format!("<KeyCount>{}</KeyCount>", result.contents.len() + result.prefixes.len()));
+        if let Some(token) = query.get("continuation-token") {
+            body.push_str(&format!("<ContinuationToken>{}</ContinuationToken>", xml_escape(token)));
+        }
+        if !marker.is_empty() {
+            body.push_str(&format!("<StartAfter>{}</StartAfter>", encode(marker)));
+        }
+        if result.truncated {
+            body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", hex::encode(result.cursor.as_bytes())));
+        }
+    } else {
+        body.push_str(&format!("<Marker>{}</Marker>", encode(marker)));
+        if result.truncated && !delimiter.is_empty() {
+            body.push_str(&format!("<NextMarker>{}</NextMarker>", encode(&result.next_marker)));
+        }
+    }
+    for file in &result.contents {
+        body.push_str(&format!(
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            encode(&format!("{}{}", file.full_path, file.name)),
+            local_to_utc(&file.create_time).format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+            xml_escape(&object_etag(file)),
+            file.size
+        ));
+    }
+    for prefix in &result.prefixes {
+        body.push_str(&format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", encode(prefix)));
+    }
+    body.push_str("</ListBucketResult>");
+    Ok(xml_response(body))
+}
+
+/// 按字节序遍历 key，把包含分隔符的 key 归并为 CommonPrefixes
+///
+/// 所在目录不可读的 key 直接跳过，只由不可读 key 组成的前缀不返回
+async fn list_entries(
+    bucket_id: i64,
+    prefix: &str,
+    delimiter: &str,
+    marker: &str,
+    start: String,
+    max_keys: usize,
+    permissions: &mut PathPermissionCache<'_>,
+    file_rep: &FileRepository,
+    path_rep: &PathRepository,
+) -> Result<ListResult, S3Error> {
+    let mut result = ListResult {
+        cursor: start,
+        ..Default::default()
+    };
+    if max_keys == 0 {
+        return Ok(result);
+    }
+    'outer: loop {
+        let entries = fetch_entries(bucket_id, prefix, delimiter == "/", &result.cursor, file_rep, path_rep).await?;
+        let fetched = entries.len();
+        for (key, file) in entries {
+            if result.contents.len() + result.prefixes.len() >= max_keys {
+                result.truncated = true;
+                break 'outer;
+            }
+            if !permissions.allows(key_dir(&key), Action::Read).await? {
+                result.cursor = format!("{}\0", key);
+                continue;
+            }
+            let rest = &key[prefix.len()..];
+            if let Some(index) = rest.find(delimiter).filter(|_| !delimiter.is_empty()) {
+                let common_prefix = format!("{}{}", prefix, &rest[..index + delimiter.len()]);
+                result.cursor = skip_prefix(&common_prefix);
+                if common_prefix.as_str() > marker {
+                    result.next_marker = common_prefix.clone();
+                    result.prefixes.push(common_prefix);
+                }
+                // 跳过该前缀下的所有 key，重新查询
+                continue 'outer;
+            }
+            result.cursor = format!("{}\0", key);
+            if let Some(file) = file {
+                result.next_marker = key;
+                result.contents.push(file);
+            }
+        }
+        if fetched < LIST_BATCH as usize {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+/// 合并文件与目录两个有序结果，目录仅在分隔符为 `/` 时参与，用于列出空目录
+async fn fetch_entries(
+    bucket_id: i64,
+    prefix: &str,
+    with_dirs: bool,
+    cursor: &str,
+    file_rep: &FileRepository,
+    path_rep: &PathRepository,
+) -> Result<Vec<(String, Option<FileInfo>)>, S3Error> {
+    let files = file_rep.list_by_key(bucket_id, prefix, cursor, LIST_BATCH).await?;
+    let mut entries: Vec<(String, Option<FileInfo>)> = files.into_iter().map(|f| (format!("{}{}", f.full_path, f.name), Some(f))).collect();
+    if with_dirs {
+        let dirs = path_rep.list_by_key(bucket_id, prefix, cursor, LIST_BATCH).await?;
+        entries.extend(dirs.into_iter().map(|p| (format!("{}/", p.full_path), None)));
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        entries.truncate(LIST_BATCH as usize);
+    }
+    Ok(entries)
+}
+
+/// 返回大于所有以 prefix 开头的 key 的最小下界
+fn skip_prefix(prefix: &str) -> String {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    match chars.pop() {
+        Some(last) if (last as u32) < 0x7f => {
+            chars.push((last as u8 + 1) as char);
+            chars.into_iter().collect()
+        }
+        _ => format!("{}\u{10FFFF}", prefix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use common::{chunk_hash, MemoryStorage};
+    use sqlx::types::Json;
+
+    async fn stored_object(storage: &MemoryStorage, parts: &[&[u8]]) -> FileInfo {
+        let mut items = Vec::new();
+        for (i, data) in parts.iter().enumerate() {
+            let path = format!("chunk-{}", i);
+            storage.put(&path, data).await.unwrap();
+            items.push(FileItemDto {
+                path,
+                size: data.len() as u32,
+                hash: chunk_hash(data),
+            });
+        }
+        FileInfo {
+            id: 1,
+            root: true,
+            bucket_id: 1,
+            path_ref: 0,
+            name: "a.txt".to_owned(),
+            full_path: String::new(),
+            file_type: FileType::TEXT,
+            items: Json(items),
+            image_type: ImageType::NONE,
+            size: parts.iter().map(|data| data.len() as u32).sum(),
+            sha256: String::new(),
+            md5: EMPTY_MD5.to_owned(),
+            thumbnail_status: FileInfo::THUMBNAIL_SKIPPED,
+            thumbnails: None,
+            create_time: NaiveDateTime::default(),
+        }
+    }
+
+    async fn get_object(range: Option<&str>) -> Result<(StatusCode, Option<String>, Bytes), S3Error> {
+        let storage = Arc::new(MemoryStorage::new());
+        let file = stored_object(&storage, &[b"0123", b"4567", b"89"]).await;
+        let mut req = TestRequest::get();
+        if let Some(range) = range {
+            req = req.insert_header((header::RANGE, range));
+        }
+        let response = object_response(&req.to_http_request(), file, Some(storage))?;
+        let status = response.status();
+        let content_range = response.headers().get(header::CONTENT_RANGE).map(|value| value.to_str().unwrap().to_owned());
+        let body = to_bytes(response.into_body()).await.unwrap();
+        Ok((status, content_range, body))
+    }
+
+    #[actix_web::test]
+    async fn get_object_range() {
+        let (status, content_range, body) = get_object(None).await.unwrap();
+        assert_eq!((status, content_range, &body[..]), (StatusCode::OK, None, &b"0123456789"[..]));
+
+        let (status, content_range, body) = get_object(Some("bytes=2-6")).await.unwrap();
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 2-6/10"));
+        assert_eq!(&body[..], b"23456");
+
+        let (status, content_range, body) = get_object(Some("bytes=-3")).await.unwrap();
+        assert_eq!((status, content_range.as_deref(), &body[..]), (StatusCode::PARTIAL_CONTENT, Some("bytes 7-9/10"), &b"789"[..]));
+
+        // 多个区间按整个对象返回
+        let (status, _, body) = get_object(Some("bytes=0-1,5-6")).await.unwrap();
+        assert_eq!((status, &body[..]), (StatusCode::OK, &b"0123456789"[..]));
+
+        let e = get_object(Some("bytes=10-")).await.unwrap_err();
+        assert_eq!(e.status_code(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(e.code, "InvalidRange");
+    }
+}
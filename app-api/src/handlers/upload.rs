@@ -1,23 +1,33 @@
 use actix_multipart::Multipart;
-use actix_web::{post, web, HttpRequest, Responder};
-use chrono::{Datelike, Local, Timelike};
-use common::{build_id, AppError, AppState, BaseResponse, RightType};
+use actix_web::{post, put, web, HttpRequest, Responder};
+use chrono::Local;
+use common::{build_snow_id, read_chunk, result, result_data, AppError, AppState, BaseResponse, BodyHash, ExpectedDigest, FileDigest, FileHasher, PresignedRequest, RequestVerifier, SignedRequest, PRESIGN_PATH};
 use futures_util::StreamExt;
 use model::*;
 use moka::future::Cache;
 use sanitize_filename::sanitize;
-use sqlx::MySqlPool;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlConnection;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
+use std::rc::Rc;
 use std::sync::Arc;
 use web::Data;
 pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
     cfg.app_data(state.clone()).service(upload_file);
+    cfg.service(multipart_init);
+    cfg.service(multipart_part);
+    cfg.service(multipart_parts);
+    cfg.service(multipart_complete);
+    cfg.service(multipart_abort);
 }
 
-const CHUNK_SIZE: usize = 4 * 1024 * 1024; // ✅ 4MB 分片
+pub(crate) const CHUNK_SIZE: usize = 4 * 1024 * 1024; // ✅ 4MB 分片
+const MAX_PART_SIZE: usize = 16 * CHUNK_SIZE; // 单个分片最大 64MB
+// file_info.size 为 INT UNSIGNED，单个文件最大 4GB
+const MAX_FILE_SIZE: usize = u32::MAX as usize;
+const MAX_PART_NUMBER: i32 = 10000;
 
 /// **处理文件上传（4MB 分片）**
 #[post("/upload/{bucket}")]
@@ -25,256 +35,648 @@ pub async fn upload_file(
     bucket: web::Path<String>,
     app_state: Data<AppState>,
     path_info_rep: Data<PathRepository>,
+    verifier: Data<RequestVerifier>,
     bucket_rep: Data<BucketRepository>,
-    user_bucket_right_rep: Data<UserBucketRightRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
     file_rep: Data<FileRepository>,
+    chunk_rep: Data<ChunkRepository>,
+    audit_rep: Data<AuditLogRepository>,
     req: HttpRequest,
-    mut payload: Multipart,
+    body: web::Payload,
 ) -> std::result::Result<impl Responder, AppError> {
-    if (bucket.is_empty()) {
-        return Err(AppError::NotErrorNoRight("bucket.error".to_owned()));
-    }
-    let mut params: HashMap<&str, String> = HashMap::new();
-    params.insert("name", bucket.to_string());
-    let bucket_result = bucket_rep.dao.find_by_one(params).await;
-    let bucket_info = match bucket_result {
-        Ok(info) => info,
-        Err(e) => return Err(AppError::NotErrorNoRight(e.to_string())),
+    let mut audit = AuditEntry::new(&req, AuditAction::Upload);
+    let response = save_upload(
+        &bucket, &app_state, &path_info_rep, &verifier, &bucket_rep, &api_key_rep, &auth_service, &file_rep, &chunk_rep, req, body, &mut audit,
+    ).await;
+    match &response {
+        Ok(json) if !json.success => audit.outcome::<()>(&Err(AppError::InvalidInput(json.msg.clone().unwrap_or_default()))),
+        _ => audit.outcome(&response),
     };
-    if (!bucket_info.pub_write) {
-        let secret_key_header = req.headers().get("secret_key");
-        let secret_key = match secret_key_header {
-            Some(secret_key) => secret_key.to_str().unwrap().to_string(),
-            None => {
-                return Err(AppError::NotErrorNoRight("no.right".to_string()));
-            }
-        };
+    audit_rep.record(&audit).await;
+    response
+}
 
-        let access_key = req.headers().get("Access-Key");
-        match access_key {
-            Some(key) => {
-                params = HashMap::new();
-                let access_key_value = key.to_str().unwrap().to_string();
-                params.insert("access_key", access_key_value);
-                params.insert("bucket_name", bucket.to_string());
-                let user_bucket_right_result =
-                    user_bucket_right_rep.dao.query_by_params(params).await?;
-                let mut has_right: bool = false;
-                for bucket_right in user_bucket_right_result {
-                    if bucket_right.right == RightType::write {
-                        has_right = true;
-                        if secret_key == bucket_right.secret_key {
-                            return Err(AppError::NotErrorNoRight("no.right".to_string()));
-                        }
-                    }
-                }
-                if !&has_right {
-                    return Err(AppError::NotErrorNoRight("no.right".to_string()));
-                }
-            }
-            None => {
-                return Err(AppError::NotErrorNoRight("no.right".to_string()));
-            }
+async fn save_upload(
+    bucket: &str,
+    app_state: &Data<AppState>,
+    path_info_rep: &PathRepository,
+    verifier: &RequestVerifier,
+    bucket_rep: &BucketRepository,
+    api_key_rep: &ApiKeyRepository,
+    auth_service: &AuthService,
+    file_rep: &FileRepository,
+    chunk_rep: &ChunkRepository,
+    req: HttpRequest,
+    body: web::Payload,
+    audit: &mut AuditEntry,
+) -> std::result::Result<web::Json<BaseResponse<String>>, AppError> {
+    let grant = check_write_right(bucket, &req, verifier, bucket_rep, api_key_rep, auth_service).await?;
+    let bucket_info = grant.bucket.clone();
+    grant.audit(audit);
+    let expected = ExpectedDigest::from_headers(req.headers())?;
+    // 签名覆盖整个请求体，解析表单的同时计算原始请求体摘要
+    let body_hasher = Rc::new(RefCell::new(Sha256::new()));
+    let stream_hasher = body_hasher.clone();
+    let mut payload = Multipart::new(req.headers(), body.inspect(move |bytes| {
+        if let Ok(bytes) = bytes {
+            stream_hasher.borrow_mut().update(bytes);
         }
-    }
+    }));
 
     let mut path = String::new();
+    //预签名上传只能写入签名时指定的目录，忽略表单中的 path
+    if let Some(signed_path) = &grant.path {
+        path = signed_path.clone();
+    }
+    let mut authorized = false;
     let mut is_thumbnail: bool = true;
     let mut file_name: String = String::new();
-    let mut uploaded_files = Vec::new();
+    let mut uploaded_files: Vec<FileItemDto> = Vec::new();
     let mut size: usize = 0;
-    while let Some(field) = payload.next().await {
-        let mut field = field.unwrap();
+    let mut hasher = FileHasher::new();
+    // 每个分片写入前检查配额，入库时在同一事务内占用；出错时统一释放已写入的分片
+    let read_result: Result<Option<&str>, AppError> = async {
+        while let Some(field) = payload.next().await {
+            let mut field = field?;
 
-        let content_disposition = field.content_disposition().unwrap();
-        let dir_name = build_dir_name(&app_state.root_path, &app_state.dir_create_cache).await?;
+            let field_name = match field.content_disposition().and_then(|cd| cd.get_name()) {
+                Some(name) => name.to_string(),
+                None => return Err(AppError::InvalidInput("multipart.field.invalid".to_owned())),
+            };
 
-        match content_disposition.get_name().unwrap() {
-            "path" => {
-                // 读取普通表单字段（文本）
-                let mut data = String::new();
-                while let Some(chunk) = field.next().await {
-                    data.push_str(&String::from_utf8_lossy(&chunk?));
-                }
-                path = data;
-            }
-            "is_thumbnail" => {
-                // 读取普通表单字段（文本）
-                let mut data = String::new();
-                while let Some(chunk) = field.next().await {
-                    data.push_str(&String::from_utf8_lossy(&chunk?));
-                }
-                is_thumbnail = match data.to_lowercase().as_str() {
-                    // "true" | "1" | "on" | "yes" => true,
-                    "false" | "0" | "off" | "no" => false,
-                    _ => true,
-                };
-            }
-            "file" => {
-                let mut fid = build_id();
-                // 定义分片文件的路径
-                let mut buffer = Vec::with_capacity(CHUNK_SIZE);
-                file_name = field
-                    .content_disposition()
-                    .unwrap()
-                    .get_filename()
-                    .unwrap()
-                    .to_string();
-                if file_name.is_empty() {
-                    return Ok(web::Json(BaseResponse::err_result_msg(
-                        "Invalid file_name value",
-                    )));
+            match field_name.as_str() {
+                "path" => {
+                    // 读取普通表单字段（文本）
+                    let mut data = String::new();
+                    while let Some(chunk) = field.next().await {
+                        data.push_str(&String::from_utf8_lossy(&chunk?));
+                    }
+                    // 目录在读取文件内容前校验，path 必须在 file 之前
+                    if grant.path.is_none() {
+                        if authorized {
+                            return Ok(Some("path must precede file"));
+                        }
+                        path = data;
+                    }
                 }
-                while let Some(Ok(bytes)) = field.next().await {
-                    buffer.extend_from_slice(&bytes); // ✅ 累积数据
-                    size += bytes.len();
-                    if buffer.len() >= CHUNK_SIZE {
-                        //写入文件
-                        let chunk_path = format!("{}/{}", dir_name, fid.clone().to_string());
-                        uploaded_files.push(chunk_path.clone());
-                        let mut file = File::create(&chunk_path).unwrap();
-                        file.write_all(&buffer[..CHUNK_SIZE]).unwrap();
-
-                        buffer.drain(..CHUNK_SIZE); // ✅ 清除已写入的部分
-                        fid = build_id();
+                "is_thumbnail" => {
+                    // 读取普通表单字段（文本）
+                    let mut data = String::new();
+                    while let Some(chunk) = field.next().await {
+                        data.push_str(&String::from_utf8_lossy(&chunk?));
                     }
+                    is_thumbnail = match data.to_lowercase().as_str() {
+                        // "true" | "1" | "on" | "yes" => true,
+                        "false" | "0" | "off" | "no" => false,
+                        _ => true,
+                    };
                 }
-                // 处理剩余数据（小于 4MB）
-                if !buffer.is_empty() {
-                    let chunk_path = format!("{}/{}", dir_name, fid.clone().to_string());
-                    uploaded_files.push(chunk_path.clone());
-                    let mut file = File::create(&chunk_path).unwrap();
-                    file.write_all(&buffer).unwrap();
+                "file" => {
+                    let mut buffer = Vec::with_capacity(CHUNK_SIZE);
+                    // 与断点续传一致，去掉文件名中的路径分隔符等非法字符
+                    file_name = sanitize(field.content_disposition().and_then(|cd| cd.get_filename()).unwrap_or(""));
+                    if file_name.is_empty() {
+                        return Ok(Some("Invalid file_name value"));
+                    }
+                    if path.len() > 128 {
+                        return Ok(Some("path name to lang (max=128)"));
+                    }
+                    // 写入分片前校验目录权限
+                    if !authorized {
+                        grant.authorize(auth_service, &normalize_path(&path)).await?;
+                        authorized = true;
+                    }
+                    while let Some(bytes) = field.next().await {
+                        let bytes = bytes?;
+                        buffer.extend_from_slice(&bytes); // ✅ 累积数据
+                        hasher.update(&bytes);
+                        size += bytes.len();
+                        if size > MAX_FILE_SIZE {
+                            return Err(AppError::InvalidInput("file.too.large".to_owned()));
+                        }
+                        if buffer.len() >= CHUNK_SIZE {
+                            //按内容写入分片，相同分片只保存一份
+                            uploaded_files.push(chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_info.id, size, &buffer[..CHUNK_SIZE]).await?);
+                            buffer.drain(..CHUNK_SIZE); // ✅ 清除已写入的部分
+                        }
+                    }
+                    // 处理剩余数据（小于 4MB）
+                    if !buffer.is_empty() {
+                        uploaded_files.push(chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_info.id, size, &buffer).await?);
+                    }
                 }
+                _ => {} // 忽略未知字段
             }
-            _ => {} // 忽略未知字段
+        }
+        if path.len() > 128 {
+            return Ok(Some("path name to lang (max=128)"));
+        }
+        if file_name.len() > 64 {
+            return Ok(Some("file name to lang (max=64)"));
+        }
+        Ok(None)
+    }
+    .await;
+    // 没有 file 字段时同样需要校验目录权限
+    let read_result = match read_result {
+        Ok(None) if !authorized => grant.authorize(auth_service, &normalize_path(&path)).await.map(|_| None),
+        other => other,
+    };
+    match read_result {
+        Ok(None) => {}
+        Ok(Some(msg)) => {
+            chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+            return Ok(web::Json(BaseResponse::err_result_msg(msg)));
+        }
+        Err(e) => {
+            chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+            return Err(e);
         }
     }
-    let fid = build_id();
-    //s
-    let file_type = FileType::get_file_type(&file_name);
-    let mut thumbnail_status_tem: &bool;
-    if file_type != FileType::IMAGE {
-        thumbnail_status_tem = &true;
-    } else {
-        thumbnail_status_tem = &is_thumbnail;
+    let digest = hasher.finish();
+    let body_sha256 = hex::encode(body_hasher.borrow_mut().finalize_reset());
+    if let Err(e) = grant.body_hash.verify(&body_sha256).and_then(|_| expected.verify(&digest)) {
+        chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+        return Err(e);
     }
+    let fid = build_snow_id();
+    // 目录、配额与文件记录在同一事务中写入，失败时不会留下空目录或占用的配额
+    let saved = {
+        let (bucket_id, path, file_name, items, digest) = (bucket_info.id, &path, &file_name, &uploaded_files, &digest);
+        with_transaction(&file_rep.dao.pool, |mut tx| async move {
+            let (path_id, full_path) = check_and_save_path(&mut tx, &bucket_id, path, &app_state.db_path_cache, path_info_rep).await?;
+            bucket_rep.reserve_quota_with(&mut *tx, bucket_id, size as i64).await?;
+            insert_file_name(&mut tx, &bucket_id, file_rep, fid, path_id, file_name, &full_path, items.clone(), &size, digest, is_thumbnail).await?;
+            Ok(((path_id, full_path), tx))
+        }).await
+    };
+    let (path_id, full_path) = match saved {
+        Ok(result) => result,
+        Err(e) => {
+            chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+            return Err(e);
+        }
+    };
+    cache_path(&app_state.db_path_cache, &bucket_info.id, &full_path, path_id).await;
+    match full_path.is_empty() {
+        true => audit.target(fid, &file_name),
+        false => audit.target(fid, &format!("{}/{}", full_path, file_name)),
+    };
+    Ok(web::Json(BaseResponse::ok_result_data(fid.to_string())))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MultipartInitDto {
+    path: Option<String>,
+    file_name: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MultipartPartsResult {
+    upload_id: i64,
+    name: String,
+    status: i32,
+    parts: Vec<UploadPart>,
+}
+
+/// **初始化断点续传上传**，返回 uploadId
+#[post("/upload/{bucket}/multipart/init")]
+pub async fn multipart_init(
+    bucket: web::Path<String>,
+    body: web::Bytes,
+    app_state: Data<AppState>,
+    path_info_rep: Data<PathRepository>,
+    verifier: Data<RequestVerifier>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
+    upload_session_rep: Data<UploadSessionRepository>,
+    file_rep: Data<FileRepository>,
+    req: HttpRequest,
+) -> std::result::Result<impl Responder, AppError> {
+    let grant = check_write_right(&bucket, &req, &verifier, &bucket_rep, &api_key_rep, &auth_service).await?;
+    let bucket_info = grant.bucket.clone();
+    grant.body_hash.verify_body(&body)?;
+    let dto: MultipartInitDto = serde_json::from_slice(&body).map_err(|e| AppError::InvalidInput(e.to_string()))?;
+    let path = dto.path.clone().unwrap_or_default();
     if path.len() > 128 {
-        return Ok(web::Json(BaseResponse::err_result_msg(
-            "path name to lang (max=128)",
-        )));
+        return Err(AppError::InvalidInput("path.too.long".to_owned()));
+    }
+    grant.authorize(&auth_service, &normalize_path(&path)).await?;
+    let file_name = sanitize(&dto.file_name);
+    if file_name.is_empty() || file_name.len() > 64 {
+        return Err(AppError::InvalidInput("file_name.invalid".to_owned()));
+    }
+    let (path_id, full_path, upload_id) = {
+        let (bucket_id, path, file_name, db_path_cache) = (bucket_info.id, &path, &file_name, &app_state.db_path_cache);
+        let (path_info_rep, upload_session_rep, file_rep) = (&path_info_rep, &upload_session_rep, &file_rep);
+        with_transaction(&upload_session_rep.dao.pool, |mut tx| async move {
+            let (path_id, full_path) = check_and_save_path(&mut tx, &bucket_id, path, db_path_cache, path_info_rep).await?;
+            // 同名文件已存在时提前拒绝，避免分片全部上传后才在完成时失败
+            if file_rep.find_by_key_with(&mut *tx, bucket_id, &file_full_path(path_id, &full_path), file_name).await?.is_some() {
+                return Err(AppError::BizError("file.exists".to_owned()));
+            }
+            let upload_id = upload_session_rep.create_with(&mut tx, &bucket_id, &path_id, &full_path, file_name).await?;
+            Ok(((path_id, full_path, upload_id), tx))
+        }).await?
+    };
+    cache_path(&app_state.db_path_cache, &bucket_info.id, &full_path, path_id).await;
+    Ok(web::Json(result_data(serde_json::json!({ "uploadId": upload_id.to_string() }))))
+}
+
+/// **上传单个分片**，请求体即分片内容，同一分片号可重复上传
+#[put("/upload/{bucket}/multipart/{upload_id}/{part_number}")]
+pub async fn multipart_part(
+    params: web::Path<(String, i64, i32)>,
+    app_state: Data<AppState>,
+    verifier: Data<RequestVerifier>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
+    upload_session_rep: Data<UploadSessionRepository>,
+    upload_part_rep: Data<UploadPartRepository>,
+    chunk_rep: Data<ChunkRepository>,
+    req: HttpRequest,
+    mut payload: web::Payload,
+) -> std::result::Result<impl Responder, AppError> {
+    let (bucket, upload_id, part_number) = params.into_inner();
+    if !(1..=MAX_PART_NUMBER).contains(&part_number) {
+        return Err(AppError::InvalidInput("part_number.invalid".to_owned()));
+    }
+    let grant = check_write_right(&bucket, &req, &verifier, &bucket_rep, &api_key_rep, &auth_service).await?;
+    let bucket_info = grant.bucket.clone();
+    let session = find_uploading_session(&upload_session_rep, upload_id, bucket_info.id).await?;
+    grant.authorize(&auth_service, &session.full_path).await?;
+    let expected = ExpectedDigest::from_headers(req.headers())?;
+
+    // 分片按 4MB 切分后写入分片库，完成上传时直接引用，无需再次拷贝
+    let mut items: Vec<FileItemDto> = Vec::new();
+    let mut size: usize = 0;
+    let mut hasher = FileHasher::new();
+    let write_result: Result<(), AppError> = async {
+        let mut buffer: Vec<u8> = Vec::with_capacity(CHUNK_SIZE);
+        while let Some(bytes) = payload.next().await {
+            let bytes = bytes.map_err(|e| AppError::InvalidInput(e.to_string()))?;
+            size += bytes.len();
+            if size > MAX_PART_SIZE {
+                return Err(AppError::InvalidInput("part.too.large".to_owned()));
+            }
+            buffer.extend_from_slice(&bytes);
+            hasher.update(&bytes);
+            if buffer.len() >= CHUNK_SIZE {
+                items.push(chunk_rep.save_bucket_chunk(&*app_state.storage, &bucket_rep, bucket_info.id, &buffer[..CHUNK_SIZE]).await?);
+                buffer.drain(..CHUNK_SIZE);
+            }
+        }
+        if size == 0 {
+            return Err(AppError::InvalidInput("part.is.empty".to_owned()));
+        }
+        if !buffer.is_empty() {
+            items.push(chunk_rep.save_bucket_chunk(&*app_state.storage, &bucket_rep, bucket_info.id, &buffer).await?);
+        }
+        Ok(())
     }
-    let mut current_path = path;
-    let path_id: String;
-    if current_path.is_empty() || current_path == "/" || current_path == "" {
-        current_path = String::from("");
-        path_id = current_path.clone();
-    } else {
-        path_id = check_and_save_path(
-            &current_path.clone(),
-            &app_state.db_path_cache,
-            &path_info_rep,
-        )
-        .await?;
+    .await;
+    let digest = hasher.finish();
+    let write_result = write_result.and_then(|_| grant.body_hash.verify(&digest.sha256)).and_then(|_| expected.verify(&digest));
+    if let Err(e) = write_result {
+        chunk_rep.release_bucket_items(&*app_state.storage, &bucket_rep, bucket_info.id, &items).await?;
+        return Err(e);
     }
-    if file_name.len() > 64 {
-        return Ok(web::Json(BaseResponse::err_result_msg(
-            "file name to lang (max=64)",
-        )));
+
+    // 分片占用的配额在完成上传后转给文件，取消上传时归还
+    // 锁定会话后再保存，与完成、取消上传串行执行；会话已关闭时释放本次写入的分片
+    let saved = {
+        let (upload_session_rep, upload_part_rep, items) = (&upload_session_rep, &upload_part_rep, &items);
+        with_transaction(&upload_part_rep.dao.pool, |mut tx| async move {
+            match upload_session_rep.lock_with(&mut *tx, upload_id).await? {
+                Some(locked) if locked.status == UploadSession::UPLOADING => {}
+                _ => return Err(AppError::BizError("upload.is.closed".to_owned())),
+            }
+            let old_part = upload_part_rep.save_part_with(&mut *tx, upload_id, part_number, items, size as u32).await?;
+            Ok((old_part, tx))
+        }).await
+    };
+    let old_part = match saved {
+        Ok(old_part) => old_part,
+        Err(e) => {
+            chunk_rep.release_bucket_items(&*app_state.storage, &bucket_rep, bucket_info.id, &items).await?;
+            return Err(e);
+        }
+    };
+    if let Some(old_part) = old_part {
+        chunk_rep.release_bucket_items(&*app_state.storage, &bucket_rep, bucket_info.id, &old_part.items).await?;
     }
-    insert_file_name(
-        &file_rep,
-        fid.clone().to_string(),
-        &path_id,
-        &file_name,
-        &file_type,
-        uploaded_files,
-        &size,
-        thumbnail_status_tem,
-    )
-    .await?;
-    Ok(web::Json(BaseResponse::ok_result_data(fid.to_string())))
+    Ok(web::Json(result_data(serde_json::json!({ "partNumber": part_number, "size": size, "sha256": digest.sha256, "md5": digest.md5 }))))
 }
 
-async fn insert_file_error(conn: &MySqlPool, error_files: Vec<String>) -> Result<(), AppError> {
-    let mut items_str = String::new();
-    for item in error_files {
-        items_str.push_str(&item);
-        items_str.push_str(",");
+/// **查询已上传的分片**，客户端据此续传
+#[post("/upload/{bucket}/multipart/{upload_id}/parts")]
+pub async fn multipart_parts(
+    params: web::Path<(String, i64)>,
+    verifier: Data<RequestVerifier>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
+    upload_session_rep: Data<UploadSessionRepository>,
+    upload_part_rep: Data<UploadPartRepository>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> std::result::Result<impl Responder, AppError> {
+    let (bucket, upload_id) = params.into_inner();
+    let grant = check_write_right(&bucket, &req, &verifier, &bucket_rep, &api_key_rep, &auth_service).await?;
+    let bucket_info = grant.bucket.clone();
+    grant.body_hash.verify_body(&body)?;
+    let session = upload_session_rep.dao.find_by_id(upload_id).await?;
+    if session.bucket_id != bucket_info.id {
+        return Err(AppError::NotFound("upload.not.found".to_owned()));
     }
+    grant.authorize(&auth_service, &session.full_path).await?;
+    let parts = upload_part_rep.list_parts(upload_id).await?;
+    Ok(web::Json(result_data(MultipartPartsResult {
+        upload_id,
+        name: session.name,
+        status: session.status,
+        parts,
+    })))
+}
 
-    sqlx::query(
-        "insert into file_info (file, error_count, del_status, create_time)VALUES(?,0,:0,?)",
-    )
-    .bind(&items_str)
-    .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
-    .execute(conn)
-    .await
-    .unwrap();
+/// **完成上传**，按分片号顺序合并为文件，读取全部分片校验并计算文件摘要
+#[post("/upload/{bucket}/multipart/{upload_id}/complete")]
+pub async fn multipart_complete(
+    params: web::Path<(String, i64)>,
+    app_state: Data<AppState>,
+    verifier: Data<RequestVerifier>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
+    upload_session_rep: Data<UploadSessionRepository>,
+    upload_part_rep: Data<UploadPartRepository>,
+    file_rep: Data<FileRepository>,
+    audit_rep: Data<AuditLogRepository>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> std::result::Result<impl Responder, AppError> {
+    let (bucket, upload_id) = params.into_inner();
+    let mut audit = AuditEntry::new(&req, AuditAction::Upload);
+    audit.target(upload_id, "");
+    let outcome: Result<i64, AppError> = async {
+        let grant = check_write_right(&bucket, &req, &verifier, &bucket_rep, &api_key_rep, &auth_service).await?;
+        let bucket_info = grant.bucket.clone();
+        grant.audit(&mut audit);
+        grant.body_hash.verify_body(&body)?;
+        let session = find_uploading_session(&upload_session_rep, upload_id, bucket_info.id).await?;
+        let target = match session.full_path.is_empty() {
+            true => session.name.clone(),
+            false => format!("{}/{}", session.full_path, session.name),
+        };
+        audit.target(upload_id, &target);
+        grant.authorize(&auth_service, &session.full_path).await?;
+        let expected = ExpectedDigest::from_headers(req.headers())?;
+        let parts = upload_part_rep.list_parts(upload_id).await?;
+        if parts.is_empty() {
+            return Err(AppError::InvalidInput("parts.is.empty".to_owned()));
+        }
+        let mut size: usize = 0;
+        let mut items: Vec<FileItemDto> = Vec::new();
+        for (index, part) in parts.iter().enumerate() {
+            // 分片号必须从 1 开始连续
+            if part.part_number != index as i32 + 1 {
+                return Err(AppError::InvalidInput(format!("part.missing:{}", index + 1)));
+            }
+            size += part.size as usize;
+            items.extend(part.items.iter().cloned());
+        }
+        // 读取分片计算摘要前先检查大小，超出时不会写入 file_info
+        if size > MAX_FILE_SIZE {
+            return Err(AppError::InvalidInput("file.too.large".to_owned()));
+        }
+        let mut hasher = FileHasher::new();
+        for item in items.iter() {
+            let chunk = item.chunk_ref();
+            hasher.update(&read_chunk(&*app_state.storage, &chunk, 0, chunk.size).await?);
+        }
+        let digest = hasher.finish();
+        expected.verify(&digest)?;
+        let fid = build_snow_id();
+        audit.target(fid, &target);
+        let (bucket_id, session, digest) = (bucket_info.id, &session, &digest);
+        let (upload_session_rep, upload_part_rep, file_rep) = (&upload_session_rep, &upload_part_rep, &file_rep);
+        let parts = &parts;
+        with_transaction(&file_rep.dao.pool, |mut tx| async move {
+            // 锁定会话，读取分片期间有新分片写入或覆盖时拒绝完成，由客户端重新提交
+            match upload_session_rep.lock_with(&mut *tx, upload_id).await? {
+                Some(locked) if locked.status == UploadSession::UPLOADING => {}
+                _ => return Err(AppError::BizError("upload.is.closed".to_owned())),
+            }
+            let current = upload_part_rep.list_parts_with(&mut *tx, upload_id).await?;
+            let unchanged = current.len() == parts.len()
+                && current.iter().zip(parts.iter()).all(|(a, b)| a.id == b.id && a.size == b.size && a.items.0 == b.items.0);
+            if !unchanged {
+                return Err(AppError::BizError("upload.parts.changed".to_owned()));
+            }
+            // 先关闭会话，与取消上传、清理任务并发时只有一方成功；写入失败时回滚为上传中
+            if !upload_session_rep.close_with(&mut tx, upload_id, UploadSession::COMPLETED).await? {
+                return Err(AppError::BizError("upload.is.closed".to_owned()));
+            }
+            // 同名文件已存在时整个事务回滚，会话保持上传中，分片与其占用的配额由取消上传或清理任务归还
+            insert_file_name(&mut tx, &bucket_id, file_rep, fid, session.path_ref, &session.name, &session.full_path, items, &size, digest, true).await?;
+            upload_part_rep.del_by_upload_id_with(&mut tx, upload_id).await?;
+            Ok(((), tx))
+        }).await?;
+        Ok(fid)
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    Ok(web::Json(result_data(outcome?.to_string())))
+}
 
-    Ok(())
+/// **取消上传**，删除已上传的分片
+#[post("/upload/{bucket}/multipart/{upload_id}/abort")]
+pub async fn multipart_abort(
+    params: web::Path<(String, i64)>,
+    app_state: Data<AppState>,
+    verifier: Data<RequestVerifier>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    auth_service: Data<AuthService>,
+    upload_session_rep: Data<UploadSessionRepository>,
+    upload_part_rep: Data<UploadPartRepository>,
+    chunk_rep: Data<ChunkRepository>,
+    audit_rep: Data<AuditLogRepository>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> std::result::Result<impl Responder, AppError> {
+    let (bucket, upload_id) = params.into_inner();
+    let mut audit = AuditEntry::new(&req, AuditAction::UploadAbort);
+    audit.target(upload_id, "");
+    let outcome: Result<(), AppError> = async {
+        let grant = check_write_right(&bucket, &req, &verifier, &bucket_rep, &api_key_rep, &auth_service).await?;
+        let bucket_info = grant.bucket.clone();
+        grant.audit(&mut audit);
+        grant.body_hash.verify_body(&body)?;
+        let session = find_uploading_session(&upload_session_rep, upload_id, bucket_info.id).await?;
+        let target = match session.full_path.is_empty() {
+            true => session.name.clone(),
+            false => format!("{}/{}", session.full_path, session.name),
+        };
+        audit.target(upload_id, &target);
+        grant.authorize(&auth_service, &session.full_path).await?;
+        if !upload_session_rep.close(upload_id, UploadSession::ABORTED).await? {
+            return Err(AppError::BizError("upload.is.closed".to_owned()));
+        }
+        // 逐个删除分片记录后再释放，与清理任务并发时不会重复释放
+        for part in upload_part_rep.list_parts(upload_id).await? {
+            if upload_part_rep.del_part(part.id).await? {
+                chunk_rep.release_bucket_items(&*app_state.storage, &bucket_rep, bucket_info.id, &part.items).await?;
+            }
+        }
+        Ok(())
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+async fn find_uploading_session(
+    upload_session_rep: &UploadSessionRepository,
+    upload_id: i64,
+    bucket_id: i64,
+) -> Result<UploadSession, AppError> {
+    let session = match upload_session_rep.dao.find_by_id(upload_id).await {
+        Ok(session) => session,
+        Err(_) => return Err(AppError::NotFound("upload.not.found".to_owned())),
+    };
+    if session.bucket_id != bucket_id {
+        return Err(AppError::NotFound("upload.not.found".to_owned()));
+    }
+    if session.status != UploadSession::UPLOADING {
+        return Err(AppError::BizError("upload.is.closed".to_owned()));
+    }
+    Ok(session)
+}
+
+/// 写请求的认证结果，写权限按上传的目标目录校验
+struct WriteGrant {
+    bucket: Bucket,
+    //公开写的 bucket 未携带签名时为空
+    user: Option<Subject>,
+    //请求体的校验方式
+    body_hash: BodyHash,
+    //预签名上传指定的目录
+    path: Option<String>,
+}
+
+impl WriteGrant {
+    fn audit(&self, audit: &mut AuditEntry) {
+        audit.bucket(self.bucket.id);
+        if let Some(user) = self.user {
+            audit.actor(user.id);
+        }
+    }
+
+    async fn authorize(&self, auth_service: &AuthService, dir: &str) -> Result<(), AppError> {
+        auth_service.authorize_path(self.user, &self.bucket, dir, Action::Write).await
+    }
 }
 
 ///
-///
-/// 插入文件
-async fn insert_file_name(
-    file_rep: &FileRepository,
-    id: String,
-    path_ref: &String,
-    file_name: &String,
-    file_type: &FileType,
-    items: Vec<String>,
-    size: &usize,
-    thumbnail_status: &bool,
-) -> Result<(), AppError> {
-    let mut items_str = String::new();
-    for item in items {
-        items_str.push_str(&item);
-        items_str.push_str(",");
+/// 认证写请求，非公开写的 bucket 需要携带请求签名或预签名
+async fn check_write_right(
+    bucket: &str,
+    req: &HttpRequest,
+    verifier: &RequestVerifier,
+    bucket_rep: &BucketRepository,
+    api_key_rep: &ApiKeyRepository,
+    auth_service: &AuthService,
+) -> Result<WriteGrant, AppError> {
+    if bucket.is_empty() {
+        return Err(AppError::NoRight("bucket.error".to_owned()));
     }
-    let image_type = match &file_type {
-        FileType::IMAGE => ImageType::get_image_type(file_name),
-        _ => ImageType::EMPTY,
+    let bucket_info = match bucket_rep.find_by_name(&bucket.to_string()).await {
+        Ok(info) => info,
+        Err(e) => return Err(AppError::NoRight(e.to_string())),
     };
-    let mut current_thumbnail_status;
+    if let Some(signed) = SignedRequest::from_request(req)? {
+        let credential = find_credential(&signed.access_key, api_key_rep).await?;
+        let body_hash = verifier.verify(req, &signed, &credential.secret_key).await?;
+        api_key_rep.mark_used(&credential).await;
+        return Ok(WriteGrant {
+            bucket: bucket_info,
+            user: Some(credential.subject),
+            body_hash,
+            path: None,
+        });
+    }
+    if let Some(presigned) = PresignedRequest::from_request(req)? {
+        let credential = find_credential(&presigned.access_key, api_key_rep).await?;
+        presigned.verify(req, &credential.secret_key)?;
+        api_key_rep.mark_used(&credential).await;
+        return Ok(WriteGrant {
+            bucket: bucket_info,
+            user: Some(credential.subject),
+            body_hash: BodyHash::Unsigned,
+            path: Some(presigned.param(PRESIGN_PATH).unwrap_or_default().to_string()),
+        });
+    }
+    //匿名请求不受目录授权影响，不是公开写时直接拒绝
+    auth_service.authorize(None, &bucket_info, Action::Write).await?;
+    Ok(WriteGrant {
+        bucket: bucket_info,
+        user: None,
+        body_hash: BodyHash::Unsigned,
+        path: None,
+    })
+}
 
-    if image_type != ImageType::EMPTY && *thumbnail_status {
-        current_thumbnail_status = false;
-    } else {
-        current_thumbnail_status = match image_type {
-            ImageType::JPEG => true,
-            ImageType::BMP => true,
-            ImageType::JPG => true,
-            ImageType::PNG => true,
-            ImageType::TIFF => true,
-            ImageType::TIF => true,
-            ImageType::WebP => true,
-            _ => false,
-        };
-        //如果图片小于 100K,则不进行压缩
-        if size > &(CHUNK_SIZE / 10 / 4) {
-            current_thumbnail_status = false;
-        }
+/// 查询 access key 对应的用户与密钥范围，签名校验通过后再校验 bucket 权限
+async fn find_credential(access_key: &str, api_key_rep: &ApiKeyRepository) -> Result<Credential, AppError> {
+    match api_key_rep.find_credential(access_key).await {
+        Ok(credential) => Ok(credential),
+        Err(_) => Err(AppError::NoRight("no.right".to_string())),
     }
+}
 
-    let root: bool;
-    if path_ref.eq("") {
-        root = true;
-    } else {
-        root = false;
+/// 文件记录中的 full_path，根目录为空，其他目录以 / 结尾
+pub(crate) fn file_full_path(path_id: i64, dir_full_path: &str) -> String {
+    match path_id {
+        0 => String::new(),
+        _ => format!("{}/", dir_full_path),
     }
+}
 
-    let i = u32::try_from(*size).unwrap();
+///
+///
+/// 插入文件，同一目录下已有同名文件时返回 `file.exists`
+pub(crate) async fn insert_file_name(
+    conn: &mut MySqlConnection,
+    bucket_id: &i64,
+    file_rep: &FileRepository,
+    id: i64,
+    path_ref: i64,
+    name: &str,
+    full_path: &str,
+    items: Vec<FileItemDto>,
+    size: &usize,
+    digest: &FileDigest,
+    thumbnail: bool,
+) -> Result<(), AppError> {
+    let file_type = FileType::get_file_type(name);
+    let image_type = match &file_type {
+        FileType::IMAGE => ImageType::get_image_type(name),
+        _ => ImageType::NONE,
+    };
+    let root = path_ref == 0;
     let mut params: HashMap<&str, String> = HashMap::new();
-    params.insert("id", id.clone());
-    params.insert("path_ref", path_ref.clone());
-    params.insert("file_name", file_name.clone());
+    params.insert("id", id.to_string());
+    params.insert("bucket_id", bucket_id.to_string());
+    params.insert("path_ref", path_ref.to_string());
+    params.insert("name", name.to_string());
+    params.insert("full_path", file_full_path(path_ref, full_path));
     params.insert("file_type", file_type.as_ref().to_string());
     params.insert("image_type", image_type.as_ref().to_string());
-    params.insert("thumbnail_status", thumbnail_status.to_string());
-    params.insert("thumbnail", thumbnail_status.to_string());
-    params.insert("items", items_str);
     params.insert(
         "root",
         match root {
@@ -283,131 +685,85 @@ async fn insert_file_name(
         },
     );
     params.insert("size", size.to_string());
-    file_rep.dao.insert(params);
+    params.insert("sha256", digest.sha256.clone());
+    params.insert("md5", digest.md5.clone());
+    //图片由 app-job 的 thumbnail 任务生成缩略图
+    let thumbnail_status = match thumbnail {
+        true => FileInfo::THUMBNAIL_PENDING,
+        false => FileInfo::THUMBNAIL_SKIPPED,
+    };
+    params.insert("thumbnail_status", thumbnail_status.to_string());
+    let now = Local::now();
+    params.insert("create_time", now.format("%Y-%m-%d %H:%M:%S").to_string());
+    file_rep.insert_with(conn, params, &items).await?;
     Ok(())
 }
 
 ///
-/// 检查目录是否存在
-async fn check_and_save_path(
-    full_path: &String,
+/// 检查目录是否存在，不存在则在 `conn` 上逐级创建，返回 (目录 id, 目录 full_path)
+///
+/// 新建的目录提交后才能放入缓存，调用方提交后调用 `cache_path`
+pub(crate) async fn check_and_save_path(
+    conn: &mut MySqlConnection,
+    bucket_id: &i64,
+    full_path: &str,
     db_path_cache: &Arc<Cache<String, String>>,
     path_info_rep: &PathRepository,
-) -> Result<String, AppError> {
-    let safe_path = sanitize(&full_path);
-    //判断缓存里是否存在文件夹
-    let option = db_path_cache.get(&safe_path.to_string()).await;
-    let cache_dir_id = match option {
-        Some(option) => option,
-        None => "".to_string(),
-    };
-    if !cache_dir_id.is_empty() {
-        return Ok(cache_dir_id);
+) -> Result<(i64, String), AppError> {
+    let safe_path = normalize_path(full_path);
+    if safe_path.is_empty() {
+        return Ok((0, String::new()));
     }
-    let root: bool;
-    if safe_path.eq("") {
-        root = true;
-    } else {
-        root = false;
+    let path_list: Vec<&str> = safe_path.split("/").collect();
+    //判断缓存里是否存在文件夹；目录可能已被控制台删除，命中后按 id 重新确认
+    let cache_key = format!("{}:{}", bucket_id, safe_path);
+    if let Some(cache_dir_id) = db_path_cache.get(&cache_key).await
+        && let Ok(id) = cache_dir_id.parse::<i64>()
+    {
+        match path_info_rep.dao.find_by_id_with(&mut *conn, id).await {
+            Ok(path_info) if path_info.bucket_id == *bucket_id && path_info.full_path == safe_path => return Ok((id, safe_path)),
+            Ok(_) | Err(AppError::DBError(sqlx::Error::RowNotFound)) => db_path_cache.invalidate(&cache_key).await,
+            Err(e) => return Err(e),
+        }
     }
 
-    let path_list = &safe_path.split("/").collect::<Vec<&str>>();
-    let mut current_dir: String = String::from("");
-    let mut parent_id: String = String::from("");
-
-    let mut finally_id: String = String::new();
+    let mut current_dir: String = String::new();
+    let mut parent_id: i64 = 0;
     for path_item in path_list.iter() {
         if current_dir.is_empty() {
-            current_dir = format!("{}", path_item);
+            current_dir = path_item.to_string();
         } else {
-            current_dir = format!("{}/{}", current_dir.clone(), &path_item);
+            current_dir = format!("{}/{}", current_dir, path_item);
         }
-        let option = db_path_cache.get(current_dir.clone().as_str()).await;
-        let cache_dir_id = match option {
-            Some(option) => option,
-            None => "".to_string(),
+        // 按上级目录 id 查找，已删除目录下待清理的子目录不会被复用
+        let list_path = path_info_rep
+            .dao
+            .query_by_params_with(&mut *conn, vec![
+                QueryParam::eq("full_path", current_dir.as_str()),
+                QueryParam::eq("bucket_id", bucket_id.to_string().as_str()),
+                QueryParam::eq("parent", parent_id.to_string().as_str()),
+            ])
+            .await?;
+        parent_id = match list_path.first() {
+            Some(path_info) => path_info.id,
+            None => path_info_rep.new_path_with(&mut *conn, &path_item.to_string(), &parent_id, bucket_id).await?,
         };
-        if cache_dir_id.is_empty() {
-            let mut path_info = PathInfo::default();
-            path_info.full_path = current_dir.clone();
-            let mut params: HashMap<&str, String> = HashMap::new();
-            params.insert("full_path", current_dir.to_string());
-            let result = path_info_rep.dao.find_by_one(params).await;
-            match result {
-                Ok(info) => {
-                    path_info = info;
-                    finally_id = path_info.id;
-                }
-                Err(e) => {
-                    // write!("not.found:{:}", "{}", *path_item);
-                    params = HashMap::new();
-                    let current_id = build_id();
-                    params.insert("id", current_id.clone());
-                    params.insert(
-                        "root",
-                        match root {
-                            true => "1".to_string(),
-                            false => "0".to_string(),
-                        },
-                    );
-                    params.insert("path", path_item.to_string());
-                    params.insert("parent", parent_id.to_string());
-                    params.insert("full_path", full_path.to_string());
-                    path_info_rep.dao.insert(params).await?;
-                }
-            }
-        } else {
-            return Ok(finally_id);
-        }
     }
-    return Ok(finally_id);
+    Ok((parent_id, safe_path))
 }
 
-/// **防止路径遍历攻击**
-fn sanitize_filename(filename: &str) -> String {
-    filename.replace("/", "_").replace("\\", "_")
-}
-//
-// ///生成文件目录
-async fn build_dir_name(
-    root: &String,
-    cache: &Arc<Cache<String, String>>,
-) -> std::result::Result<String, AppError> {
-    let now = chrono::Local::now();
-    //获取年
-    let year = &now.year();
-    let year_path_str = format!("{}/{}", root, year);
-    if let None = cache.get(&year_path_str.to_string()).await {
-        let year_path = Path::new(&year_path_str);
-        if !year_path.exists() {
-            std::fs::create_dir_all(year_path);
-        }
-        cache.insert(year_path_str.clone(), "1".to_string()).await;
-    }
-    //获取一年中的第几天
-    let day = &now.date_naive();
-    let day_index = &day.ordinal(); // 获取一年中的第几天（1-366）
-    let day_path_str = format!("{}/{}/{}", &root, year, day_index);
-    if let None = cache.get(&day_path_str.to_string()).await {
-        let day_path = Path::new(&day_path_str);
-        if !day_path.exists() {
-            std::fs::create_dir_all(day_path);
-        }
-        cache.insert(day_path_str.clone(), "1".to_string()).await;
+/// 缓存目录 id，根目录不缓存
+pub(crate) async fn cache_path(db_path_cache: &Arc<Cache<String, String>>, bucket_id: &i64, full_path: &str, path_id: i64) {
+    if path_id != 0 {
+        db_path_cache.insert(format!("{}:{}", bucket_id, full_path), path_id.to_string()).await;
     }
+}
 
-    //获取一天中的第几分钟
-    let minutes_of_day = &now.hour() * 60 + &now.minute();
-
-    let minutes_path_str = format!("{}/{}/{}/{}", &root, &year, &day_index, minutes_of_day);
-    if let None = cache.get(&minutes_path_str.to_string()).await {
-        let minutes_path = Path::new(&minutes_path_str);
-        if !minutes_path.exists() {
-            std::fs::create_dir_all(minutes_path);
-        }
-        cache
-            .insert(minutes_path_str.clone(), "1".to_string())
-            .await;
-    }
-    Ok(minutes_path_str)
+/// 逐级清理目录名，与保存时的 full_path 一致
+pub(crate) fn normalize_path(path: &str) -> String {
+    path.split("/")
+        .map(sanitize)
+        .filter(|item| !item.is_empty())
+        .collect::<Vec<String>>()
+        .join("/")
 }
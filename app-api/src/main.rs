@@ -2,7 +2,8 @@ mod handlers;
 
 use actix_web::{web, App, HttpServer};
 // use app_api::ApiDoc;
-use common::AppState;
+use clap::Parser;
+use common::{build_session_store, AppState, Cli, Command, LocalStorage, RequestVerifier};
 use log::info;
 use model::db;
 use moka::future::Cache;
@@ -16,9 +17,22 @@ async fn main() -> std::io::Result<()> {
     //定议swagger
     // let api_doc = ApiDoc::openapi();
     // 初始化数据库连接池
+    let cli = Cli::parse();
     let config = AppState::from_env();
     let mut log_builder = AppState::build_log(&config);
     log_builder.init();
+    let pool = Arc::new(db::get_conn(&config.database.url).await);
+    if let Some(Command::Migrate { action }) = cli.command {
+        db::run_migrate(&pool, action, &config.migrate, &config.password)
+            .await
+            .expect("Failed to migrate database");
+        return Ok(());
+    }
+    if config.migrate.auto {
+        db::migrate_up(&pool, &config.migrate, &config.password)
+            .await
+            .expect("Failed to migrate database");
+    }
     let dir_create_cache: Arc<Cache<String, String>> = Arc::new(
         Cache::builder()
             .time_to_live(Duration::from_secs(60 * 60 * 24)) // 设置 TTL 60 秒
@@ -30,27 +44,32 @@ async fn main() -> std::io::Result<()> {
         Cache::builder()
             .time_to_live(Duration::from_secs(60 * 60 * 24)) // 设置 TTL 60 秒
             .max_capacity(1000) // 最大存储 1000 个键值
+            //删除目录时按前缀失效子目录
+            .support_invalidation_closures()
             .build(),
     );
-    //用户 session
-    let session_cache: Arc<Cache<String, String>> = Arc::new(
-        Cache::builder()
-            .time_to_live(Duration::from_secs(60 * 60 * 24)) // 设置 TTL 60 秒
-            .max_capacity(1000) // 最大存储 1000 个键值
-            .build(),
-    );
+    //数据接口不使用登录会话，只用来记录签名的 nonce，redis 时多个实例共享
+    let session_store = build_session_store(&config.session)
+        .await
+        .expect("Failed to create session store");
     let app_status = AppState {
-        session_cache:session_cache,
+        session_store: session_store.clone(),
         root_path: config.server.root_path.clone(),
-        dir_create_cache,
+        dir_create_cache: dir_create_cache.clone(),
         db_path_cache: db_cache,
+        storage: Arc::new(LocalStorage::new(&config.server.root_path, dir_create_cache)),
     };
     let address_and_port = format!("{}:{}", &config.server.host, &config.server.port);
     info!("Starting server on {}", address_and_port);
     let data = web::Data::new(app_status.clone());
-    let pool = Arc::new(db::get_conn(&config.database.url).await);
+    //请求签名校验，nonce 缓存在各 worker 间共享
+    let verifier = web::Data::new(RequestVerifier::new(Duration::from_secs(config.sign.max_skew), session_store.clone()));
+    //审计日志按其中的 trusted_proxies 取客户端地址
+    let login_config = web::Data::new(config.login.clone());
     HttpServer::new(move || {
         App::new()
+            .app_data(verifier.clone())
+            .app_data(login_config.clone())
             //配置 orm
             .configure(|cfg| {
                 model::db::configure(cfg,pool.clone())
@@ -0,0 +1,115 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use chrono::{Local, NaiveDateTime};
+use common::*;
+use model::*;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(api_key_list);
+    cfg.service(api_key_create);
+    cfg.service(api_key_revoke);
+}
+
+/// 当前用户的全部密钥，不返回 secret
+#[post("/api_key/list")]
+async fn api_key_list(
+    req: HttpRequest,
+    state: Data<AppState>,
+    api_key_rep: Data<ApiKeyRepository>,
+) -> Result<impl Responder> {
+    let user = get_session_user(&state, req).await?;
+    Ok(web::Json(result_list(api_key_rep.list_by_user(user.id).await?)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyNewDto {
+    #[validate(length(min = 1, max = 64))]
+    label: String,
+    //限定的 bucket，为空时不限制
+    bucket_id: Option<i64>,
+    #[serde(default)]
+    read_only: bool,
+    //yyyy-MM-dd HH:mm:ss，为空时不过期
+    expire_time: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiKeyCreated {
+    #[serde(flatten)]
+    key: ApiKey,
+    //只在创建时返回一次
+    secret_key: String,
+}
+
+#[post("/api_key/create")]
+async fn api_key_create(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<ApiKeyNewDto>,
+    auth_service: Data<AuthService>,
+    bucket_rep: Data<BucketRepository>,
+    api_key_rep: Data<ApiKeyRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    if let Err(e) = dto.validate() {
+        let msg = format!("Validation failed: {:?}", e);
+        return Ok(web::Json(result_error_msg(msg.as_str())));
+    }
+    let bucket_id = dto.bucket_id.unwrap_or_default();
+    let mut audit = AuditEntry::new(&req, AuditAction::ApiKeyCreate);
+    audit.bucket(bucket_id).target(0, &dto.label);
+    let outcome: Result<ApiKey> = async {
+        let user = get_session_user(&state, req).await?;
+        if let Some(expire_time) = &dto.expire_time {
+            let expire_time = NaiveDateTime::parse_from_str(expire_time, "%Y-%m-%d %H:%M:%S")
+                .map_err(|_| AppError::InvalidInput("expire_time.invalid".to_owned()))?;
+            if expire_time <= Local::now().naive_local() {
+                return Err(AppError::InvalidInput("expire_time.invalid".to_owned()));
+            }
+        }
+        //只能限定到自己有权限的 bucket
+        if bucket_id != 0 {
+            let bucket = match bucket_rep.dao.find_by_id(bucket_id).await {
+                Ok(bucket) => bucket,
+                Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NoRight("no.right".to_owned())),
+                Err(e) => return Err(e),
+            };
+            if auth_service.permissions(Some((&user).into()), &bucket).await? == PermissionSet::NONE {
+                return Err(AppError::NoRight("no.right".to_owned()));
+            }
+        }
+        let key = api_key_rep.create(user.id, &dto.label, bucket_id, dto.read_only, dto.expire_time.as_deref()).await?;
+        audit.target(key.id, &dto.label);
+        Ok(key)
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    let key = outcome?;
+    Ok(web::Json(result_data(ApiKeyCreated { secret_key: key.secret_key.clone(), key })))
+}
+
+#[post("/api_key/revoke/{id}")]
+async fn api_key_revoke(
+    req: HttpRequest,
+    state: Data<AppState>,
+    id: web::Path<i64>,
+    api_key_rep: Data<ApiKeyRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::ApiKeyRevoke);
+    audit.target(*id, "");
+    let outcome: Result<()> = async {
+        let user = get_session_user(&state, req).await?;
+        match api_key_rep.revoke(*id, user.id).await? {
+            true => Ok(()),
+            false => Err(AppError::NotFound("api_key.not.found".to_owned())),
+        }
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
@@ -0,0 +1,34 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use common::*;
+use model::*;
+use serde::Deserialize;
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(audit_list);
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditListDto {
+    page: PageInfo,
+    #[serde(default)]
+    query: AuditQuery,
+}
+
+/// 审计日志按时间倒序，只允许管理员查询
+#[post("/audit/list")]
+async fn audit_list(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<AuditListDto>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let user = get_session_user(&state, req).await?;
+    if !user.is_admin {
+        return Err(AppError::NoRight("no.right".to_owned()));
+    }
+    let page_result = audit_rep.query(&dto.query, &dto.page).await?;
+    Ok(web::Json(result_page(page_result)))
+}
@@ -1,19 +1,22 @@
+use super::totp;
 use actix_web::web::Data;
-use actix_web::{cookie::time::Duration, post, web, HttpRequest, Responder};
+use actix_web::{post, web, HttpRequest, Responder};
 use common::{
-    build_id, result_data, AppError, AppState, BaseResponse, BucketCache, UserCache,
+    bearer_token, build_id, client_ip, get_session_user, result, result_data, AppError, AppState, BaseResponse, BucketCache,
+    LoginGuard, TotpConfig, UserCache,
 };
 use model::UserRepository;
 use model::*;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
-const ONE_MINUTE: Duration = Duration::minutes(60);
-
 pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
     cfg.app_data(state.clone());
     cfg.service(login);
+    cfg.service(login_totp);
+    cfg.service(login_totp_setup);
     cfg.service(logout);
+    cfg.service(unlock);
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, Default)]
@@ -22,22 +25,164 @@ pub struct LoginInfo {
     pub user_name: String,
     pub password: String,
 }
-#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct LoginResult<'a> {
     pub user_name: &'a str,
+    //需要两步验证时为 challenge，用于 /auth/login/totp
     pub token: &'a str,
+    pub totp_required: bool,
+    //被要求启用两步验证但还未绑定，先调用 /auth/login/totp/setup
+    pub totp_setup_required: bool,
+    //首次绑定完成时返回一次
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginTotpDto {
+    pub challenge: String,
+    //验证码或恢复码
+    #[serde(default)]
+    pub code: String,
+}
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnlockDto {
+    pub user_name: String,
+    //同时解锁的客户端 IP
+    pub client_ip: Option<String>,
+}
+
+fn totp_needed(totp_config: &TotpConfig, user: &UserInfo) -> bool {
+    user.totp_enabled || (totp_config.require_admin && user.is_admin)
 }
+
+/// 连续失败被锁定期间不再校验密码，失败和锁定都写入审计日志
+///
+/// 校验前先计入一次失败，密码正确后撤销
+///
+/// 启用两步验证的用户密码正确后只返回 challenge，失败计数在第二步成功后才清零
 #[post("/auth/login")]
 pub async fn login(
+    req: HttpRequest,
     dto: web::Json<LoginInfo>,
     state: web::Data<AppState>,
+    login_guard: web::Data<LoginGuard>,
+    totp_config: web::Data<TotpConfig>,
+    user_rep: web::Data<UserRepository>,
+    user_bucket_rep: web::Data<UserBucketRepository>,
+    audit_rep: web::Data<AuditLogRepository>,
+) -> Result<impl Responder, AppError> {
+    let client_ip = client_ip(&req);
+    let mut audit = AuditEntry::new(&req, AuditAction::Login);
+    audit.target(0, &dto.user_name);
+    let outcome: Result<UserInfo, AppError> = async {
+        login_guard.attempt(&dto.user_name, &client_ip).await?;
+        let user = user_rep.login(&dto.user_name, &dto.password).await?;
+        match totp_needed(&totp_config, &user) {
+            true => login_guard.release_ip(&client_ip).await?,
+            false => login_guard.succeeded(&dto.user_name, &client_ip).await?,
+        }
+        Ok(user)
+    }.await;
+    match &outcome {
+        // 等待第二步的结果
+        Ok(user) if totp_needed(&totp_config, user) => {}
+        Ok(user) => audit_rep.record(audit.actor(user.id).target(user.id, &user.user_name).succeeded()).await,
+        Err(_) => audit_rep.record(audit.outcome(&outcome)).await,
+    }
+    let result = outcome?;
+    if totp_needed(&totp_config, &result) {
+        let challenge = build_id();
+        let ttl = std::time::Duration::from_secs(totp_config.challenge_ttl);
+        state.session_store.put_challenge(&challenge, result.id, ttl).await?;
+        return Ok(web::Json(result_data(LoginResult {
+            user_name: &result.user_name,
+            token: &challenge,
+            totp_required: result.totp_enabled,
+            totp_setup_required: !result.totp_enabled,
+            recovery_codes: vec![],
+        })));
+    }
+    let session_id = create_session(&state, &user_bucket_rep, &result).await?;
+    Ok(web::Json(result_data(LoginResult {
+        user_name: &result.user_name,
+        token: &session_id,
+        ..Default::default()
+    })))
+}
+
+/// 两步验证的第二步，接受验证码或恢复码；未绑定的管理员在这里确认绑定
+#[post("/auth/login/totp")]
+pub async fn login_totp(
+    req: HttpRequest,
+    dto: web::Json<LoginTotpDto>,
+    state: web::Data<AppState>,
+    login_guard: web::Data<LoginGuard>,
+    totp_config: web::Data<TotpConfig>,
     user_rep: web::Data<UserRepository>,
     user_bucket_rep: web::Data<UserBucketRepository>,
+    audit_rep: web::Data<AuditLogRepository>,
+) -> Result<impl Responder, AppError> {
+    let client_ip = client_ip(&req);
+    let mut audit = AuditEntry::new(&req, AuditAction::Login);
+    let outcome: Result<(UserInfo, Vec<String>), AppError> = async {
+        let user = challenge_user(&state, &user_rep, &dto.challenge).await?;
+        audit.target(user.id, &user.user_name);
+        login_guard.attempt(&user.user_name, &client_ip).await?;
+        let recovery_codes = match user.totp_enabled {
+            true => totp::verify_code(&user_rep, &totp_config, &user, &dto.code, true).await?.then(Vec::new),
+            false => totp::confirm_setup(&user_rep, &totp_config, &user, &dto.code).await?,
+        };
+        let recovery_codes = match recovery_codes {
+            Some(recovery_codes) => recovery_codes,
+            None => return Err(AppError::BizError("totp.code.error".to_owned())),
+        };
+        state.session_store.remove_challenge(&dto.challenge).await?;
+        login_guard.succeeded(&user.user_name, &client_ip).await?;
+        Ok((user, recovery_codes))
+    }.await;
+    if let Ok((user, _)) = &outcome {
+        audit.actor(user.id);
+    }
+    audit_rep.record(audit.outcome(&outcome)).await;
+    let (result, recovery_codes) = outcome?;
+    let session_id = create_session(&state, &user_bucket_rep, &result).await?;
+    Ok(web::Json(result_data(LoginResult {
+        user_name: &result.user_name,
+        token: &session_id,
+        recovery_codes,
+        ..Default::default()
+    })))
+}
+
+/// 被要求启用两步验证的管理员在第二步前生成密钥
+#[post("/auth/login/totp/setup")]
+pub async fn login_totp_setup(
+    dto: web::Json<LoginTotpDto>,
+    state: web::Data<AppState>,
+    totp_config: web::Data<TotpConfig>,
+    user_rep: web::Data<UserRepository>,
 ) -> Result<impl Responder, AppError> {
-    let result = user_rep.login(&dto.user_name, &dto.password).await?;
-    let session_id = &build_id();
-    let bucket_list = user_bucket_rep.query_by_user_id_and_bucket_Id(&result.id,&result.id).await?;
+    let user = challenge_user(&state, &user_rep, &dto.challenge).await?;
+    Ok(web::Json(result_data(totp::begin_setup(&user_rep, &totp_config, &user).await?)))
+}
+
+async fn challenge_user(state: &AppState, user_rep: &UserRepository, challenge: &str) -> Result<UserInfo, AppError> {
+    match state.session_store.get_challenge(challenge).await? {
+        Some(user_id) => user_rep.dao.find_by_id(user_id).await,
+        None => Err(AppError::NoRight("totp.challenge.expired".to_owned())),
+    }
+}
+
+async fn create_session(
+    state: &AppState,
+    user_bucket_rep: &UserBucketRepository,
+    result: &UserInfo,
+) -> Result<String, AppError> {
+    let session_id = build_id();
+    let bucket_list = user_bucket_rep.query_by_user_id_and_bucket_id(&result.id,&result.id).await?;
     let mut bucket_cache_list: Vec<BucketCache> = Vec::new();
     for bucket in bucket_list {
         bucket_cache_list.push(BucketCache {
@@ -49,26 +194,41 @@ pub async fn login(
     }
     let user_cache = UserCache {
         id: result.id,
-        is_admin: result.is_admin.clone(),
+        is_admin: result.is_admin,
         user_name: result.user_name.clone(),
         bucket_list:vec![],
+        session_version: state.session_store.session_version(result.id).await?,
     };
-    state.session_cache.insert(session_id.clone(),user_cache).await;
-    Ok(web::Json(result_data(LoginResult {
-        user_name: &result.user_name,
-        token: &session_id,
-    })))
+    state.session_store.put(&session_id, &user_cache).await?;
+    Ok(session_id)
 }
 #[post("/auth/logout")]
 async fn logout(state: web::Data<AppState>, req: HttpRequest) -> Result<impl Responder, AppError> {
-    let auth_header = req.headers().get("Authorization");
-    if let Some(auth_value) = auth_header {
-        if let Ok(auth_str) = auth_value.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token_key = &auth_str[9..];
-                state.session_cache.remove(token_key);
-            }
-        }
+    if let Some(token) = bearer_token(&req) {
+        state.session_store.remove(&token).await?;
     }
     Ok(web::Json(BaseResponse::ok_no_result()))
 }
+
+/// 管理员解除登录锁定
+#[post("/auth/unlock")]
+async fn unlock(
+    req: HttpRequest,
+    dto: web::Json<UnlockDto>,
+    state: web::Data<AppState>,
+    login_guard: web::Data<LoginGuard>,
+    audit_rep: web::Data<AuditLogRepository>,
+) -> Result<impl Responder, AppError> {
+    let mut audit = AuditEntry::new(&req, AuditAction::LoginUnlock);
+    audit.target(0, &dto.user_name);
+    let outcome: Result<(), AppError> = async {
+        let user = get_session_user(&state, req).await?;
+        if !user.is_admin {
+            return Err(AppError::NoRight("no.right".to_owned()));
+        }
+        login_guard.unlock(&dto.user_name, dto.client_ip.as_deref()).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
@@ -1,5 +1,5 @@
 use actix_web::web::Data;
-use actix_web::{post, web, Responder};
+use actix_web::{post, web, HttpRequest, Responder};
 use chrono::Local;
 use common::*;
 use model::*;
@@ -15,13 +15,30 @@ pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
     cfg.service(save);
     cfg.service(user_list);
     cfg.service(user_right_bind);
+    cfg.service(quota_recalc);
 }
+/// 管理员查看全部 bucket，其他用户只能看到已授权的 bucket
 #[post("/bucket/list")]
 async fn list(
-    page: web::Json<PageInfo>,
+    req: HttpRequest,
+    state: Data<AppState>,
+    page: web::Json<PageQuery>,
     bucket_rep: Data<BucketRepository>,
+    user_bucket_rep: Data<UserBucketRepository>,
 ) -> std::result::Result<impl Responder, AppError> {
-    let page_result = bucket_rep.dao.query_by_page(vec![], &page).await?;
+    let user = get_session_user(&state, req).await?;
+    let mut filter = page.filter()?;
+    if !user.is_admin {
+        let rights = user_bucket_rep.dao.query_by_params(vec![QueryParam::eq("user_id", user.id.to_string().as_str())]).await?;
+        let mut bucket_ids: Vec<String> = rights.iter().map(|right| right.bucket_id.to_string()).collect();
+        // 空的 IN 条件会被忽略，没有授权时用不存在的 id
+        if bucket_ids.is_empty() {
+            bucket_ids.push("0".to_owned());
+        }
+        let bucket_ids: Vec<&str> = bucket_ids.iter().map(|id| id.as_str()).collect();
+        filter.push(QueryParam::is_in("id", &bucket_ids));
+    }
+    let page_result = bucket_rep.dao.query_by_page(filter, &page.page).await?;
     Ok(web::Json(result_page(page_result)))
 }
 // #[post("/bucket/user")]
@@ -36,10 +53,15 @@ async fn list(
 
 #[post("/bucket/user/{bucket_id}")]
 async fn user_list(
+    req: HttpRequest,
+    state: Data<AppState>,
     bucket_id: web::Path<i64>,
+    auth_service: Data<AuthService>,
     user_bucket_reg: web::Data<UserBucketRepository>,
 ) -> std::result::Result<impl Responder, AppError> {
-    Ok(web::Json(result_list(user_bucket_reg.query_by_bucket_id(&*bucket_id).await?)))
+    let user = get_session_user(&state, req).await?;
+    auth_service.authorize_id(Some((&user).into()), *bucket_id, Action::Admin).await?;
+    Ok(web::Json(result_list(user_bucket_reg.query_by_bucket_id(&bucket_id).await?)))
 }
 
 #[derive(Debug, Deserialize, Validate,Clone)]
@@ -47,27 +69,58 @@ async fn user_list(
 struct UserBucketNew {
     bucket_id: i64,
     user_id: i64,
-    right_type: i32,
+    //read / write / delete / share / admin
+    permissions: Vec<Action>,
 }
 
 #[post("/bucket/user/right/bind")]
 async fn user_right_bind(
+    req: HttpRequest,
+    state: Data<AppState>,
     data: web::Json<UserBucketNew>,
+    auth_service: Data<AuthService>,
     user_bucket_rep: Data<UserBucketRepository>,
+    audit_rep: Data<AuditLogRepository>,
 ) -> std::result::Result<impl Responder, AppError> {
-    &data.validate();
-    user_bucket_rep.change_right(data.user_id,data.bucket_id,  data.right_type.clone()).await?;
-    return Ok(web::Json(result()));
+    if let Err(e) = data.validate() {
+        let msg = format!("Validation failed: {:?}", e);
+        return Ok(web::Json(result_error_msg(msg.as_str())));
+    }
+    let permissions = PermissionSet::from_actions(&data.permissions);
+    let mut audit = AuditEntry::new(&req, AuditAction::RightBind);
+    let target = permissions.actions().iter().map(|action| action.as_ref()).collect::<Vec<_>>().join(",");
+    audit.bucket(data.bucket_id).target(data.user_id, &target);
+    let outcome: Result<()> = async {
+        let user = get_session_user(&state, req).await?;
+        auth_service.authorize_id(Some((&user).into()), data.bucket_id, Action::Admin).await?;
+        user_bucket_rep.change_permissions(data.user_id, data.bucket_id, permissions).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
 }
 
 
 #[post("/bucket/delete/{id}")]
 async fn bucket_delete(
+    req: HttpRequest,
+    state: Data<AppState>,
     id: web::Path<String>,
+    auth_service: Data<AuthService>,
     bucket_rep: Data<BucketRepository>,
+    audit_rep: Data<AuditLogRepository>,
 ) -> std::result::Result<impl Responder, AppError> {
-    let n_id=id.parse().unwrap();
-    bucket_rep.dao.del_by_id(n_id).await?;
+    let n_id = id.parse().map_err(|_| AppError::InvalidInput("id.invalid".to_owned()))?;
+    let mut audit = AuditEntry::new(&req, AuditAction::BucketDelete);
+    audit.bucket(n_id).target(n_id, "");
+    let outcome: Result<u64> = async {
+        let user = get_session_user(&state, req).await?;
+        let bucket = auth_service.authorize_id(Some((&user).into()), n_id, Action::Admin).await?;
+        audit.target(bucket.id, &bucket.name);
+        bucket_rep.dao.del_by_id(n_id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
 
@@ -77,22 +130,56 @@ struct BucketSaveDto {
     id: i64,
     #[validate(length(min = 1, max = 32))]
     name: String,
-    quota: i32,
+    //配额（字节），0 表示不限制
+    #[validate(range(min = 0))]
+    quota: i64,
     pub_read: bool,
     pub_write: bool,
 }
 #[post("/bucket/save")]
 async fn save(
+    req: HttpRequest,
+    state: Data<AppState>,
+    auth_service: Data<AuthService>,
     bucket_rep: Data<BucketRepository>,
+    audit_rep: Data<AuditLogRepository>,
     data: web::Json<BucketSaveDto>,
 ) -> std::result::Result<impl Responder, AppError> {
     if let Err(e) = &data.validate() {
         let msg = format!("Validation failed: {:?}", e);
         return Ok(web::Json(result_error_msg(msg.as_str())));
     }
+    let bucket_id = match data.id {
+        0 => build_snow_id(),
+        id => id,
+    };
+    let mut audit = AuditEntry::new(&req, AuditAction::BucketSave);
+    audit.bucket(bucket_id).target(bucket_id, &data.name);
+    let outcome = save_bucket(bucket_id, &state, req, &auth_service, &bucket_rep, &data).await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+async fn save_bucket(
+    bucket_id: i64,
+    state: &Data<AppState>,
+    req: HttpRequest,
+    auth_service: &AuthService,
+    bucket_rep: &BucketRepository,
+    data: &BucketSaveDto,
+) -> std::result::Result<(), AppError> {
+    //新建 bucket 只允许管理员，修改需要 bucket 的管理权限
+    let user = get_session_user(state, req).await?;
+    if data.id == 0 && !user.is_admin {
+        return Err(AppError::NoRight("no.right".to_owned()));
+    }
+    if data.id != 0 {
+        auth_service.authorize_id(Some((&user).into()), data.id, Action::Admin).await?;
+    }
     let mut params: HashMap<&str, String> = HashMap::new();
-    if (data.id==0) {
-        params.insert("id", build_snow_id().to_string());
+    if data.id==0 {
+        params.insert("id", bucket_id.to_string());
         params.insert("current_quota", "0".to_owned());
         let now = Local::now();
         params.insert("create_time", now.format("%Y-%m-%d %H:%M:%S").to_string());
@@ -114,11 +201,25 @@ async fn save(
         },
     );
 
-    if (data.id==0) {
+    if data.id==0 {
         bucket_rep.dao.insert(params).await?;
     } else {
         bucket_rep.dao.change(data.id, params).await?;
     }
+    Ok(())
+}
 
-    return Ok(web::Json(result()));
+/// 已用空间与 file_info 不一致时重新计算
+#[post("/bucket/quota/recalc/{id}")]
+async fn quota_recalc(
+    req: HttpRequest,
+    state: Data<AppState>,
+    id: web::Path<i64>,
+    auth_service: Data<AuthService>,
+    bucket_rep: Data<BucketRepository>,
+) -> std::result::Result<impl Responder, AppError> {
+    let user = get_session_user(&state, req).await?;
+    auth_service.authorize_id(Some((&user).into()), *id, Action::Admin).await?;
+    let current_quota = bucket_rep.recalc_quota(*id).await?;
+    Ok(web::Json(result_data(current_quota)))
 }
@@ -7,6 +7,5 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 
 #[post("/status")]
 pub async fn status() -> Result<impl Responder, AppError> {
-    let s = String::from("OK");
     Ok(web::Json(BaseResponse::ok_no_result()))
 }
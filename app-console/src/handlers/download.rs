@@ -1,23 +1,16 @@
 use actix_web::{get, web, HttpRequest, HttpResponse, Responder};
-use common::{do_zip, get_session_user, AppError, AppState};
+use common::{chunk_stream, find_session_user, get_session_user, parse_range, read_chunk, AppError, AppState, ByteRange, ChunkRef, MultiRangeBody, PresignedRequest, RangeRequest, ZipStreamWriter};
 use model::{
-    BucketRepository, FileInfo, FileRepository, PathInfo, PathRepository, Repository,
-    UserBucketRepository,
+    Action, ApiKeyRepository, AuditAction, AuditEntry, AuditLogRepository, AuthService, Bucket, FileInfo, FileRepository, PathInfo, PathRepository, Repository, Subject,
 };
-use std::collections::HashMap;
-use std::fs;
-use std::path::Path;
-use tempfile::tempdir;
 
 use actix_web::http::header;
 use async_stream::stream;
-use hex::encode;
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio_util::io::ReaderStream;
+use log::error;
 pub fn configure(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
     cfg.app_data(state.clone()).service(download);
     cfg.app_data(state.clone()).service(download_path);
+    cfg.app_data(state.clone()).service(thumbnail);
 }
 
 #[get("/downloadPath/{bucket_id}/{path_id}")]
@@ -25,137 +18,249 @@ async fn download_path(
     req: HttpRequest,
     state: web::Data<AppState>,
     params: web::Path<(i64, i64)>,
-    user_bucket_rep: web::Data<UserBucketRepository>,
+    auth_service: web::Data<AuthService>,
     path_rep: web::Data<PathRepository>,
     file_rep: web::Data<FileRepository>,
+    audit_rep: web::Data<AuditLogRepository>,
 ) -> Result<impl Responder, AppError> {
-    let user = get_session_user(&state, req).await?;
     let (bucket_id, path_id) = params.into_inner();
-    let mut has_right = false;
-    if (user.is_admin) {
-        has_right = true;
-    }
-    if (!has_right) {
-        let user_bucket_list = user_bucket_rep.query_by_user_id_and_bucket_Id(&user.id,&bucket_id).await?;
-        for user_bucket in user_bucket_list {
-            if user_bucket.bucket_id == bucket_id {
-                match &user_bucket.user_right {
-                    0 => {
-                        has_right = true;
-                        break;
+    let mut audit = AuditEntry::new(&req, AuditAction::DownloadPath);
+    audit.bucket(bucket_id).target(path_id, "");
+    let outcome: Result<(Subject, Bucket, PathInfo), AppError> = async {
+        let user = get_session_user(&state, req).await?;
+        audit.actor(user.id);
+        let subject = Subject::from(&user);
+        let (bucket, _) = auth_service.authorize_path_ref(Some(subject), bucket_id, path_id, Action::Read).await?;
+        let path_info: PathInfo = path_rep.dao.find_by_id(path_id).await?;
+        if path_info.bucket_id != bucket_id {
+            return Err(AppError::NotFound("path.not.found".to_owned()));
+        }
+        audit.target(path_id, &path_info.full_path);
+        Ok((subject, bucket, path_info))
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    let (subject, bucket, path_info) = outcome?;
+
+    // 边查询边输出，只在内存中保留一页文件信息和 ZIP 中央目录
+    let storage = state.storage.clone();
+    let file_rep = file_rep.into_inner();
+    let auth_service = auth_service.into_inner();
+    let full_path = path_info.full_path;
+    let body = stream! {
+        // 子目录可能单独设置了拒绝授权，逐个目录校验读权限
+        let mut permissions = auth_service.path_permission_cache(Some(subject), &bucket);
+        let mut writer = ZipStreamWriter::new();
+        let mut max_id: i64 = 0;
+        loop {
+            let file_list = match file_rep.path_file_list(&full_path, max_id, bucket_id).await {
+                Ok(list) => list,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let Some(last) = file_list.last() else {
+                break;
+            };
+            max_id = last.id;
+            for file in file_list {
+                match permissions.allows(&file.full_path, Action::Read).await {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
                     }
-                    2 => {
-                        has_right = true;
-                        break;
+                }
+                let size: u64 = file.items.iter().map(|item| item.size as u64).sum();
+                let name = format!("{}{}", file.full_path, file.name);
+                match writer.start_file(&name, size, &file.create_time) {
+                    Ok(header) => yield Ok(header),
+                    Err(e) => {
+                        yield Err(e);
+                        return;
                     }
-                    _ => {
-                        has_right = false;
+                }
+                for chunk in file.chunk_refs() {
+                    match read_chunk(&*storage, &chunk, 0, chunk.size).await {
+                        Ok(data) => {
+                            writer.write(&data);
+                            yield Ok(data);
+                        }
+                        Err(e) => {
+                            error!("read file {} error: {}", chunk.key, e);
+                            yield Err(e);
+                            return;
+                        }
                     }
                 }
+                yield writer.finish_file();
             }
         }
-    }
-
-    if !has_right {
-        return Err(AppError::NoRight("no.right".to_string()));
-    }
-
-    let path_info: PathInfo = path_rep.dao.find_by_id(path_id).await?;
-
-    // 创建临时目录
-    let temp_dir = tempdir().map_err(|e| AppError::InternalError(e.to_string()))?;
-    let mut max_id = 0 as i64;
-    let mut file_list: Vec<FileInfo> = Vec::new();
-    let mut create_dir_map: HashMap<&String, bool> = HashMap::new();
-    let mut current_list = file_rep.path_file_list(&path_info.full_path, max_id, bucket_id).await?;
-    while current_list.len() > 0 {
-        file_list.extend(current_list.clone());
-        let info = current_list.last().unwrap();
-        max_id = info.id;
-        current_list = file_rep.path_file_list(&path_info.full_path, max_id, bucket_id).await?
-    }
-    for file in file_list.iter() {
-        let data_dir = &temp_dir.path().join(&file.full_path);
-        let option = create_dir_map.get(&file.full_path);
-        if option.is_none() {
-            fs::create_dir_all(&data_dir)?;
-            create_dir_map.insert(&file.full_path, true);
-        }
-
-        let output_path = data_dir.join(&file.name);
-        let mut output_file = File::create(&output_path).await?;
-        for item in file.items.iter() {
-            let path = Path::new(&item.path);
-            if !path.exists(){
-                return Err(AppError::InternalError("file not exists".to_owned()));
-            }
-            let mut chunk_file = File::open(&*item.path).await?;
-            let mut buffer = Vec::new();
-            chunk_file.read_to_end(&mut buffer).await?;
-            output_file.write_all(&buffer).await?;
-        }
-    }
-    let mut out_file = tempdir().map_err(|e| AppError::InternalError(e.to_string()))?;
-    let download_file = out_file.path().join("default.zip");
-    do_zip(temp_dir.path().as_ref(), download_file.as_path(), zip::CompressionMethod::Deflated).map_err(|e| AppError::InternalError(e.to_string()));
-
-    let file = File::open(download_file).await?;
-    let stream = ReaderStream::new(file);
+        yield writer.finish();
+    };
     Ok(HttpResponse::Ok()
         .append_header((header::CONTENT_TYPE, "application/zip"))
         .append_header((header::CONTENT_DISPOSITION, "attachment; filename=\"default.zip\""))
-        .streaming(stream))
+        .streaming(body))
 }
 
-/// **大文件流式下载**（`streaming`）
+/// **大文件流式下载**（`streaming`），支持单区间与多区间 Range 请求
 #[get("/download/{file_id}")]
 async fn download(
     req: HttpRequest,
     state: web::Data<AppState>,
     file_id: web::Path<i64>,
-    user_bucket_rep: web::Data<UserBucketRepository>,
-    bucket_rep: web::Data<BucketRepository>,
+    auth_service: web::Data<AuthService>,
+    api_key_rep: web::Data<ApiKeyRepository>,
     file_rep: web::Data<FileRepository>,
+    audit_rep: web::Data<AuditLogRepository>,
 ) -> Result<impl Responder, AppError> {
-    let file_info: FileInfo = match file_rep.dao.find_by_id(*file_id).await {
-        Ok(file) => file,
-        Err(e) => return Err(AppError::NotFound("file.not.found".to_owned())),
+    let mut audit = AuditEntry::new(&req, AuditAction::Download);
+    audit.target(*file_id, "");
+    let file_info = find_readable_file(&req, &state, *file_id, &auth_service, &api_key_rep, &file_rep, Some(&mut audit)).await;
+    audit_rep.record(audit.outcome(&file_info)).await;
+    let file_info = file_info?;
+
+    let chunks: Vec<ChunkRef> = file_info.chunk_refs();
+    let digest = file_info.digest();
+    let size: u64 = chunks.iter().map(|chunk| chunk.size).sum();
+    let content_type = file_info.file_type.content_type(&file_info.name);
+    let content_disposition = header::ContentDisposition {
+        disposition: header::DispositionType::Attachment,
+        parameters: vec![
+            header::DispositionParam::Filename(file_info.name.clone()),
+            header::DispositionParam::FilenameExt(header::ExtendedValue {
+                charset: header::Charset::Ext("UTF-8".to_owned()),
+                language_tag: None,
+                value: file_info.name.clone().into_bytes(),
+            }),
+        ],
     };
-    let mut has_right = false;
-    let bucket_info = bucket_rep.dao.find_by_id(file_info.bucket_id).await?;
-    if bucket_info.pub_read {
-        has_right = true;
+    // 客户端缓存的 ETag 与文件摘要一致时直接返回 304
+    if let Some(etag) = digest.etag() {
+        let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+        if if_none_match.is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")) {
+            return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
+        }
     }
-
-    if !has_right {
-        let user_id = get_session_user(&state, req).await?.id;
-        let user_bucket_list_right = user_bucket_rep.query_by_user_id_and_bucket_Id(&user_id,&bucket_info.id).await?;
-        for user_bucket_tmp in &user_bucket_list_right {
-            if &user_bucket_tmp.bucket_id == &file_info.bucket_id {
-                match &user_bucket_tmp.user_right {
-                    1 => {
-                        has_right = false;
-                    }
-                    (item) => {
-                        has_right = true;
-                    }
-                }
+    let range_header = req.headers().get(header::RANGE).and_then(|value| value.to_str().ok());
+    let (mut builder, range) = match parse_range(range_header, size) {
+        RangeRequest::Unsatisfiable => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish());
+        }
+        RangeRequest::Partial(ranges) if ranges.len() > 1 => {
+            // 多区间，返回 multipart/byteranges
+            let body = MultiRangeBody::new(ranges, content_type, size);
+            let mut builder = HttpResponse::PartialContent();
+            if let Some(etag) = digest.etag() {
+                builder.insert_header((header::ETAG, etag));
             }
+            return Ok(builder
+                .insert_header((header::ACCEPT_RANGES, "bytes"))
+                .insert_header((header::CONTENT_TYPE, body.content_type()))
+                .insert_header(content_disposition)
+                .no_chunking(body.content_length)
+                .streaming(body.into_stream(state.storage.clone(), chunks)));
+        }
+        RangeRequest::Partial(ranges) => {
+            let range = ranges[0];
+            let mut builder = HttpResponse::PartialContent();
+            builder.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, range.end, size)));
+            (builder, Some(range))
         }
+        RangeRequest::Full => (HttpResponse::Ok(), None),
+    };
+    builder
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::CONTENT_TYPE, content_type))
+        .insert_header(content_disposition);
+    if let Some(etag) = digest.etag() {
+        builder.insert_header((header::ETAG, etag));
     }
-    if !has_right {
-        return Err(AppError::NoRight("no.right".to_owned()));
+    // Digest 为整个文件的摘要，部分响应不返回
+    if let (None, Some(value)) = (range, digest.digest_header()) {
+        builder.insert_header(("Digest", value));
     }
+    let range = match range {
+        Some(range) => range,
+        None if size == 0 => return Ok(builder.finish()),
+        None => ByteRange { start: 0, end: size - 1 },
+    };
+    Ok(builder
+        .no_chunking(range.length())
+        .streaming(chunk_stream(state.storage.clone(), chunks, range)))
+}
+
 
-    let item_files = file_info.items;
-    let mut buffer = Vec::new();
-    for item in item_files.iter() {
-        let mut item_file = File::open(&item.path).await.unwrap();
-        item_file.read_to_end(&mut buffer).await?;
+/// **缩略图**，`size` 为 `[thumbnail] sizes` 中配置的尺寸，未生成时返回 404
+#[get("/thumbnail/{file_id}/{size}")]
+async fn thumbnail(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    params: web::Path<(i64, u32)>,
+    auth_service: web::Data<AuthService>,
+    api_key_rep: web::Data<ApiKeyRepository>,
+    file_rep: web::Data<FileRepository>,
+) -> Result<impl Responder, AppError> {
+    let (file_id, size) = params.into_inner();
+    let file_info = find_readable_file(&req, &state, file_id, &auth_service, &api_key_rep, &file_rep, None).await?;
+    let thumbnail = match file_info.thumbnail(size) {
+        Some(thumbnail) => thumbnail,
+        None => return Err(AppError::NotFound("thumbnail.not.found".to_owned())),
+    };
+    // 缩略图内容不变，分片 hash 即 ETag
+    let etag = format!("\"{}\"", thumbnail.item.hash);
+    let if_none_match = req.headers().get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok());
+    if if_none_match.is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")) {
+        return Ok(HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish());
     }
-    let accachement_name=format!("attachment; filename={}",&file_info.name);
+    let chunk = thumbnail.item.chunk_ref();
+    let data = read_chunk(&*state.storage, &chunk, 0, chunk.size).await?;
     Ok(HttpResponse::Ok()
-        .append_header((header::CONTENT_TYPE, "application/zip"))
-        .append_header((header::CONTENT_DISPOSITION, accachement_name))
-        .body(buffer))
+        .insert_header((header::CONTENT_TYPE, "image/webp"))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::CACHE_CONTROL, "private, max-age=86400"))
+        .body(data))
+}
+
+/// 查询文件并校验读权限，公开读的 bucket 不需要登录，携带预签名时按签名用户校验
+///
+/// 传入审计记录时补充文件与实际访问用户
+async fn find_readable_file(
+    req: &HttpRequest,
+    state: &web::Data<AppState>,
+    file_id: i64,
+    auth_service: &AuthService,
+    api_key_rep: &ApiKeyRepository,
+    file_rep: &FileRepository,
+    mut audit: Option<&mut AuditEntry>,
+) -> Result<FileInfo, AppError> {
+    let file_info: FileInfo = match file_rep.dao.find_by_id(file_id).await {
+        Ok(file) => file,
+        Err(_) => return Err(AppError::NotFound("file.not.found".to_owned())),
+    };
+    if let Some(audit) = audit.as_mut() {
+        audit.bucket(file_info.bucket_id).target(file_info.id, &format!("{}{}", file_info.full_path, file_info.name));
+    }
+    let user: Option<Subject> = match PresignedRequest::from_request(req)? {
+        Some(presigned) => {
+            let credential = match api_key_rep.find_credential(&presigned.access_key).await {
+                Ok(credential) => credential,
+                Err(_) => return Err(AppError::NoRight("no.right".to_owned())),
+            };
+            presigned.verify(req, &credential.secret_key)?;
+            api_key_rep.mark_used(&credential).await;
+            Some(credential.subject)
+        }
+        None => find_session_user(state, req).await?.as_ref().map(Subject::from),
+    };
+    if let (Some(audit), Some(user)) = (audit, user) {
+        audit.actor(user.id);
+    }
+    auth_service.authorize_path_ref(user, file_info.bucket_id, file_info.path_ref, Action::Read).await?;
+    Ok(file_info)
 }
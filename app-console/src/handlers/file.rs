@@ -1,15 +1,13 @@
 use actix_web::web::Data;
-use actix_web::{post, web, App, HttpRequest, Responder};
+use actix_web::{post, web, HttpRequest, Responder};
 use chrono::{Local, NaiveDateTime};
-use common::{build_snow_id, build_time, get_session_user, result, result_data, result_list, AppError, AppState, OrderType};
+use common::{build_snow_id, get_session_user, result, result_data, AppError, AppState, OrderType};
 use model::date_format::date_format;
-use model::{BucketRepository, FileInfo, FileRepository, FileType, ImageType, PathDelTask, PathDelTaskRepository, PathRepository, QueryParam, Repository, UserBucketRepository, UserRepository};
+use model::{Action, AuditAction, AuditEntry, AuditLogRepository, AuthService, ChunkRepository, FileInfo, FileRepository, FileType, ImageType, PathDelTask, PathDelTaskRepository, PathRepository, QueryParam, Repository};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use log::error;
-use tokio::fs;
 
-pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+pub fn configure(cfg: &mut web::ServiceConfig, _state: Data<AppState>) {
     cfg.service(file_list);
     cfg.service(file_path_info);
     cfg.service(mkdir);
@@ -21,44 +19,38 @@ pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
 pub async fn mkdir(dto: web::Json<PathNewDao>,
                    app_state: web::Data<AppState>,
                    path_info_rep: web::Data<PathRepository>,
-                   user_bucket_rep: web::Data<UserBucketRepository>,
+                   auth_service: web::Data<AuthService>,
+                   audit_rep: web::Data<AuditLogRepository>,
                    req: HttpRequest, ) -> Result<impl Responder, AppError> {
-    if (dto.parent == 0 && dto.path.is_empty()) {
+    if dto.parent == 0 && dto.path.is_empty() {
         return Err(AppError::InvalidInput("invalid.params".to_string()));
     }
-    let user = get_session_user(&app_state, req).await?;
-    let mut right = false;
-    if user.is_admin {
-        right = true;
-    }
-    if !right {
-        let user_bucket = user_bucket_rep.query_by_user_id_and_bucket_Id(&user.id, &dto.bucket_id).await?;
-        if user_bucket.is_empty() {
-            return Err(AppError::NoRight("no.right".to_string()));
-        }
-        match &user_bucket[0].user_right {
-            1 => {
-                right = true;
-            }
-            2 => {
-                right = true;
-            }
-            _ => {
-                return Err(AppError::NoRight("no.right".to_string()))
-            }
-        }
-    }
-    if !right {
-        return Err(AppError::NoRight("no.right".to_string()));
-    }
-    path_info_rep.new_path(&dto.path, &dto.parent, &dto.bucket_id).await?;
+    let mut audit = AuditEntry::new(&req, AuditAction::Mkdir);
+    audit.bucket(dto.bucket_id).target(dto.parent, &dto.path);
+    let outcome: Result<(), AppError> = async {
+        let user = get_session_user(&app_state, req).await?;
+        let (_, parent_path) = auth_service.authorize_path_ref(Some((&user).into()), dto.bucket_id, dto.parent, Action::Write).await?;
+        audit.target(dto.parent, &format!("{}/{}", parent_path, dto.path));
+        path_info_rep.new_path(&dto.path, &dto.parent, &dto.bucket_id).await?;
+        Ok(())
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
 
 
 #[post("/file/path/{id}")]
-async fn file_path_info(path_id: web::Path<i64>, path_rep: Data<PathRepository>) -> Result<impl Responder, AppError> {
+async fn file_path_info(
+    path_id: web::Path<i64>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    auth_service: Data<AuthService>,
+    path_rep: Data<PathRepository>,
+) -> Result<impl Responder, AppError> {
+    let user = get_session_user(&state, req).await?;
     let result = path_rep.dao.find_by_id(*path_id).await?;
+    auth_service.authorize_path_ref(Some((&user).into()), result.bucket_id, result.id, Action::Read).await?;
     Ok(web::Json(result_data(result)))
 }
 #[post("/file/del_path/{path_id}")]
@@ -68,36 +60,38 @@ async fn del_path(
     state: web::Data<AppState>,
     path_rep: Data<PathRepository>,
     path_del_task_rep: Data<PathDelTaskRepository>,
-    user_bucket_rep: Data<UserBucketRepository>, ) -> Result<impl Responder, AppError> {
-    let user = get_session_user(&state, req).await?;
-    let mut right = false;
-    if user.is_admin {
-        right = true;
-    }
-    let path_info = path_rep.dao.find_by_id(*path_id).await?;
-    if !right {
-        let user_bucket = user_bucket_rep.query_by_user_id_and_bucket_Id(&user.id, &path_info.bucket_id).await?;
-        if user_bucket.is_empty() {
-            return Err(AppError::NoRight("no.right".to_string()));
-        }
-        match &user_bucket[0].user_right {
-            1 => {
-                right = true;
-            }
-            2 => {
-                right = true;
-            }
-            _ => {
-                return Err(AppError::NoRight("no.right".to_string()))
-            }
+    auth_service: Data<AuthService>,
+    audit_rep: Data<AuditLogRepository>, ) -> Result<impl Responder, AppError> {
+    let mut audit = AuditEntry::new(&req, AuditAction::PathDelete);
+    audit.target(*path_id, "");
+    let outcome: Result<(), AppError> = async {
+        let user = get_session_user(&state, req).await?;
+        let path_info = path_rep.dao.find_by_id(*path_id).await?;
+        audit.bucket(path_info.bucket_id).target(path_info.id, &path_info.full_path);
+        auth_service.authorize_path_ref(Some((&user).into()), path_info.bucket_id, path_info.id, Action::Delete).await?;
+        let now = Local::now();
+        let path_del_task = PathDelTask {
+            id: build_snow_id(),
+            path_id: *path_id,
+            bucket_id: path_info.bucket_id,
+            full_path: path_info.full_path.clone(),
+            del_file_status: false,
+            del_path_status: false,
+            lock_time: None,
+            create_time: now.naive_local(),
+        };
+        path_del_task_rep.create(path_del_task, &path_rep).await?;
+        // 缓存 key 为 `bucket_id:full_path`，目录及其子目录全部失效；其他进程的缓存命中时会按 id 重新确认
+        let cache_key = format!("{}:{}", path_info.bucket_id, path_info.full_path);
+        state.db_path_cache.invalidate(&cache_key).await;
+        let prefix = format!("{}/", cache_key);
+        if let Err(e) = state.db_path_cache.invalidate_entries_if(move |key, _| key.starts_with(&prefix)) {
+            error!("invalidate path cache {} error: {}", cache_key, e);
         }
-    }
-    if !right {
-        return Err(AppError::NoRight("no.right".to_string()));
-    }
-    let now = Local::now();
-    let path_del_task = PathDelTask { id: build_snow_id(), path_id: *path_id, del_file_status: false, del_path_status: false, create_time: now.naive_local() };
-    path_del_task_rep.create(path_del_task, &path_rep).await?;
+        Ok(())
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
 #[post("/file/delete/{file_id}")]
@@ -106,96 +100,76 @@ async fn file_del(
     req: HttpRequest,
     state: web::Data<AppState>,
     file_rep: Data<FileRepository>,
-    bucket_rep: web::Data<BucketRepository>,
-    user_bucket_rep: Data<UserBucketRepository>, ) -> Result<impl Responder, AppError>
+    auth_service: Data<AuthService>,
+    audit_rep: Data<AuditLogRepository>,
+    chunk_rep: Data<ChunkRepository>, ) -> Result<impl Responder, AppError>
 {
-    let file_info: FileInfo = match file_rep.dao.find_by_id(*file_id).await {
-        Ok(file) => file,
-        Err(e) => return Err(AppError::NotFound("file.not.found".to_owned())),
-    };
-    let mut has_right = false;
-    let bucket_info = bucket_rep.dao.find_by_id(file_info.bucket_id).await?;
-    if bucket_info.pub_read {
-        has_right = true;
-    }
-
-    if !has_right {
-        let user_id = get_session_user(&state, req).await?.id;
-        let user_bucket_list_right = user_bucket_rep.query_by_user_id_and_bucket_Id(&user_id, &bucket_info.id).await?;
-        for user_bucket_tmp in &user_bucket_list_right {
-            if &user_bucket_tmp.bucket_id == &file_info.bucket_id {
-                match &user_bucket_tmp.user_right {
-                    1 => {
-                        has_right = false;
-                    }
-                    2 => {
-                        has_right = false;
-                    }
-                    (item) => {
-                        has_right = true;
-                    }
-                }
-            }
-        }
-    }
-    if !has_right {
-        return Err(AppError::NoRight("no.right".to_owned()));
-    }
-    let item_files = file_info.items;
-    for file_item in item_files.iter() {
-        let msg = match fs::remove_file(&file_item.path).await {
-            Ok(_) => "",
-            Err(e) => &e.to_string(),
+    let mut audit = AuditEntry::new(&req, AuditAction::FileDelete);
+    audit.target(*file_id, "");
+    let outcome: Result<(FileInfo, bool), AppError> = async {
+        let file_info: FileInfo = match file_rep.dao.find_by_id(*file_id).await {
+            Ok(file) => file,
+            Err(_) => return Err(AppError::NotFound("file.not.found".to_owned())),
         };
-        if !msg.is_empty() {
-            error!("delete file {} error: {}", file_item.path, msg);
-        }
+        audit.bucket(file_info.bucket_id).target(file_info.id, &format!("{}{}", file_info.full_path, file_info.name));
+        let user = get_session_user(&state, req).await?;
+        auth_service.authorize_path_ref(Some((&user).into()), file_info.bucket_id, file_info.path_ref, Action::Delete).await?;
+        //删除记录的同时归还配额，并发删除时只有一个请求释放分片
+        let deleted = file_rep.delete_file(&file_info).await?;
+        Ok((file_info, deleted))
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    let (file_info, deleted) = outcome?;
+    if !deleted {
+        return Ok(web::Json(result()));
+    }
+    //分片可能被其他文件共用，按引用计数释放
+    if let Err(e) = chunk_rep.release_items(&*state.storage, &file_info.stored_items()).await {
+        error!("release file {} chunks error: {}", file_id, e);
     }
-    file_rep.dao.del_by_id(*file_id).await?;
     Ok(web::Json(result()))
 }
 
 #[post("/file/list")]
 async fn file_list(
     query: web::Json<PathQuery>,
+    req: HttpRequest,
+    state: web::Data<AppState>,
     file_rep: Data<FileRepository>,
     path_rep: Data<PathRepository>,
-    user_bucket_rep: Data<UserBucketRepository>,
+    auth_service: Data<AuthService>,
 ) -> Result<impl Responder, AppError> {
+    let user = get_session_user(&state, req).await?;
+    auth_service.authorize_path_ref(Some((&user).into()), query.bucket_id, query.path_id, Action::Read).await?;
     let mut params=vec![];
     // let mut params: HashMap<&str, String> = HashMap::new();
-    if (query.path_id == 0) {
+    if query.path_id == 0 {
         // params.insert("root", "1".to_owned());
         params.push(QueryParam::eq("root", "1".to_owned().as_str()));
     }
     let mut result_list: Vec<FileResult> = Vec::new();
     params.push(QueryParam::eq("bucket_id", query.bucket_id.to_string().as_str()));
-    if query.query_type == QueryDataType::DIR {
+    if query.query_type == QueryDataType::Dir {
         let mut path_params = params.clone();
-        if (query.path_id != 0) {
+        if query.path_id != 0 {
             path_params.push(QueryParam::eq("parent", query.path_id.to_string().as_str()));
         }
         let mut path_query=params.clone();
-        if query.search_key.is_some() {
-            match &query.search_key{
-                Some(key) => {
-                    path_query.push(QueryParam::like_end("path", key));
-                }
-                None => {}
-            }
+        if let Some(key) = &query.search_key {
+            path_query.push(QueryParam::like_end("path", key));
         }
         path_query.push(QueryParam::eq("bucket_id", query.bucket_id.to_string().as_str()));
         path_query.push(QueryParam::eq("parent", query.path_id.to_string().as_str()));
         let path_list = path_rep.dao.query_by_max_id(query.max_id,path_query, OrderType::ASC, &query.page_size).await?;
         for item in path_list {
             let path_file_name = format!("{}{}", &item.full_path, "/");
-            let x = file_rep.path_size(&path_file_name).await?;
+            let x = file_rep.path_size(item.bucket_id, &path_file_name).await?;
             let file = FileResult {
                 id: item.id,
                 bucket_id: item.bucket_id,
                 file_name: item.path,
                 file_type: FileType::DIR,
-                size: x.clone() as u32,
+                size: x as u32,
                 image_type: ImageType::NONE,
                 create_time: item.create_time,
             };
@@ -205,19 +179,18 @@ async fn file_list(
     let current_data_size = result_list.len() as i64;
     let page_size = query.page_size as i64;
     let mut file_max_id = 0;
-    if query.query_type == QueryDataType::FILE {
+    if query.query_type == QueryDataType::File {
         file_max_id = query.max_id;
     }
     if current_data_size < page_size {
         params.push(QueryParam::eq("path_ref", query.path_id.to_string().as_str()));
-        if query.search_key.is_some() {
-            match &query.search_key{
-                Some(key) => {
-                    params.push(QueryParam::like_end("name", key));
-                }
-                None => {}
-            }
+        if let Some(key) = &query.search_key {
+            params.push(QueryParam::like_end("name", key));
+        }
+        for param in &query.filter {
+            param.check_public()?;
         }
+        params.extend(query.filter.iter().cloned());
         let limit_size = (page_size - current_data_size) as i16;
         let file_list = file_rep
             .dao
@@ -242,8 +215,8 @@ async fn file_list(
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 enum QueryDataType {
-    FILE,
-    DIR,
+    File,
+    Dir,
 }
 
 #[derive(Debug, Deserialize)]
@@ -255,6 +228,9 @@ struct PathQuery {
     query_type: QueryDataType,
     search_key: Option<String>,
     max_id: i64,
+    //按 file_info 的列过滤，只作用于文件
+    #[serde(default)]
+    filter: Vec<QueryParam>,
 }
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
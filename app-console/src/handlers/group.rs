@@ -0,0 +1,158 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use common::*;
+use model::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use validator::Validate;
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(group_list);
+    cfg.service(group_save);
+    cfg.service(group_delete);
+    cfg.service(member_list);
+    cfg.service(member_add);
+    cfg.service(member_remove);
+}
+
+/// 用户组只允许管理员维护
+async fn check_admin(state: &Data<AppState>, req: HttpRequest) -> Result<()> {
+    let user = get_session_user(state, req).await?;
+    match user.is_admin {
+        true => Ok(()),
+        false => Err(AppError::NoRight("no.right".to_owned())),
+    }
+}
+
+#[post("/group/list")]
+async fn group_list(
+    req: HttpRequest,
+    state: Data<AppState>,
+    page: web::Json<PageQuery>,
+    user_group_rep: Data<UserGroupRepository>,
+) -> Result<impl Responder> {
+    check_admin(&state, req).await?;
+    let page_result = user_group_rep.dao.query_by_page(page.filter()?, &page.page).await?;
+    Ok(web::Json(result_page(page_result)))
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(rename_all = "camelCase")]
+struct GroupSaveDto {
+    id: i64,
+    #[validate(length(min = 1, max = 64))]
+    name: String,
+}
+
+#[post("/group/save")]
+async fn group_save(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<GroupSaveDto>,
+    user_group_rep: Data<UserGroupRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    if let Err(e) = dto.validate() {
+        let msg = format!("Validation failed: {:?}", e);
+        return Ok(web::Json(result_error_msg(msg.as_str())));
+    }
+    let group_id = match dto.id {
+        0 => build_snow_id(),
+        id => id,
+    };
+    let mut audit = AuditEntry::new(&req, AuditAction::GroupSave);
+    audit.target(group_id, &dto.name);
+    let outcome: Result<()> = async {
+        check_admin(&state, req).await?;
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("name", dto.name.clone());
+        if dto.id == 0 {
+            params.insert("id", group_id.to_string());
+            params.insert("create_time", build_time().await);
+            user_group_rep.dao.insert(params).await?;
+        } else {
+            user_group_rep.dao.change(dto.id, params).await?;
+        }
+        Ok(())
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+#[post("/group/delete/{id}")]
+async fn group_delete(
+    req: HttpRequest,
+    state: Data<AppState>,
+    id: web::Path<i64>,
+    user_group_rep: Data<UserGroupRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::GroupDelete);
+    audit.target(*id, "");
+    let outcome: Result<()> = async {
+        check_admin(&state, req).await?;
+        user_group_rep.del_group(*id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+#[post("/group/member/list/{group_id}")]
+async fn member_list(
+    req: HttpRequest,
+    state: Data<AppState>,
+    group_id: web::Path<i64>,
+    user_group_rep: Data<UserGroupRepository>,
+) -> Result<impl Responder> {
+    check_admin(&state, req).await?;
+    let list = user_group_rep.member_dao.query_by_params(vec![QueryParam::eq("group_id", group_id.to_string().as_str())]).await?;
+    Ok(web::Json(result_list(list)))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GroupMemberDto {
+    group_id: i64,
+    user_id: i64,
+}
+
+#[post("/group/member/add")]
+async fn member_add(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<GroupMemberDto>,
+    user_group_rep: Data<UserGroupRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::GroupMember);
+    audit.target(dto.group_id, &format!("add {}", dto.user_id));
+    let outcome: Result<()> = async {
+        check_admin(&state, req).await?;
+        user_group_rep.add_member(dto.group_id, dto.user_id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+#[post("/group/member/remove")]
+async fn member_remove(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<GroupMemberDto>,
+    user_group_rep: Data<UserGroupRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::GroupMember);
+    audit.target(dto.group_id, &format!("remove {}", dto.user_id));
+    let outcome: Result<u64> = async {
+        check_admin(&state, req).await?;
+        user_group_rep.remove_member(dto.group_id, dto.user_id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
@@ -1,14 +1,21 @@
+// actix 处理函数按提取器注入依赖，参数较多
+#![allow(clippy::too_many_arguments)]
+pub mod api_key;
+pub mod audit;
 pub mod auth;
 pub mod bucket;
 pub mod common;
 pub mod download;
+pub mod group;
+pub mod path_grant;
+pub mod presign;
+pub mod totp;
 pub mod upload;
 pub mod user;
 pub mod user_bucket;
 pub mod file;
 pub use actix_web::web;
 use ::common::AppState;
-pub use file::*;
 
 pub fn configure(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
     common::configure(cfg);
@@ -18,6 +25,12 @@ pub fn configure(cfg: &mut web::ServiceConfig, state: web::Data<AppState>) {
     auth::configure(cfg, state.clone());
     upload::configure(cfg, state.clone());
     download::configure(cfg, state.clone());
+    presign::configure(cfg, state.clone());
+    path_grant::configure(cfg, state.clone());
+    group::configure(cfg, state.clone());
+    audit::configure(cfg, state.clone());
+    api_key::configure(cfg, state.clone());
+    totp::configure(cfg, state.clone());
     file::configure(cfg, state.clone());
 }
 
@@ -0,0 +1,161 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use common::*;
+use model::*;
+use serde::{Deserialize, Serialize};
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(grant_save);
+    cfg.service(grant_list);
+    cfg.service(grant_delete);
+    cfg.service(path_permissions);
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PathGrantDto {
+    path_id: i64,
+    //0 用户 1 用户组
+    subject_type: i32,
+    subject_id: i64,
+    //0 允许 1 拒绝
+    effect: i32,
+    permissions: Vec<Action>,
+}
+
+/// 目录授权需要 bucket 的管理权限
+async fn find_admin_path(
+    state: &Data<AppState>,
+    req: HttpRequest,
+    path_id: i64,
+    auth_service: &AuthService,
+    path_rep: &PathRepository,
+) -> Result<PathInfo> {
+    let user = get_session_user(state, req).await?;
+    let path = match path_rep.dao.find_by_id(path_id).await {
+        Ok(path) => path,
+        Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("path.not.found".to_owned())),
+        Err(e) => return Err(e),
+    };
+    auth_service.authorize_id(Some((&user).into()), path.bucket_id, Action::Admin).await?;
+    Ok(path)
+}
+
+#[post("/path/grant/save")]
+async fn grant_save(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<PathGrantDto>,
+    auth_service: Data<AuthService>,
+    path_rep: Data<PathRepository>,
+    path_grant_rep: Data<PathGrantRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    if ![PathGrant::SUBJECT_USER, PathGrant::SUBJECT_GROUP].contains(&dto.subject_type) {
+        return Err(AppError::InvalidInput("subject_type.invalid".to_owned()));
+    }
+    if ![PathGrant::EFFECT_ALLOW, PathGrant::EFFECT_DENY].contains(&dto.effect) {
+        return Err(AppError::InvalidInput("effect.invalid".to_owned()));
+    }
+    let mut audit = AuditEntry::new(&req, AuditAction::PathGrantSave);
+    audit.target(dto.path_id, "");
+    let outcome: Result<i64> = async {
+        let path = find_admin_path(&state, req, dto.path_id, &auth_service, &path_rep).await?;
+        audit.bucket(path.bucket_id).target(path.id, &path.full_path);
+        path_grant_rep
+            .save_grant(path.bucket_id, path.id, dto.subject_type, dto.subject_id, dto.effect, PermissionSet::from_actions(&dto.permissions))
+            .await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    Ok(web::Json(result_data(outcome?.to_string())))
+}
+
+#[post("/path/grant/list/{path_id}")]
+async fn grant_list(
+    req: HttpRequest,
+    state: Data<AppState>,
+    path_id: web::Path<i64>,
+    auth_service: Data<AuthService>,
+    path_rep: Data<PathRepository>,
+    path_grant_rep: Data<PathGrantRepository>,
+) -> Result<impl Responder> {
+    let path = find_admin_path(&state, req, *path_id, &auth_service, &path_rep).await?;
+    let list = path_grant_rep.dao.query_by_params(vec![QueryParam::eq("path_id", path.id.to_string().as_str())]).await?;
+    Ok(web::Json(result_list(list)))
+}
+
+#[post("/path/grant/delete/{id}")]
+async fn grant_delete(
+    req: HttpRequest,
+    state: Data<AppState>,
+    id: web::Path<i64>,
+    auth_service: Data<AuthService>,
+    path_rep: Data<PathRepository>,
+    path_grant_rep: Data<PathGrantRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::PathGrantDelete);
+    audit.target(*id, "");
+    let outcome: Result<u64> = async {
+        let grant = match path_grant_rep.dao.find_by_id(*id).await {
+            Ok(grant) => grant,
+            Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("grant.not.found".to_owned())),
+            Err(e) => return Err(e),
+        };
+        let path = find_admin_path(&state, req, grant.path_id, &auth_service, &path_rep).await?;
+        audit.bucket(path.bucket_id).target(path.id, &path.full_path);
+        path_grant_rep.dao.del_by_id(grant.id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PathPermissionsDto {
+    bucket_id: i64,
+    //0 为 bucket 根目录
+    path_id: i64,
+    //为空时查询当前用户，查询其他用户需要 bucket 的管理权限
+    user_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PathPermissionsResult {
+    user_id: String,
+    full_path: String,
+    permissions: Vec<Action>,
+}
+
+/// 用户在目录上的有效权限，已合并 bucket 权限与各级目录授权
+#[post("/path/permissions")]
+async fn path_permissions(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<PathPermissionsDto>,
+    auth_service: Data<AuthService>,
+    user_rep: Data<UserRepository>,
+) -> Result<impl Responder> {
+    let user = get_session_user(&state, req).await?;
+    let subject: Subject = match dto.user_id {
+        Some(user_id) if user_id != user.id => {
+            auth_service.authorize_id(Some((&user).into()), dto.bucket_id, Action::Admin).await?;
+            match user_rep.dao.find_by_id(user_id).await {
+                Ok(user_info) => (&user_info).into(),
+                Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("user.not.found".to_owned())),
+                Err(e) => return Err(e),
+            }
+        }
+        _ => (&user).into(),
+    };
+    let (bucket, full_path) = auth_service.resolve_path_ref(dto.bucket_id, dto.path_id).await?;
+    let permissions = auth_service.path_permissions(Some(subject), &bucket, &full_path).await?;
+    Ok(web::Json(result_data(PathPermissionsResult {
+        user_id: subject.id.to_string(),
+        full_path,
+        permissions: permissions.actions(),
+    })))
+}
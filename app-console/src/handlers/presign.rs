@@ -0,0 +1,119 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use chrono::Utc;
+use common::{get_session_user, result_data, AppError, AppState, PresignedRequest, SignConfig, PRESIGN_PATH};
+use model::{Action, ApiKeyRepository, AuthService, BucketRepository, FileInfo, FileRepository, Repository, Subject};
+use sanitize_filename::sanitize;
+use serde::{Deserialize, Serialize};
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(presign_download);
+    cfg.service(presign_upload);
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignDownloadDto {
+    pub file_id: i64,
+    //有效期（秒），为空时使用 [sign] presign_expires
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignUploadDto {
+    pub bucket: String,
+    pub path: Option<String>,
+    pub expires: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PresignResult {
+    pub url: String,
+    pub method: &'static str,
+    //过期时间（Unix 秒）
+    pub expires: i64,
+}
+
+/// **预签名下载地址**，使用当前用户的预签名密钥签名，持有地址即可在有效期内下载
+#[post("/presign/download")]
+async fn presign_download(
+    req: HttpRequest,
+    dto: web::Json<PresignDownloadDto>,
+    state: Data<AppState>,
+    sign_config: Data<SignConfig>,
+    api_key_rep: Data<ApiKeyRepository>,
+    file_rep: Data<FileRepository>,
+    auth_service: Data<AuthService>,
+) -> Result<impl Responder, AppError> {
+    let user = get_session_user(&state, req).await?;
+    let expires = expires_at(&sign_config, dto.expires)?;
+    let file_info: FileInfo = match file_rep.dao.find_by_id(dto.file_id).await {
+        Ok(file) => file,
+        Err(_) => return Err(AppError::NotFound("file.not.found".to_owned())),
+    };
+    //下载时按签名用户重新校验读权限，这里提前拒绝无权限的请求
+    let subject = Subject::from(&user);
+    let (bucket, dir) = auth_service.authorize_path_ref(Some(subject), file_info.bucket_id, file_info.path_ref, Action::Share).await?;
+    auth_service.authorize_path(Some(subject), &bucket, &dir, Action::Read).await?;
+    let key = api_key_rep.presign_key(user.id).await?;
+    let path = format!("/download/{}", file_info.id);
+    let url = PresignedRequest::sign("GET", &path, &[], &key.access_key, &key.secret_key, expires);
+    Ok(web::Json(result_data(PresignResult {
+        url: format!("{}{}", sign_config.console_url.trim_end_matches('/'), url),
+        method: "GET",
+        expires,
+    })))
+}
+
+/// **预签名上传地址**，指向 app-api 的 `/upload/{bucket}`，上传目录由签名固定
+#[post("/presign/upload")]
+async fn presign_upload(
+    req: HttpRequest,
+    dto: web::Json<PresignUploadDto>,
+    state: Data<AppState>,
+    sign_config: Data<SignConfig>,
+    api_key_rep: Data<ApiKeyRepository>,
+    bucket_rep: Data<BucketRepository>,
+    auth_service: Data<AuthService>,
+) -> Result<impl Responder, AppError> {
+    let user = get_session_user(&state, req).await?;
+    let expires = expires_at(&sign_config, dto.expires)?;
+    let path = dto.path.clone().unwrap_or_default();
+    if path.len() > 128 {
+        return Err(AppError::InvalidInput("path.too.long".to_owned()));
+    }
+    let bucket_info = match bucket_rep.find_by_name(&dto.bucket).await {
+        Ok(bucket) => bucket,
+        Err(_) => return Err(AppError::NotFound("bucket.not.found".to_owned())),
+    };
+    let subject = Subject::from(&user);
+    //与上传时一样按清理后的目录校验
+    let dir = path.split('/').map(sanitize).filter(|item| !item.is_empty()).collect::<Vec<String>>().join("/");
+    auth_service.authorize_path(Some(subject), &bucket_info, &dir, Action::Share).await?;
+    auth_service.authorize_path(Some(subject), &bucket_info, &dir, Action::Write).await?;
+    let key = api_key_rep.presign_key(user.id).await?;
+    let url = PresignedRequest::sign(
+        "POST",
+        &format!("/upload/{}", bucket_info.name),
+        &[(PRESIGN_PATH, path)],
+        &key.access_key,
+        &key.secret_key,
+        expires,
+    );
+    Ok(web::Json(result_data(PresignResult {
+        url: format!("{}{}", sign_config.api_url.trim_end_matches('/'), url),
+        method: "POST",
+        expires,
+    })))
+}
+
+fn expires_at(sign_config: &SignConfig, expires: Option<u64>) -> Result<i64, AppError> {
+    let expires = expires.unwrap_or(sign_config.presign_expires);
+    if expires == 0 || expires > sign_config.presign_max_expires {
+        return Err(AppError::InvalidInput("expires.invalid".to_owned()));
+    }
+    Ok(Utc::now().timestamp() + expires as i64)
+}
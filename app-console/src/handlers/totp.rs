@@ -0,0 +1,251 @@
+use actix_web::web::Data;
+use actix_web::{post, web, HttpRequest, Responder};
+use common::*;
+use model::*;
+use serde::{Deserialize, Serialize};
+
+pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
+    cfg.app_data(state.clone());
+    cfg.service(totp_status);
+    cfg.service(totp_setup);
+    cfg.service(totp_enable);
+    cfg.service(totp_disable);
+    cfg.service(totp_recovery_codes);
+    cfg.service(totp_reset);
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpSetupResult {
+    //base32 密钥，用于手动输入
+    pub secret: String,
+    //otpauth:// 地址，前端生成二维码
+    pub uri: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpStatus {
+    enabled: bool,
+    //已生成密钥但还未确认
+    pending: bool,
+    //管理员被要求启用
+    required: bool,
+    recovery_codes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpCodeDto {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TotpDisableDto {
+    password: String,
+    //验证码或恢复码
+    code: String,
+}
+
+/// 生成新的密钥并加密保存，确认前不生效
+pub async fn begin_setup(user_rep: &UserRepository, config: &TotpConfig, user: &UserInfo) -> Result<TotpSetupResult> {
+    if user.totp_enabled {
+        return Err(AppError::BizError("totp.already.enabled".to_owned()));
+    }
+    let cipher = config.cipher()?;
+    let secret = generate_totp_secret()?;
+    user_rep.save_totp_secret(user.id, &cipher.encrypt(&secret)?).await?;
+    let totp = build_totp(secret, &config.issuer, &user.user_name)?;
+    Ok(TotpSetupResult {
+        secret: totp.get_secret_base32(),
+        uri: totp.get_url(),
+    })
+}
+
+/// 用待确认的密钥校验验证码，通过后启用并返回新的恢复码，验证码错误返回 None
+pub async fn confirm_setup(user_rep: &UserRepository, config: &TotpConfig, user: &UserInfo, code: &str) -> Result<Option<Vec<String>>> {
+    if user.totp_enabled {
+        return Err(AppError::BizError("totp.already.enabled".to_owned()));
+    }
+    if user.totp_secret.is_empty() {
+        return Err(AppError::BizError("totp.setup.required".to_owned()));
+    }
+    if !check_totp(user_rep, config, user, code).await? {
+        return Ok(None);
+    }
+    let codes = generate_recovery_codes(config.recovery_codes);
+    let code_hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    user_rep.enable_totp(user.id, &code_hashes).await?;
+    Ok(Some(codes))
+}
+
+/// 校验已启用用户的验证码，`allow_recovery` 时也接受恢复码，恢复码使用后失效
+pub async fn verify_code(
+    user_rep: &UserRepository,
+    config: &TotpConfig,
+    user: &UserInfo,
+    code: &str,
+    allow_recovery: bool,
+) -> Result<bool> {
+    if !user.totp_enabled {
+        return Err(AppError::BizError("totp.not.enabled".to_owned()));
+    }
+    if check_totp(user_rep, config, user, code).await? {
+        return Ok(true);
+    }
+    match allow_recovery {
+        true => user_rep.use_recovery_code(user.id, &hash_recovery_code(code)).await,
+        false => Ok(false),
+    }
+}
+
+/// 验证码通过后记录时间步，已使用过的验证码视为错误
+async fn check_totp(user_rep: &UserRepository, config: &TotpConfig, user: &UserInfo, code: &str) -> Result<bool> {
+    let secret = config.cipher()?.decrypt(&user.totp_secret)?;
+    let totp = build_totp(secret, &config.issuer, &user.user_name)?;
+    match verify_totp(&totp, code) {
+        Some(step) => user_rep.use_totp_step(user.id, step).await,
+        None => Ok(false),
+    }
+}
+
+#[post("/totp/status")]
+async fn totp_status(
+    req: HttpRequest,
+    state: Data<AppState>,
+    totp_config: Data<TotpConfig>,
+    user_rep: Data<UserRepository>,
+) -> Result<impl Responder> {
+    let session_user = get_session_user(&state, req).await?;
+    let user = user_rep.dao.find_by_id(session_user.id).await?;
+    let recovery_codes = match user.totp_enabled {
+        true => user_rep.recovery_code_count(user.id).await?,
+        false => 0,
+    };
+    Ok(web::Json(result_data(TotpStatus {
+        enabled: user.totp_enabled,
+        pending: !user.totp_enabled && !user.totp_secret.is_empty(),
+        required: totp_config.require_admin && user.is_admin,
+        recovery_codes,
+    })))
+}
+
+#[post("/totp/setup")]
+async fn totp_setup(
+    req: HttpRequest,
+    state: Data<AppState>,
+    totp_config: Data<TotpConfig>,
+    user_rep: Data<UserRepository>,
+) -> Result<impl Responder> {
+    let session_user = get_session_user(&state, req).await?;
+    let user = user_rep.dao.find_by_id(session_user.id).await?;
+    Ok(web::Json(result_data(begin_setup(&user_rep, &totp_config, &user).await?)))
+}
+
+/// 确认绑定，恢复码只在此时返回一次
+#[post("/totp/enable")]
+async fn totp_enable(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<TotpCodeDto>,
+    totp_config: Data<TotpConfig>,
+    user_rep: Data<UserRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::TotpEnable);
+    let outcome: Result<Vec<String>> = async {
+        let session_user = get_session_user(&state, req).await?;
+        audit.target(session_user.id, &session_user.user_name);
+        let user = user_rep.dao.find_by_id(session_user.id).await?;
+        match confirm_setup(&user_rep, &totp_config, &user, &dto.code).await? {
+            Some(codes) => Ok(codes),
+            None => Err(AppError::BizError("totp.code.error".to_owned())),
+        }
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    Ok(web::Json(result_list(outcome?)))
+}
+
+/// 关闭需要同时提供密码和验证码
+#[post("/totp/disable")]
+async fn totp_disable(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<TotpDisableDto>,
+    totp_config: Data<TotpConfig>,
+    user_rep: Data<UserRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::TotpDisable);
+    let outcome: Result<()> = async {
+        let session_user = get_session_user(&state, req).await?;
+        audit.target(session_user.id, &session_user.user_name);
+        let user = user_rep.dao.find_by_id(session_user.id).await?;
+        if totp_config.require_admin && user.is_admin {
+            return Err(AppError::BizError("totp.required".to_owned()));
+        }
+        if !verify_password(&dto.password, &user.password).is_match() {
+            return Err(AppError::BizError("password.is.not.correct".to_owned()));
+        }
+        if !verify_code(&user_rep, &totp_config, &user, &dto.code, true).await? {
+            return Err(AppError::BizError("totp.code.error".to_owned()));
+        }
+        user_rep.disable_totp(user.id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
+
+/// 重新生成恢复码，旧的全部失效
+#[post("/totp/recovery/codes")]
+async fn totp_recovery_codes(
+    req: HttpRequest,
+    state: Data<AppState>,
+    dto: web::Json<TotpCodeDto>,
+    totp_config: Data<TotpConfig>,
+    user_rep: Data<UserRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::TotpRecoveryCodes);
+    let outcome: Result<Vec<String>> = async {
+        let session_user = get_session_user(&state, req).await?;
+        audit.target(session_user.id, &session_user.user_name);
+        let user = user_rep.dao.find_by_id(session_user.id).await?;
+        if !verify_code(&user_rep, &totp_config, &user, &dto.code, false).await? {
+            return Err(AppError::BizError("totp.code.error".to_owned()));
+        }
+        let codes = generate_recovery_codes(totp_config.recovery_codes);
+        let code_hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+        user_rep.replace_recovery_codes(user.id, &code_hashes).await?;
+        Ok(codes)
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    Ok(web::Json(result_list(outcome?)))
+}
+
+/// 管理员为丢失验证器的用户关闭两步验证，被要求启用的管理员下次登录时重新绑定
+#[post("/totp/reset/{id}")]
+async fn totp_reset(
+    req: HttpRequest,
+    state: Data<AppState>,
+    id: web::Path<i64>,
+    user_rep: Data<UserRepository>,
+    audit_rep: Data<AuditLogRepository>,
+) -> Result<impl Responder> {
+    let mut audit = AuditEntry::new(&req, AuditAction::TotpReset);
+    audit.target(*id, "");
+    let outcome: Result<()> = async {
+        let session_user = get_session_user(&state, req).await?;
+        if !session_user.is_admin {
+            return Err(AppError::NoRight("no.right".to_owned()));
+        }
+        let user = user_rep.dao.find_by_id(*id).await?;
+        audit.target(user.id, &user.user_name);
+        user_rep.disable_totp(user.id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
+    Ok(web::Json(result()))
+}
@@ -1,17 +1,10 @@
 use actix_multipart::Multipart;
-use actix_web::{post, web, App, HttpRequest, Responder};
-use chrono::{Datelike, Local, Timelike};
-use common::{build_id, build_snow_id, build_time, get_session_user, result, result_data, result_error_msg, AppError, AppState};
+use actix_web::{post, web, HttpRequest, Responder};
+use chrono::Local;
+use common::{build_snow_id, get_session_user, result, result_error_msg, AppError, AppState, ExpectedDigest, FileDigest, FileHasher};
 use futures_util::StreamExt;
 use model::*;
-use moka::future::Cache;
-use sqlx::{FromRow, MySqlPool};
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-use std::sync::Arc;
-use serde::{Deserialize, Serialize};
 use web::Data;
 
 pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
@@ -27,86 +20,117 @@ pub async fn upload_file(
     parmas: web::Path<(i64, i64)>,
     app_state: web::Data<AppState>,
     path_info_rep: web::Data<PathRepository>,
-    user_bucket_rep: web::Data<UserBucketRepository>,
+    auth_service: web::Data<AuthService>,
     file_rep: web::Data<FileRepository>,
+    bucket_rep: web::Data<BucketRepository>,
+    chunk_rep: web::Data<ChunkRepository>,
+    audit_rep: web::Data<AuditLogRepository>,
     req: HttpRequest,
-    mut payload: Multipart,
+    payload: Multipart,
 ) -> Result<impl Responder, AppError> {
     let (bucket_id, path_id) = parmas.into_inner();
+    let mut audit = AuditEntry::new(&req, AuditAction::Upload);
+    audit.bucket(bucket_id).target(path_id, "");
+    let outcome = save_upload(
+        bucket_id, path_id, &app_state, &path_info_rep, &auth_service, &file_rep, &bucket_rep, &chunk_rep, req, payload, &mut audit,
+    ).await;
+    match &outcome {
+        Ok(Some(msg)) => audit.outcome::<()>(&Err(AppError::InvalidInput(msg.to_string()))),
+        _ => audit.outcome(&outcome),
+    };
+    audit_rep.record(&audit).await;
+    match outcome? {
+        Some(msg) => Ok(web::Json(result_error_msg(msg))),
+        None => Ok(web::Json(result())),
+    }
+}
 
-    if (path_id != 0) {
+/// 写入分片与文件记录，返回 `Some` 时为文件名等参数不合法的提示
+async fn save_upload(
+    bucket_id: i64,
+    path_id: i64,
+    app_state: &web::Data<AppState>,
+    path_info_rep: &PathRepository,
+    auth_service: &AuthService,
+    file_rep: &FileRepository,
+    bucket_rep: &BucketRepository,
+    chunk_rep: &ChunkRepository,
+    req: HttpRequest,
+    mut payload: Multipart,
+    audit: &mut AuditEntry,
+) -> Result<Option<&'static str>, AppError> {
+    if path_id != 0 {
         let path_info = path_info_rep.dao.find_by_id(path_id).await?;
-        if (path_info.bucket_id != bucket_id) {
+        if path_info.bucket_id != bucket_id {
             return Err(AppError::InvalidInput("InvalidInput.params".to_owned()));
         }
     }
-    let user_cache = get_session_user(&app_state, req).await?;
-    let mut right = false;
-    if (user_cache.is_admin) {
-        right = true;
-    }
-    if (right == false) {
-        let mut user_bucket_list_right = user_bucket_rep.query_by_user_id_and_bucket_Id(&user_cache.id, &&bucket_id).await?;
-        if (user_bucket_list_right.is_empty()) {
-            return Ok(web::Json(result_error_msg("no.right")));
-        }
-    }
+    let user_cache = get_session_user(app_state, req.clone()).await?;
+    auth_service.authorize_path_ref(Some((&user_cache).into()), bucket_id, path_id, Action::Write).await?;
+    let expected = ExpectedDigest::from_headers(req.headers())?;
 
     let mut path = String::new();
     let mut file_name: String = String::new();
     let mut uploaded_files: Vec<FileItemDto> = Vec::new();
     let mut size: usize = 0;
-    while let Some(field) = payload.next().await {
-        let mut field = field.unwrap();
+    let mut hasher = FileHasher::new();
+    // 每个分片写入前检查配额，入库时在同一事务内占用；出错时统一释放已写入的分片
+    let read_result: Result<Option<&str>, AppError> = async {
+        while let Some(field) = payload.next().await {
+            let mut field = field?;
 
-        let content_disposition = field.content_disposition().unwrap();
-        let dir_name = build_dir_name(&app_state.root_path, &app_state.dir_create_cache).await?;
+            let content_disposition = field.content_disposition().unwrap();
 
-        match content_disposition.get_name().unwrap() {
-            "file" => {
-                let mut fid = build_id();
-                // 定义分片文件的路径
+            // 只处理 file 字段，忽略未知字段
+            if content_disposition.get_name().unwrap() == "file" {
                 let mut buffer = Vec::with_capacity(CHUNK_SIZE);
                 file_name = field
                     .content_disposition()
                     .unwrap()
                     .get_filename()
-                    .unwrap()
+                    .unwrap_or("")
                     .to_string();
                 if file_name.is_empty() {
-                    return Ok(web::Json(result_error_msg("Invalid file_name value")));
+                    return Ok(Some("Invalid file_name value"));
                 }
-                while let Some(Ok(bytes)) = field.next().await {
+                while let Some(bytes) = field.next().await {
+                    let bytes = bytes?;
                     buffer.extend_from_slice(&bytes); // ✅ 累积数据
+                    hasher.update(&bytes);
                     size += bytes.len();
+                    // file_info.size 为 INT UNSIGNED，单个文件最大 4GB
+                    if size > u32::MAX as usize {
+                        return Err(AppError::InvalidInput("file.too.large".to_owned()));
+                    }
                     if buffer.len() >= CHUNK_SIZE {
-                        //写入文件
-                        let chunk_path = format!("{}/{}", dir_name, fid.clone().to_string());
-                        let file_item = FileItemDto {
-                            path: chunk_path.clone(),
-                            size: buffer.len() as u32,
-                        };
+                        //按内容写入分片，相同分片只保存一份
+                        let file_item = chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_id, size, &buffer[..CHUNK_SIZE]).await?;
                         uploaded_files.push(file_item);
-                        let mut file = File::create(&chunk_path).unwrap();
-                        file.write_all(&buffer[..CHUNK_SIZE]).unwrap();
-
                         buffer.drain(..CHUNK_SIZE); // ✅ 清除已写入的部分
-                        fid = build_id();
                     }
                 }
                 // 处理剩余数据（小于 4MB）
                 if !buffer.is_empty() {
-                    let chunk_path = format!("{}/{}", dir_name, fid.clone().to_string());
-                    let file_item = FileItemDto {
-                        path: chunk_path.clone(),
-                        size: buffer.len() as u32,
-                    };
+                    let file_item = chunk_rep.save_pending_chunk(&*app_state.storage, bucket_rep, bucket_id, size, &buffer).await?;
                     uploaded_files.push(file_item);
-                    let mut file = File::create(&chunk_path).unwrap();
-                    file.write_all(&buffer).unwrap();
                 }
             }
-            _ => {} // 忽略未知字段
+        }
+        if file_name.len() > 64 {
+            return Ok(Some("file name to lang (max=64)"));
+        }
+        Ok(None)
+    }
+    .await;
+    match read_result {
+        Ok(None) => {}
+        Ok(Some(msg)) => {
+            chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+            return Ok(Some(msg));
+        }
+        Err(e) => {
+            chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+            return Err(e);
         }
     }
 
@@ -119,31 +143,21 @@ pub async fn upload_file(
         path = path_info_rep.dao.find_by_id(path_id).await?.full_path;
     }
 
-    if file_name.len() > 64 {
-        return Ok(web::Json(result_error_msg("file name to lang (max=64)")));
+    match path.is_empty() {
+        true => audit.target(fid, &file_name),
+        false => audit.target(fid, &format!("{}/{}", path, file_name)),
+    };
+    let digest = hasher.finish();
+    if let Err(e) = expected.verify(&digest) {
+        chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+        return Err(e);
     }
-    insert_file_name(&bucket_id, &file_rep, fid, path_id, &file_name, &path, &file_type, uploaded_files, &size).await?;
-    // Ok(web::Json(result_data(fid.to_string())))
-    Ok(web::Json(result()))
-}
-
-async fn insert_file_error(conn: &MySqlPool, error_files: Vec<String>) -> Result<(), AppError> {
-    let mut items_str = String::new();
-    for item in error_files {
-        items_str.push_str(&item);
-        items_str.push_str(",");
+    if let Err(e) = insert_file_name(&bucket_id, file_rep, bucket_rep, fid, path_id, &file_name, &path, &file_type, uploaded_files.clone(), &size, &digest).await {
+        chunk_rep.release_items(&*app_state.storage, &uploaded_files).await?;
+        return Err(e);
     }
-
-    sqlx::query(
-        "insert into file_info (file, error_count, del_status, create_time)VALUES(?,0,:0,?)",
-    )
-        .bind(&items_str)
-        .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
-        .execute(conn)
-        .await
-        .unwrap();
-
-    Ok(())
+    // Ok(web::Json(result_data(fid.to_string())))
+    Ok(None)
 }
 
 ///
@@ -152,33 +166,29 @@ async fn insert_file_error(conn: &MySqlPool, error_files: Vec<String>) -> Result
 async fn insert_file_name(
     bucket_id: &i64,
     file_rep: &FileRepository,
+    bucket_rep: &BucketRepository,
     id: i64,
     path_ref: i64,
     name: &str,
-    full_path: &String,
+    full_path: &str,
     file_type: &FileType,
     items: Vec<FileItemDto>,
     size: &usize,
+    digest: &FileDigest,
 ) -> Result<(), AppError> {
     let image_type = match &file_type {
         FileType::IMAGE => ImageType::get_image_type(name),
         _ => ImageType::NONE,
     };
 
-    let root: bool;
-    if path_ref == 0 {
-        root = true;
-    } else {
-        root = false;
-    }
+    let root = path_ref == 0;
 
-    let i = u32::try_from(*size).unwrap();
     let mut params: HashMap<&str, String> = HashMap::new();
     params.insert("id", id.to_string());
     params.insert("bucket_id", bucket_id.to_string());
     params.insert("path_ref", path_ref.to_string());
     params.insert("name", name.to_string());
-    if (root) {
+    if root {
         params.insert("full_path", full_path.to_owned());
     } else {
         params.insert("full_path", format!("{}/", full_path));
@@ -194,139 +204,15 @@ async fn insert_file_name(
         },
     );
     params.insert("size", size.to_string());
+    params.insert("sha256", digest.sha256.clone());
+    params.insert("md5", digest.md5.clone());
     let now = Local::now();
     params.insert("create_time", now.format("%Y-%m-%d %H:%M:%S").to_string());
-    file_rep.insert(params, &items).await?;
-    Ok(())
-}
-
-
-///
-/// 检查目录是否存在
-async fn check_and_save_path(
-    bucket_id: &i64,
-    full_path: &String,
-    db_path_cache: &Arc<Cache<String, String>>,
-    path_info_rep: &PathRepository,
-) -> Result<i64, AppError> {
-    //判断缓存里是否存在文件夹
-    let option = db_path_cache.get(&full_path.to_string()).await;
-    let cache_dir_id = match option {
-        Some(option) => option,
-        None => "".to_string(),
-    };
-    if !cache_dir_id.is_empty() {
-        return Ok(cache_dir_id.parse().unwrap());
-    }
-    let mut root: bool;
-    if full_path.eq("") {
-        root = true;
-    } else {
-        root = false;
-    }
-
-    let path_list = &full_path.split("/").collect::<Vec<&str>>();
-    let mut current_dir: String = String::from("");
-    let mut parent_id: String = String::from("");
-
-    let mut finally_id = 0;
-    for path_item in path_list.iter() {
-        if current_dir.is_empty() {
-            current_dir = format!("{}", path_item);
-        } else {
-            current_dir = format!("{}/{}/", current_dir.clone(), &path_item);
-        }
-        let option = db_path_cache.get(current_dir.clone().as_str()).await;
-        let cache_dir_id = match option {
-            Some(option) => option,
-            None => "".to_string(),
-        };
-        if cache_dir_id.is_empty() {
-            let mut path_info: PathInfo = PathInfo::default();
-            path_info.full_path = current_dir.clone();
-
-
-            let list_path = path_info_rep.dao.query_by_params(vec![
-                QueryParam::eq("full_path",current_dir.as_str()),
-                QueryParam::eq("bucket_id",bucket_id.to_string().as_str()),
-            ]).await?;
-
-            if list_path.len() > 0 {
-                path_info = list_path[0].clone();
-                finally_id = path_info.id.clone();
-            } else {
-                let mut params: HashMap<&str, String> = HashMap::new();
-                params = HashMap::new();
-                let current_id = build_snow_id();
-                params.insert("id", current_id.to_string());
-                if parent_id == "" {
-                    root = true;
-                }
-                params.insert(
-                    "root",
-                    match root {
-                        true => "1".to_string(),
-                        false => "0".to_string(),
-                    },
-                );
-                params.insert("bucket_id", bucket_id.to_string());
-                params.insert("path", path_item.to_string());
-                params.insert("parent", parent_id.to_string());
-                params.insert("create_time", build_time().await);
-                params.insert("full_path", "".to_owned());
-                path_info_rep.dao.insert(params).await?;
-                finally_id = current_id;
-            }
-        }
-    }
-    return Ok(finally_id);
-}
-
-/// **防止路径遍历攻击**
-fn sanitize_filename(filename: &str) -> String {
-    filename.replace("/", "_").replace("\\", "_")
-}
-//
-// ///生成文件目录
-async fn build_dir_name(
-    root: &String,
-    cache: &Arc<Cache<String, String>>,
-) -> std::result::Result<String, AppError> {
-    let now = chrono::Local::now();
-    //获取年
-    let year = &now.year();
-    let year_path_str = format!("{}/{}", root, year);
-    if let None = cache.get(&year_path_str.to_string()).await {
-        let year_path = Path::new(&year_path_str);
-        if !year_path.exists() {
-            std::fs::create_dir_all(year_path);
-        }
-        cache.insert(year_path_str.clone(), "1".to_string()).await;
-    }
-    //获取一年中的第几天
-    let day = &now.date_naive();
-    let day_index = &day.ordinal(); // 获取一年中的第几天（1-366）
-    let day_path_str = format!("{}/{}/{}", &root, year, day_index);
-    if let None = cache.get(&day_path_str.to_string()).await {
-        let day_path = Path::new(&day_path_str);
-        if !day_path.exists() {
-            std::fs::create_dir_all(day_path);
-        }
-        cache.insert(day_path_str.clone(), "1".to_string()).await;
-    }
-
-    //获取一天中的第几分钟
-    let minutes_of_day = &now.hour() * 60 + &now.minute();
-
-    let minutes_path_str = format!("{}/{}/{}/{}", &root, &year, &day_index, minutes_of_day);
-    if let None = cache.get(&minutes_path_str.to_string()).await {
-        let minutes_path = Path::new(&minutes_path_str);
-        if !minutes_path.exists() {
-            std::fs::create_dir_all(minutes_path);
-        }
-        cache
-            .insert(minutes_path_str.clone(), "1".to_string())
-            .await;
-    }
-    Ok(minutes_path_str)
+    // 配额与文件记录在同一事务中写入
+    let (bucket_id, size) = (*bucket_id, *size as i64);
+    with_transaction(&file_rep.dao.pool, |mut tx| async move {
+        bucket_rep.reserve_quota_with(&mut *tx, bucket_id, size).await?;
+        file_rep.insert_with(&mut *tx, params, &items).await?;
+        Ok(((), tx))
+    }).await
 }
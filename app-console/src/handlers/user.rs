@@ -1,8 +1,8 @@
 use actix_web::web::Data;
-use actix_web::{post, web, Responder};
+use actix_web::{post, web, HttpRequest, Responder};
 use chrono::Local;
-use common::{build_id, build_md5, build_snow_id, result, result_data, result_page, result_warn_msg, AppError, AppState, PageInfo};
-use model::{QueryParam, Repository, UserRepository};
+use common::{bearer_token, build_id, build_snow_id, client_ip, get_session_user, hash_password, result, result_page, result_warn_msg, verify_password, AppError, AppState, LoginGuard, PasswordPolicy, UserCache};
+use model::{AuditAction, AuditEntry, AuditLogRepository, PageQuery, Repository, UserRepository};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::collections::HashMap;
@@ -12,28 +12,71 @@ pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
     cfg.service(user_list);
     cfg.service(user_delete);
     cfg.service(user_new);
-    cfg.service(user_change_key);
-    cfg.service(user_view_key);
     cfg.service(user_change_password);
     cfg.service(user_up_password);
 }
 
+/// 用户管理只允许管理员
+async fn check_admin(state: &Data<AppState>, req: HttpRequest) -> Result<UserCache, AppError> {
+    let user = get_session_user(state, req).await?;
+    match user.is_admin {
+        true => Ok(user),
+        false => Err(AppError::NoRight("no.right".to_owned())),
+    }
+}
+
+/// 普通用户只能操作自己的账号
+async fn check_self_or_admin(state: &Data<AppState>, req: HttpRequest, user_name: &str) -> Result<UserCache, AppError> {
+    let user = get_session_user(state, req).await?;
+    match user.is_admin || user.user_name == user_name {
+        true => Ok(user),
+        false => Err(AppError::NoRight("no.right".to_owned())),
+    }
+}
+
+/// 吊销用户已登录的会话，操作人修改自己的密码时换用新版本保留当前会话
+async fn revoke_sessions(state: &AppState, user_id: i64, operator: &UserCache, token: Option<&str>) -> Result<(), AppError> {
+    let version = state.session_store.revoke_sessions(user_id).await?;
+    if let (true, Some(token)) = (operator.id == user_id, token) {
+        let user_cache = UserCache {
+            session_version: version,
+            ..operator.clone()
+        };
+        state.session_store.put(token, &user_cache).await?;
+    }
+    Ok(())
+}
+
 #[post("/user/list")]
 async fn user_list(
-    page: web::Json<PageInfo>,
+    req: HttpRequest,
+    state: Data<AppState>,
+    page: web::Json<PageQuery>,
     user_reg: web::Data<UserRepository>,
 ) -> Result<impl Responder, AppError> {
-    let page_result = user_reg.dao.query_by_page(vec![], &page).await?;
+    check_admin(&state, req).await?;
+    let page_result = user_reg.dao.query_by_page(page.filter()?, &page.page).await?;
     Ok(web::Json(result_page(page_result)))
 }
 
 
 #[post("/user/delete/{id}")]
 async fn user_delete(
+    req: HttpRequest,
+    state: Data<AppState>,
     id: web::Path<i64>,
     user_reg: Data<UserRepository>,
+    audit_rep: Data<AuditLogRepository>,
 ) -> Result<impl Responder, AppError> {
-    user_reg.dao.del_by_id(*id).await?;
+    let mut audit = AuditEntry::new(&req, AuditAction::UserDelete);
+    audit.target(*id, "");
+    let outcome = async {
+        let operator = check_admin(&state, req).await?;
+        user_reg.dao.del_by_id(*id).await?;
+        revoke_sessions(&state, *id, &operator, None).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
 
@@ -42,14 +85,45 @@ async fn user_delete(
 pub struct UserNewDto {
     pub user_name: Option<String>,
     pub password: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
 }
 
 
 #[post("/user/save")]
 async fn user_new(
+    req: HttpRequest,
+    state: Data<AppState>,
     user_rep: Data<UserRepository>,
+    password_policy: Data<PasswordPolicy>,
+    audit_rep: Data<AuditLogRepository>,
     user: web::Json<UserNewDto>,
 ) -> Result<impl Responder, AppError> {
+    let mut audit = AuditEntry::new(&req, AuditAction::UserNew);
+    let user_id = build_snow_id();
+    audit.target(user_id, user.user_name.as_deref().unwrap_or_default());
+    let outcome = async {
+        check_admin(&state, req).await?;
+        save_user(user_id, &user_rep, &password_policy, &user).await
+    }.await;
+    match &outcome {
+        Ok(Some(msg)) => audit.outcome::<()>(&Err(AppError::BizError(msg.clone()))),
+        _ => audit.outcome(&outcome),
+    };
+    audit_rep.record(&audit).await;
+    match outcome? {
+        Some(msg) => Ok(web::Json(result_warn_msg(msg.as_str()))),
+        None => Ok(web::Json(result())),
+    }
+}
+
+/// 用户名重复时返回提示
+async fn save_user(
+    user_id: i64,
+    user_rep: &UserRepository,
+    password_policy: &PasswordPolicy,
+    user: &UserNewDto,
+) -> Result<Option<String>, AppError> {
     let mut params: HashMap<&str, String> = HashMap::new();
     match &user.user_name {
         Some(user_name) => {
@@ -61,7 +135,8 @@ async fn user_new(
     }
     match &user.password {
         Some(password) => {
-            params.insert("password", build_md5(password).to_string());
+            password_policy.check(password)?;
+            params.insert("password", hash_password(password)?);
         }
         None => {
             return Err(AppError::BizError("password.is.null".to_string()));
@@ -69,52 +144,24 @@ async fn user_new(
     }
     params.insert("access_key", build_id());
     params.insert("secret_key", build_id());
-    params.insert("is_admin", "1".to_owned());
+    params.insert("is_admin", if user.is_admin { "1" } else { "0" }.to_owned());
     let now = Local::now();
     params.insert("create_time", now.format("%Y-%m-%d %H:%M:%S").to_string());
-    params.insert("id", build_snow_id().to_string());
+    params.insert("id", user_id.to_string());
     match user_rep.dao.insert(params).await {
-        Ok(_) => {
-            return Ok(web::Json(result()));
+        Ok(_) => Ok(None),
+        Err(AppError::DBError(sqlx::Error::Database(db_err))) if db_err.is_unique_violation() => {
+            let user_name = match &user.user_name {
+                Some(user_name) => user_name,
+                _ => "",
+            };
+            let message = format!("用户已存在{}", user_name);
+            Ok(Some(message))
         }
-        Err(e) => match AppError::from(e) {
-            AppError::DBError(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => {
-                let user_name = match &user.user_name {
-                    Some(user_name) => user_name,
-                    _ => "",
-                };
-                let message = format!("用户已存在{}", user_name);
-                return Ok(web::Json(result_warn_msg(message.as_str())));
-            }
-            error => return Err(error),
-        },
+        Err(error) => Err(error),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, FromRow, Default)]
-pub struct UserChangeKey {
-    pub user_name: String,
-}
-
-#[post("/user/change/key/{user_name}")]
-async fn user_change_key(
-    user_rep: web::Data<UserRepository>,
-    user_name: web::Path<String>,
-) -> Result<impl Responder, AppError> {
-    match user_rep.find_by_name((&*user_name).to_string()).await {
-        Ok(info) => {
-            let mut params: HashMap<&str, String> = HashMap::new();
-            params.insert("access_key", build_id());
-            params.insert("secret_key", build_id());
-            user_rep.dao.change(info.id, params).await?;
-        }
-        Err(e) => {
-            return Err(e);
-        }
-    }
-
-    Ok(web::Json(result()))
-}
 #[derive(Debug, Serialize, Deserialize, FromRow, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct UserChangePass {
@@ -123,35 +170,46 @@ pub struct UserChangePass {
     pub new_password: Option<String>,
 }
 
+/// 旧密码的校验与登录共用失败计数，防止借已登录的会话猜测密码
 #[post("/user/change/password")]
 async fn user_change_password(
+    req: HttpRequest,
+    state: Data<AppState>,
     user_rep: web::Data<UserRepository>,
+    password_policy: Data<PasswordPolicy>,
+    login_guard: Data<LoginGuard>,
+    audit_rep: Data<AuditLogRepository>,
     user: web::Json<UserChangePass>,
 ) -> Result<impl Responder, AppError> {
-    match user_rep.find_by_name(user.user_name.to_string()).await {
-        Ok(info) => {
-            let old_password = match &user.old_password {
-                Some(old_password) => old_password,
-                _ => return Err(AppError::BizError("old_password.is.null".to_string())),
-            };
-            if build_md5(old_password) != info.password {
-                return Err(AppError::BizError(
-                    "old_password.is.not.correct".to_string(),
-                ));
-            }
-            let new_password = match &user.new_password {
-                Some(new_password) => build_md5(new_password),
-                _ => return Err(AppError::BizError("new_password.is.null".to_string())),
-            };
-            let mut params: HashMap<&str, String> = HashMap::new();
-            params.insert("password", new_password);
-            user_rep.dao.change(info.id, params).await?;
-        }
-        Err(e) => {
-            return Err(e);
+    let client_ip = client_ip(&req);
+    let token = bearer_token(&req);
+    let mut audit = AuditEntry::new(&req, AuditAction::UserChangePassword);
+    audit.target(0, &user.user_name);
+    let outcome: Result<(), AppError> = async {
+        let operator = check_self_or_admin(&state, req, &user.user_name).await?;
+        let info = user_rep.find_by_name(user.user_name.to_string()).await?;
+        audit.target(info.id, &info.user_name);
+        let old_password = match &user.old_password {
+            Some(old_password) => old_password,
+            _ => return Err(AppError::BizError("old_password.is.null".to_string())),
+        };
+        login_guard.attempt(&info.user_name, &client_ip).await?;
+        if !verify_password(old_password, &info.password).is_match() {
+            return Err(AppError::BizError(
+                "old_password.is.not.correct".to_string(),
+            ));
         }
-    }
-
+        login_guard.succeeded(&info.user_name, &client_ip).await?;
+        let new_password = match &user.new_password {
+            Some(new_password) => new_password,
+            _ => return Err(AppError::BizError("new_password.is.null".to_string())),
+        };
+        password_policy.check(new_password)?;
+        user_rep.change_password(info.id, &hash_password(new_password)?).await?;
+        revoke_sessions(&state, info.id, &operator, token.as_deref()).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
 #[derive(Debug, Serialize, Deserialize, FromRow, Default)]
@@ -163,34 +221,30 @@ pub struct UserUpPass {
 
 #[post("/user/up/password")]
 async fn user_up_password(
+    req: HttpRequest,
+    state: Data<AppState>,
     user_rep: web::Data<UserRepository>,
+    password_policy: Data<PasswordPolicy>,
+    audit_rep: Data<AuditLogRepository>,
     user: web::Json<UserUpPass>,
 ) -> Result<impl Responder, AppError> {
-    match user_rep.find_by_name(user.user_name.to_string()).await {
-        Ok(info) => {
-            let new_password = match &user.password {
-                Some(new_password) => build_md5(new_password),
-                _ => return Err(AppError::BizError("new_password.is.null".to_string())),
-            };
-            let mut params: HashMap<&str, String> = HashMap::new();
-            params.insert("password", new_password);
-            user_rep.dao.change(info.id, params).await?;
-        }
-        Err(e) => {
-            return Err(e);
-        }
-    }
-
+    let token = bearer_token(&req);
+    let mut audit = AuditEntry::new(&req, AuditAction::UserChangePassword);
+    audit.target(0, &user.user_name);
+    let outcome: Result<(), AppError> = async {
+        let operator = check_admin(&state, req).await?;
+        let info = user_rep.find_by_name(user.user_name.to_string()).await?;
+        audit.target(info.id, &info.user_name);
+        let new_password = match &user.password {
+            Some(new_password) => new_password,
+            _ => return Err(AppError::BizError("new_password.is.null".to_string())),
+        };
+        password_policy.check(new_password)?;
+        user_rep.change_password(info.id, &hash_password(new_password)?).await?;
+        // 重置密码后被重置的用户需要重新登录
+        revoke_sessions(&state, info.id, &operator, token.as_deref()).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
-
-
-#[post("/user/view/{user_name}")]
-async fn user_view_key(
-    user_rep: web::Data<UserRepository>,
-    user_name: web::Path<String>,
-) -> Result<impl Responder, AppError> {
-    let user_info = user_rep.find_by_name(user_name.clone()).await?;
-    let result = serde_json::json!({ "accessKey": user_info.access_key,"secretKey": user_info.secret_key});
-    return Ok(web::Json(result_data(result)));
-}
@@ -1,31 +1,37 @@
 use actix_web::web::Data;
-use actix_web::{post, web, Responder};
-use common::{result, result_list, AppError, AppState};
-use model::{Repository, UserBucketRepository};
-use serde::Deserialize;
-use validator::Validate;
+use actix_web::{post, web, HttpRequest, Responder};
+use common::{get_session_user, result, AppError, AppState};
+use model::{Action, AuditAction, AuditEntry, AuditLogRepository, AuthService, Repository, UserBucketRepository};
 
 pub fn configure(cfg: &mut web::ServiceConfig, state: Data<AppState>) {
     cfg.app_data(state.clone());
-
+    cfg.service(user_bucket_delete);
 }
 
-
-
-
-
-
-
+/// 删除用户在 bucket 上的授权，需要该 bucket 的管理权限
 #[post("/user/bucket/delete/{id}")]
 async fn user_bucket_delete(
+    req: HttpRequest,
+    state: Data<AppState>,
     id: web::Path<i64>,
+    auth_service: Data<AuthService>,
     user_reg: Data<UserBucketRepository>,
+    audit_rep: Data<AuditLogRepository>,
 ) -> Result<impl Responder, AppError> {
-    user_reg.dao.del_by_id(*id).await?;
+    let mut audit = AuditEntry::new(&req, AuditAction::RightDelete);
+    audit.target(*id, "");
+    let outcome: Result<u64, AppError> = async {
+        let user = get_session_user(&state, req).await?;
+        let right = match user_reg.dao.find_by_id(*id).await {
+            Ok(right) => right,
+            Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("right.not.found".to_owned())),
+            Err(e) => return Err(e),
+        };
+        audit.bucket(right.bucket_id).target(right.user_id, "");
+        auth_service.authorize_id(Some((&user).into()), right.bucket_id, Action::Admin).await?;
+        user_reg.dao.del_by_id(right.id).await
+    }.await;
+    audit_rep.record(audit.outcome(&outcome)).await;
+    outcome?;
     Ok(web::Json(result()))
 }
-
-
-
-
-
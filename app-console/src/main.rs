@@ -1,27 +1,37 @@
 mod handlers;
 
-use actix_session::config::PersistentSession;
 // use app_api::ApiDoc;
-use actix_session::SessionMiddleware;
-use actix_web::cookie::Key;
 use actix_web::middleware::Logger;
-use actix_web::{cookie, web, App, HttpServer};
+use actix_web::{web, App, HttpServer};
 use app_console::AuthMiddleware;
-use common::{AppState, UserCache};
+use clap::Parser;
+use common::{build_session_store, AppState, Cli, Command, LocalStorage, LoginGuard};
 use log::info;
 use model::db;
 use moka::future::Cache;
 use std::sync::Arc;
 use std::time::Duration;
-use uuid::Uuid;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     // 读取配置文件
     // 初始化数据库连接池
+    let cli = Cli::parse();
     let config = AppState::from_env();
     let mut log_builder = AppState::build_log(&config);
     log_builder.init();
+    let pool = Arc::new(db::get_conn(&config.database.url).await);
+    if let Some(Command::Migrate { action }) = cli.command {
+        db::run_migrate(&pool, action, &config.migrate, &config.password)
+            .await
+            .expect("Failed to migrate database");
+        return Ok(());
+    }
+    if config.migrate.auto {
+        db::migrate_up(&pool, &config.migrate, &config.password)
+            .await
+            .expect("Failed to migrate database");
+    }
     //文件夹缓存
     let dir_create_cache: Arc<Cache<String, String>> = Arc::new(
         Cache::builder()
@@ -29,35 +39,50 @@ async fn main() -> std::io::Result<()> {
             .max_capacity(1000) // 最大存储 1000 个键值
             .build(),
     );
-    //用户 session
-    let session_cache: Arc<Cache<String, UserCache>> = Arc::new(
-        Cache::builder()
-            .time_to_live(Duration::from_secs(60 * 60 * 24)) // 设置 TTL 60 秒
-            .max_capacity(1000) // 最大存储 1000 个键值
-            .build(),
-    );
+    //用户 session，按 [session] 配置使用内存或 redis
+    let session_store = build_session_store(&config.session)
+        .await
+        .expect("Failed to create session store");
     //数据库缓存
     let db_cache: Arc<Cache<String, String>> = Arc::new(
         Cache::builder()
             .time_to_live(Duration::from_secs(60 * 60 * 24)) // 设置 TTL 60 秒
             .max_capacity(1000) // 最大存储 1000 个键值
+            //删除目录时按前缀失效子目录
+            .support_invalidation_closures()
             .build(),
     );
 
     let app_status = AppState {
         root_path: config.server.root_path.clone(),
-        dir_create_cache,
+        dir_create_cache: dir_create_cache.clone(),
         db_path_cache: db_cache,
-        session_cache,
+        storage: Arc::new(LocalStorage::new(&config.server.root_path, dir_create_cache)),
+        session_store,
     };
     let address_and_port = format!("{}:{}", &config.server.host, &config.server.port);
     info!("Starting server on {}", address_and_port);
     let data = web::Data::new(app_status.clone());
-    let pool = Arc::new(db::get_conn(&config.database.url).await);
+    let password_policy = web::Data::new(config.password.clone());
+    let sign_config = web::Data::new(config.sign.clone());
+    //登录失败计数与会话使用同一存储
+    let login_guard = web::Data::new(LoginGuard::new(app_status.session_store.clone(), config.login.clone()));
+    //client_ip 按其中的 trusted_proxies 取客户端地址
+    let login_config = web::Data::new(config.login.clone());
+    //要求管理员启用两步验证时必须配置加密密钥，否则管理员无法登录
+    if config.totp.require_admin {
+        config.totp.cipher().expect("[totp] encrypt_key is required when require_admin is enabled");
+    }
+    let totp_config = web::Data::new(config.totp.clone());
     HttpServer::new(move || {
         App::new()
+            .wrap(AuthMiddleware { state: data.clone() })
             .wrap(Logger::default())
-            // .wrap(AuthMiddleware { state: data.clone() })
+            .app_data(password_policy.clone())
+            .app_data(sign_config.clone())
+            .app_data(login_guard.clone())
+            .app_data(login_config.clone())
+            .app_data(totp_config.clone())
             //配置 orm
             .configure(|cfg| {
                 model::db::configure(cfg,pool.clone())
@@ -5,11 +5,14 @@ use actix_web::{
     HttpMessage,
     HttpResponse,
 };
-use common::{result_error_msg, AppState};
+use common::{bearer_token, result_error_msg, AppState};
 use futures_util::future::{ok, LocalBoxFuture, Ready};
+use log::error;
 use std::rc::Rc;
 use std::task::{Context, Poll};
-//
+
+//不需要登录的路径前缀；下载与缩略图在 handler 内按 bucket 是否公开读校验
+const PUBLIC_PATHS: [&str; 5] = ["/auth/login", "/status", "/download/", "/thumbnail/", "/swagger-ui"];
 
 /// Authentication Middleware
 pub struct AuthMiddleware {
@@ -55,59 +58,40 @@ where
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let srv = Rc::clone(&self.service); // ✅ Correct way to clone the service
         let state = self.state.clone();
-        let url = req.uri();
-        if 1 == 1 {
-           return Box::pin(async move {
-                let res = srv.call(req).await?;
-                let res = res.map_body(|_, body| EitherBody::new(body));
-                return Ok(res);
-            });
-        }
-        match url {
-            _ if url.path().starts_with("/swagger-ui") => Box::pin(async move {
-                let res = srv.call(req).await?;
-                let res = res.map_body(|_, body| EitherBody::new(body));
-                return Ok(res);
-            }),
-            _ if url.path().starts_with("/auth") => Box::pin(async move {
-                let res = srv.call(req).await?;
-                let res = res.map_body(|_, body| EitherBody::new(body));
-                return Ok(res);
-            }),
-            _ if url.path().starts_with("/api-doc/openapi") => Box::pin(async move {
+        Box::pin(async move {
+            let path = req.path();
+            if PUBLIC_PATHS.iter().any(|prefix| path.starts_with(prefix)) {
                 let res = srv.call(req).await?;
-                let res = res.map_body(|_, body| EitherBody::new(body));
-                return Ok(res);
-            }),
-
-            _ => Box::pin(async move {
-                let auth_header = req.headers().get("Authorization");
-                if let Some(auth_value) = auth_header {
-                    if let Ok(auth_str) = auth_value.to_str() {
-                        if auth_str.starts_with("Bearer ") {
-                            let token_key = &auth_str[9..];
-                            let token_option = state.session_cache.get(token_key).await;
-                            if let Some(token_value) = token_option {
-                                state.session_cache.get_with(token_key.to_string(), async { return token_value; }).await;
-                                let res = srv.call(req).await?;
-                                let res = res.map_body(|_, body| EitherBody::new(body));
-                                return Ok(res);
-                            } else {
-                                return Ok(req.into_response(
-                                    HttpResponse::Unauthorized()
-                                        .json(result_error_msg("Unauthorized"))
-                                        .map_into_right_body(),
-                                ));
-                            }
-                        }
-                    }
+                return Ok(res.map_into_left_body());
+            }
+            let token = match bearer_token(req.request()) {
+                Some(token) => token,
+                None => return Ok(unauthorized(req)),
+            };
+            // 命中时会话过期时间顺延，已吊销的会话视为未登录
+            let user_cache = match state.session_store.find(&token).await {
+                Ok(Some(user_cache)) => user_cache,
+                Ok(None) => return Ok(unauthorized(req)),
+                Err(e) => {
+                    error!("session store error: {}", e);
+                    return Ok(req.into_response(
+                        HttpResponse::ServiceUnavailable()
+                            .json(result_error_msg("session.unavailable"))
+                            .map_into_right_body(),
+                    ));
                 }
-                return Ok(req.into_response(
-                    HttpResponse::Unauthorized()
-                        .json(result_error_msg("Unauthorized"))
-                        .map_into_right_body(),
-                ));
-            }),
-        }
+            };
+            req.extensions_mut().insert(user_cache);
+            let res = srv.call(req).await?;
+            Ok(res.map_into_left_body())
+        })
     }
 }
+
+fn unauthorized<B>(req: ServiceRequest) -> ServiceResponse<EitherBody<B>> {
+    req.into_response(
+        HttpResponse::Unauthorized()
+            .json(result_error_msg("Unauthorized"))
+            .map_into_right_body(),
+    )
+}
@@ -1 +1,38 @@
+pub mod scheduler;
+pub mod path_del_job;
 pub mod thumbnail_job;
+pub mod upload_cleanup_job;
+
+pub use path_del_job::*;
+pub use scheduler::*;
+pub use thumbnail_job::*;
+pub use upload_cleanup_job::*;
+
+use async_trait::async_trait;
+use common::{AppError, JobConfig, StorageBackend};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// 后台任务，由 [`Scheduler`] 按 `file-cloud.toml` 中 `[jobs.<name>]` 的配置定时执行
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// 任务名，对应配置中的 key
+    fn name(&self) -> &'static str;
+
+    async fn run(&self, ctx: &JobContext) -> Result<(), AppError>;
+}
+
+/// 单次执行的上下文
+pub struct JobContext {
+    pub run_id: u64,
+    pub config: JobConfig,
+    pub storage: Arc<dyn StorageBackend>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl JobContext {
+    /// 收到停止信号后，任务应在处理完当前记录后尽快返回
+    pub fn is_shutdown(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::job::{Job, JobContext};
+use async_trait::async_trait;
+use common::AppError;
+use log::{error, info};
+use model::{ChunkRepository, FileRepository, PathDelTask, PathDelTaskRepository, PathRepository, Repository};
+use sqlx::MySqlPool;
+use std::sync::Arc;
+
+//任务占用时长（秒），每处理一批续期一次，worker 异常退出后由其他 worker 接手
+const LEASE_SECS: i64 = 600;
+
+/// 处理删除目录任务：删除子目录下的全部文件（释放分片、归还配额）和子目录记录
+///
+/// 每一步都可重复执行，中断后从未完成的状态继续；多个 worker 通过 `lock_time` 占用任务
+pub struct PathDelJob {
+    path_del_task_rep: PathDelTaskRepository,
+    path_rep: PathRepository,
+    file_rep: FileRepository,
+    chunk_rep: ChunkRepository,
+}
+
+impl PathDelJob {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            path_del_task_rep: PathDelTaskRepository::new(pool.clone()),
+            path_rep: PathRepository::new(pool.clone()),
+            file_rep: FileRepository::new(pool.clone()),
+            chunk_rep: ChunkRepository::new(pool),
+        }
+    }
+
+    /// 返回任务是否处理完成，收到停止信号时返回 false
+    async fn process(&self, ctx: &JobContext, task: &PathDelTask) -> Result<bool, AppError> {
+        let batch_size = ctx.config.batch_size;
+        if !task.del_file_status {
+            loop {
+                if ctx.is_shutdown() {
+                    return Ok(false);
+                }
+                let files = self.file_rep.list_under_path(task.bucket_id, task.path_id, batch_size).await?;
+                for file in files.iter() {
+                    // 只有删除记录成功的一方释放分片，避免重复释放
+                    if self.file_rep.delete_file(file).await?
+                        && let Err(e) = self.chunk_rep.release_items(&*ctx.storage, &file.stored_items()).await
+                    {
+                        error!("job=path_del task={} release file {} chunks error: {}", task.id, file.id, e);
+                    }
+                }
+                if (files.len() as i64) < batch_size {
+                    break;
+                }
+                self.path_del_task_rep.renew(task.id).await?;
+            }
+            self.path_del_task_rep.change_status(task.id, true, false).await?;
+        }
+        loop {
+            if ctx.is_shutdown() {
+                return Ok(false);
+            }
+            let count = self.path_rep.del_children(task.bucket_id, task.path_id, batch_size).await?;
+            if (count as i64) < batch_size {
+                break;
+            }
+            self.path_del_task_rep.renew(task.id).await?;
+        }
+        self.path_rep.dao.del_by_id(task.path_id).await?;
+        self.path_del_task_rep.change_status(task.id, true, true).await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Job for PathDelJob {
+    fn name(&self) -> &'static str {
+        "path_del"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<(), AppError> {
+        let tasks = self.path_del_task_rep.list_pending(ctx.config.batch_size).await?;
+        for task in tasks.iter() {
+            if ctx.is_shutdown() {
+                break;
+            }
+            if !self.path_del_task_rep.lock(task.id, LEASE_SECS).await? {
+                continue;
+            }
+            let done = match self.process(ctx, task).await {
+                Ok(done) => done,
+                Err(e) => {
+                    error!("job=path_del run={} task={} error: {}", ctx.run_id, task.id, e);
+                    false
+                }
+            };
+            match done {
+                true => info!("job=path_del run={} task={} path={} deleted", ctx.run_id, task.id, task.full_path),
+                // 未完成时释放占用，下次执行继续处理
+                false => self.path_del_task_rep.unlock(task.id).await?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use crate::job::{Job, JobContext};
+use common::{JobConfig, StorageBackend};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+
+//停止时等待执行中任务的最长时间
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Scheduler {
+    configs: HashMap<String, JobConfig>,
+    storage: Arc<dyn StorageBackend>,
+    jobs: Vec<Arc<dyn Job>>,
+}
+
+impl Scheduler {
+    pub fn new(configs: HashMap<String, JobConfig>, storage: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            configs,
+            storage,
+            jobs: Vec::new(),
+        }
+    }
+
+    pub fn register(&mut self, job: Arc<dyn Job>) {
+        self.jobs.push(job);
+    }
+
+    /// 启动所有已配置且启用的任务，收到停止信号并等待执行中的任务结束后返回
+    pub async fn run(self, shutdown: watch::Receiver<bool>) {
+        for name in self.configs.keys() {
+            if !self.jobs.iter().any(|job| job.name() == name) {
+                warn!("job={} configured but not registered", name);
+            }
+        }
+        let mut set = JoinSet::new();
+        for job in self.jobs {
+            let config = match self.configs.get(job.name()) {
+                Some(config) if config.enabled => config.clone(),
+                Some(_) => {
+                    info!("job={} disabled", job.name());
+                    continue;
+                }
+                None => {
+                    info!("job={} not configured, skipped", job.name());
+                    continue;
+                }
+            };
+            info!("job={} scheduled interval={}s concurrency={}", job.name(), config.interval, config.concurrency);
+            set.spawn(schedule(job, config, self.storage.clone(), shutdown.clone()));
+        }
+        while let Some(result) = set.join_next().await {
+            if let Err(e) = result {
+                error!("job scheduler task error: {}", e);
+            }
+        }
+        info!("all jobs stopped");
+    }
+}
+
+/// 单个任务的调度循环，并发数由信号量限制，达到上限时跳过本次执行
+async fn schedule(job: Arc<dyn Job>, config: JobConfig, storage: Arc<dyn StorageBackend>, mut shutdown: watch::Receiver<bool>) {
+    let semaphore = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut running = JoinSet::new();
+    let mut run_id: u64 = 0;
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = interval.tick() => {}
+        }
+        if *shutdown.borrow() {
+            break;
+        }
+        while let Some(result) = running.try_join_next() {
+            if let Err(e) = result {
+                error!("job={} panicked: {}", job.name(), e);
+            }
+        }
+        let permit = match semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                debug!("job={} skipped, concurrency limit {} reached", job.name(), config.concurrency);
+                continue;
+            }
+        };
+        run_id += 1;
+        let ctx = JobContext {
+            run_id,
+            config: config.clone(),
+            storage: storage.clone(),
+            shutdown: shutdown.clone(),
+        };
+        let job = job.clone();
+        running.spawn(async move {
+            let start = Instant::now();
+            debug!("job={} run={} started", job.name(), ctx.run_id);
+            match job.run(&ctx).await {
+                Ok(()) => info!("job={} run={} finished elapsed_ms={}", job.name(), ctx.run_id, start.elapsed().as_millis()),
+                Err(e) => error!("job={} run={} failed elapsed_ms={} error={}", job.name(), ctx.run_id, start.elapsed().as_millis(), e),
+            }
+            drop(permit);
+        });
+    }
+    if !running.is_empty() {
+        info!("job={} stopping, waiting for {} running", job.name(), running.len());
+    }
+    let wait = async {
+        while let Some(result) = running.join_next().await {
+            if let Err(e) = result {
+                error!("job={} panicked: {}", job.name(), e);
+            }
+        }
+    };
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, wait).await.is_err() {
+        warn!("job={} did not stop in {}s, aborted", job.name(), SHUTDOWN_TIMEOUT.as_secs());
+        running.abort_all();
+    }
+}
@@ -1,71 +1,145 @@
-// use common::AppError;
-// use image::ImageReader;
-// use model::{FileInfoTem, FileType};
-// use sqlx::MySqlPool;
-// use std::fs::File;
-// use std::io::{Cursor, Read, Write};
-// use std::path::Path;
-// use uuid::Uuid;
-// use webp::Encoder;
-//
-// const CHUNK_SIZE: usize = 1 * 100 * 1024;
-//
-// async fn do_task(conn: &MySqlPool) -> Result<(), AppError> {
-//     let file_list = query_thumbnail_file(conn, FileType::IMAGE).await?;
-//     for file_item in file_list {
-//         let mut buffer = Vec::with_capacity(file_item.size as usize);
-//         let file_split: Vec<&str> = file_item.items.split(",").collect();
-//         let mut file_dir = None;
-//         for item in file_split {
-//             let mut file = File::open(item)?;
-//             file.read_to_end(&mut buffer)?;
-//             if file_dir.is_none() {
-//                 let parent_dir = Path::new(item).parent().unwrap();
-//                 let x = parent_dir.to_str().unwrap();
-//                 file_dir = Some(x);
-//             }
-//         }
-//         let img = ImageReader::new(Cursor::new(buffer))
-//             .with_guessed_format()
-//             .unwrap() // 自动识别图片格式
-//             .decode()
-//             .unwrap();
-//         let encoder = Encoder::from_image(&img).unwrap();
-//         let webp_data = encoder.encode(75.0); // 75% 质量
-//         let fid = Uuid::new_v4();
-//         let thumbnail_path = format!("{}/{}", file_dir.unwrap(), fid.clone().to_string());
-//         let mut file = File::create(&thumbnail_path).unwrap();
-//         file.write_all(&webp_data)?;
-//         change_thumbnail_file(conn,&file_item.id,&thumbnail_path).await?;
-//     }
-//     Ok(())
-// }
-//
-// async fn query_thumbnail_file(
-//     conn: &MySqlPool,
-//     file_type: FileType,
-// ) -> Result<Vec<FileInfoTem>, AppError> {
-//     let list_db = sqlx::query_as::<_, FileInfoTem>(
-//         "SELECT id,file_name, file_type, image_type, items, thumbnail, size, thumbnail_status FROM file_info where thumbnail_status=? and file_type=?"
-//     )
-//         .bind(true)
-//         .bind(file_type)
-//         // ✅ 绑定参数
-//         .fetch_all(conn)
-//         .await.unwrap();
-//     Ok(list_db)
-// }
-//
-// async fn change_thumbnail_file(
-//     conn: &MySqlPool,
-//     id: &String,
-//     thumbnail_path: &String,
-// ) -> Result<(), AppError> {
-//     sqlx::query("update file_info set thumbnail_status=0,thumbnail=? where id=?")
-//         .bind(thumbnail_path)
-//         .bind(id)
-//         .execute(conn)
-//         .await
-//         .unwrap();
-//     Ok(())
-// }
+use crate::job::{Job, JobContext};
+use async_trait::async_trait;
+use common::{read_chunk, AppError, ThumbnailConfig};
+use image::{DynamicImage, GenericImageView, ImageReader};
+use log::{error, info, warn};
+use model::{ChunkRepository, FileInfo, FileRepository, FileThumbnail};
+use sqlx::MySqlPool;
+use std::io::Cursor;
+use std::sync::Arc;
+use webp::Encoder;
+
+/// 为图片生成 WebP 缩略图：读取全部分片还原原图，按配置的尺寸缩放后写入分片库
+///
+/// 解码失败的图片标记为失败不再重试；保存时文件已被其他 worker 处理则释放本次写入的分片
+pub struct ThumbnailJob {
+    config: ThumbnailConfig,
+    file_rep: FileRepository,
+    chunk_rep: ChunkRepository,
+}
+
+impl ThumbnailJob {
+    pub fn new(pool: Arc<MySqlPool>, config: ThumbnailConfig) -> Self {
+        Self {
+            config,
+            file_rep: FileRepository::new(pool.clone()),
+            chunk_rep: ChunkRepository::new(pool),
+        }
+    }
+
+    async fn process(&self, ctx: &JobContext, file: &FileInfo) -> Result<(), AppError> {
+        if file.size as u64 > self.config.max_size {
+            info!("job=thumbnail run={} file={} size={} too large, skipped", ctx.run_id, file.id, file.size);
+            self.file_rep.save_thumbnails(file.id, FileInfo::THUMBNAIL_SKIPPED, &vec![]).await?;
+            return Ok(());
+        }
+        let mut buffer = Vec::with_capacity(file.size as usize);
+        for chunk in file.chunk_refs() {
+            buffer.extend_from_slice(&read_chunk(&*ctx.storage, &chunk, 0, chunk.size).await?);
+        }
+        // 解码与缩放耗 CPU，放到阻塞线程池
+        let sizes = self.config.sizes.clone();
+        let quality = self.config.quality;
+        let encoded = tokio::task::spawn_blocking(move || build_thumbnails(&buffer, &sizes, quality))
+            .await
+            .map_err(|e| AppError::InternalError(e.to_string()))?;
+        let encoded = match encoded {
+            Ok(encoded) => encoded,
+            Err(e) => {
+                warn!("job=thumbnail run={} file={} decode error: {}", ctx.run_id, file.id, e);
+                self.file_rep.save_thumbnails(file.id, FileInfo::THUMBNAIL_FAILED, &vec![]).await?;
+                return Ok(());
+            }
+        };
+        let mut thumbnails: Vec<FileThumbnail> = Vec::new();
+        for (size, width, height, data) in encoded {
+            // AppError 不是 Send，不能跨 await 持有，先转为字符串
+            match self.chunk_rep.save_chunk(&*ctx.storage, &data).await.map_err(|e| e.to_string()) {
+                Ok(item) => thumbnails.push(FileThumbnail { size, width, height, item }),
+                Err(message) => {
+                    self.release(ctx, &thumbnails).await;
+                    return Err(AppError::InternalError(message));
+                }
+            }
+        }
+        match self.file_rep.save_thumbnails(file.id, FileInfo::THUMBNAIL_DONE, &thumbnails).await.map_err(|e| e.to_string()) {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                self.release(ctx, &thumbnails).await;
+                Ok(())
+            }
+            Err(message) => {
+                self.release(ctx, &thumbnails).await;
+                Err(AppError::InternalError(message))
+            }
+        }
+    }
+
+    async fn release(&self, ctx: &JobContext, thumbnails: &[FileThumbnail]) {
+        let items: Vec<_> = thumbnails.iter().map(|thumbnail| thumbnail.item.clone()).collect();
+        if let Err(e) = self.chunk_rep.release_items(&*ctx.storage, &items).await {
+            error!("job=thumbnail run={} release thumbnail chunks error: {}", ctx.run_id, e);
+        }
+    }
+}
+
+/// (配置尺寸, 宽, 高, WebP 数据)
+type Thumbnail = (u32, u32, u32, Vec<u8>);
+
+/// 生成各尺寸的缩略图，小于目标尺寸的图片保持原尺寸
+///
+/// 在阻塞线程池中执行，错误用 String 返回（AppError 不是 Send）
+fn build_thumbnails(data: &[u8], sizes: &[u32], quality: f32) -> Result<Vec<Thumbnail>, String> {
+    let img = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map_err(|e| e.to_string())?;
+    let mut result = Vec::with_capacity(sizes.len());
+    for &size in sizes {
+        if size == 0 {
+            continue;
+        }
+        let (width, height) = img.dimensions();
+        let resized = match width > size || height > size {
+            true => img.thumbnail(size, size),
+            false => img.clone(),
+        };
+        // WebP 编码只支持 RGB8 / RGBA8
+        let resized = match resized.color().has_alpha() {
+            true => DynamicImage::ImageRgba8(resized.to_rgba8()),
+            false => DynamicImage::ImageRgb8(resized.to_rgb8()),
+        };
+        let encoder = Encoder::from_image(&resized).map_err(|e| e.to_string())?;
+        let webp_data = encoder.encode(quality);
+        result.push((size, resized.width(), resized.height(), webp_data.to_vec()));
+    }
+    Ok(result)
+}
+
+#[async_trait]
+impl Job for ThumbnailJob {
+    fn name(&self) -> &'static str {
+        "thumbnail"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<(), AppError> {
+        let mut max_id: i64 = 0;
+        loop {
+            let list = self.file_rep.list_thumbnail_pending(max_id, ctx.config.batch_size).await?;
+            for file in list.iter() {
+                if ctx.is_shutdown() {
+                    return Ok(());
+                }
+                max_id = file.id;
+                // 读取分片等临时错误保持待生成状态，下次执行重试
+                if let Err(e) = self.process(ctx, file).await {
+                    error!("job=thumbnail run={} file={} error: {}", ctx.run_id, file.id, e);
+                }
+            }
+            if (list.len() as i64) < ctx.config.batch_size {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::job::{Job, JobContext};
+use async_trait::async_trait;
+use chrono::{Duration, Local};
+use common::AppError;
+use log::info;
+use model::{BucketRepository, ChunkRepository, UploadPartRepository, UploadSession, UploadSessionRepository};
+use sqlx::MySqlPool;
+use std::sync::Arc;
+
+//断点续传会话的有效期（小时）
+const EXPIRE_HOURS: i64 = 24;
+
+/// 取消超时未完成的断点续传，释放已上传的分片并归还配额
+pub struct UploadCleanupJob {
+    upload_session_rep: UploadSessionRepository,
+    upload_part_rep: UploadPartRepository,
+    bucket_rep: BucketRepository,
+    chunk_rep: ChunkRepository,
+}
+
+impl UploadCleanupJob {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            upload_session_rep: UploadSessionRepository::new(pool.clone()),
+            upload_part_rep: UploadPartRepository::new(pool.clone()),
+            bucket_rep: BucketRepository::new(pool.clone()),
+            chunk_rep: ChunkRepository::new(pool),
+        }
+    }
+
+    async fn clean_session(&self, ctx: &JobContext, session: &UploadSession) -> Result<(), AppError> {
+        if session.status == UploadSession::UPLOADING && !self.upload_session_rep.close(session.id, UploadSession::ABORTED).await? {
+            return Ok(());
+        }
+        // 逐个删除分片记录后再释放，中断后下次执行继续处理剩余分片
+        let parts = self.upload_part_rep.list_parts(session.id).await?;
+        for part in parts {
+            if self.upload_part_rep.del_part(part.id).await? {
+                self.chunk_rep.release_bucket_items(&*ctx.storage, &self.bucket_rep, session.bucket_id, &part.items).await?;
+            }
+        }
+        info!("job=upload_cleanup run={} upload_id={} aborted", ctx.run_id, session.id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Job for UploadCleanupJob {
+    fn name(&self) -> &'static str {
+        "upload_cleanup"
+    }
+
+    async fn run(&self, ctx: &JobContext) -> Result<(), AppError> {
+        let before = (Local::now() - Duration::hours(EXPIRE_HOURS)).format("%Y-%m-%d %H:%M:%S").to_string();
+        loop {
+            let list = self.upload_session_rep.list_expired(&before, ctx.config.batch_size).await?;
+            for session in list.iter() {
+                if ctx.is_shutdown() {
+                    return Ok(());
+                }
+                self.clean_session(ctx, session).await?;
+            }
+            if (list.len() as i64) < ctx.config.batch_size {
+                return Ok(());
+            }
+        }
+    }
+}
@@ -1,5 +1,79 @@
 pub mod job;
 
-fn main() {
-    println!("Hello, world!");
+use clap::Parser;
+use common::{AppState, Cli, Command, LocalStorage, StorageBackend};
+use job::{PathDelJob, Scheduler, ThumbnailJob, UploadCleanupJob};
+use log::{error, info};
+use model::db;
+use moka::future::Cache;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let config = AppState::from_env();
+    let mut log_builder = AppState::build_log(&config);
+    log_builder.init();
+    let pool = Arc::new(db::get_conn(&config.database.url).await);
+    //任务不自动迁移，表结构由 console 或 api 启动时、或 migrate 子命令创建
+    if let Some(Command::Migrate { action }) = cli.command {
+        db::run_migrate(&pool, action, &config.migrate, &config.password)
+            .await
+            .expect("Failed to migrate database");
+        return;
+    }
+    let dir_create_cache: Arc<Cache<String, String>> = Arc::new(
+        Cache::builder()
+            .time_to_live(Duration::from_secs(60 * 60 * 24))
+            .max_capacity(1000)
+            .build(),
+    );
+    let storage: Arc<dyn StorageBackend> = Arc::new(LocalStorage::new(&config.server.root_path, dir_create_cache));
+
+    //注册任务，是否执行及执行间隔由 [jobs.<name>] 配置
+    let mut scheduler = Scheduler::new(config.jobs.clone(), storage);
+    scheduler.register(Arc::new(PathDelJob::new(pool.clone())));
+    scheduler.register(Arc::new(UploadCleanupJob::new(pool.clone())));
+    scheduler.register(Arc::new(ThumbnailJob::new(pool.clone(), config.thumbnail.clone())));
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown signal received");
+        let _ = shutdown_tx.send(true);
+    });
+    info!("app-job started");
+    scheduler.run(shutdown_rx).await;
+    pool.close().await;
+    info!("app-job stopped");
+}
+
+/// Ctrl+C 或 SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("listen ctrl_c error: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("listen SIGTERM error: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
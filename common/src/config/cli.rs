@@ -0,0 +1,31 @@
+use clap::{Parser, Subcommand};
+
+/// 命令行参数，不带子命令时启动服务
+#[derive(Debug, Parser)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 数据库迁移
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Subcommand)]
+pub enum MigrateAction {
+    /// 执行全部未应用的迁移，并按 [migrate] 配置创建管理员
+    Up,
+    /// 回滚迁移，默认只回滚最后一个
+    Down {
+        /// 回滚到该版本，保留该版本及之前的迁移，0 为全部回滚
+        #[arg(long)]
+        target: Option<i64>,
+    },
+    /// 列出迁移及应用状态
+    Status,
+}
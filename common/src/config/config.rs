@@ -1,9 +1,10 @@
-use crate::UserCache;
+use crate::{AppError, SessionStore, StorageBackend, TotpCipher};
 use config::Config;
 use env_logger::Builder;
 use log::LevelFilter;
 use moka::future::Cache;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -12,6 +13,23 @@ pub struct AppConfig {
     pub database: DatabaseConfig,
     pub server: ServerConfig,
     pub logs: LogsConfig,
+    //后台任务，key 为任务名
+    #[serde(default)]
+    pub jobs: HashMap<String, JobConfig>,
+    #[serde(default)]
+    pub thumbnail: ThumbnailConfig,
+    #[serde(default)]
+    pub password: PasswordPolicy,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub login: LoginConfig,
+    #[serde(default)]
+    pub totp: TotpConfig,
+    #[serde(default)]
+    pub sign: SignConfig,
+    #[serde(default)]
+    pub migrate: MigrateConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -34,6 +52,337 @@ pub struct LogsConfig {
     pub trace: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct JobConfig {
+    #[serde(default = "JobConfig::default_enabled")]
+    pub enabled: bool,
+    //执行间隔（秒）
+    pub interval: u64,
+    //同一任务同时执行的最大数量
+    #[serde(default = "JobConfig::default_concurrency")]
+    pub concurrency: usize,
+    //每批处理的记录数
+    #[serde(default = "JobConfig::default_batch_size")]
+    pub batch_size: i64,
+}
+
+impl JobConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+    fn default_concurrency() -> usize {
+        1
+    }
+    fn default_batch_size() -> i64 {
+        100
+    }
+}
+
+/// 缩略图生成参数，由 app-job 的 thumbnail 任务使用
+#[derive(Debug, Deserialize, Clone)]
+pub struct ThumbnailConfig {
+    //生成的尺寸，按最长边（像素），不放大原图
+    #[serde(default = "ThumbnailConfig::default_sizes")]
+    pub sizes: Vec<u32>,
+    //WebP 质量 0-100
+    #[serde(default = "ThumbnailConfig::default_quality")]
+    pub quality: f32,
+    //超过该大小（字节）的图片不生成
+    #[serde(default = "ThumbnailConfig::default_max_size")]
+    pub max_size: u64,
+}
+
+impl ThumbnailConfig {
+    fn default_sizes() -> Vec<u32> {
+        vec![128, 256, 512]
+    }
+    fn default_quality() -> f32 {
+        75.0
+    }
+    fn default_max_size() -> u64 {
+        32 * 1024 * 1024
+    }
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            sizes: Self::default_sizes(),
+            quality: Self::default_quality(),
+            max_size: Self::default_max_size(),
+        }
+    }
+}
+
+/// 密码规则，新建用户与修改密码时校验
+#[derive(Debug, Deserialize, Clone)]
+pub struct PasswordPolicy {
+    #[serde(default = "PasswordPolicy::default_min_length")]
+    pub min_length: usize,
+    #[serde(default = "PasswordPolicy::default_max_length")]
+    pub max_length: usize,
+    //需要包含大写字母
+    #[serde(default)]
+    pub require_upper: bool,
+    //需要包含小写字母
+    #[serde(default = "PasswordPolicy::default_require")]
+    pub require_lower: bool,
+    //需要包含数字
+    #[serde(default = "PasswordPolicy::default_require")]
+    pub require_digit: bool,
+    //需要包含字母和数字以外的字符
+    #[serde(default)]
+    pub require_special: bool,
+}
+
+impl PasswordPolicy {
+    fn default_min_length() -> usize {
+        8
+    }
+    fn default_max_length() -> usize {
+        128
+    }
+    fn default_require() -> bool {
+        true
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), AppError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(AppError::BizError("password.too.short".to_owned()));
+        }
+        if length > self.max_length {
+            return Err(AppError::BizError("password.too.long".to_owned()));
+        }
+        if self.require_upper && !password.chars().any(|c| c.is_uppercase()) {
+            return Err(AppError::BizError("password.require.upper".to_owned()));
+        }
+        if self.require_lower && !password.chars().any(|c| c.is_lowercase()) {
+            return Err(AppError::BizError("password.require.lower".to_owned()));
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            return Err(AppError::BizError("password.require.digit".to_owned()));
+        }
+        if self.require_special && password.chars().all(|c| c.is_alphanumeric()) {
+            return Err(AppError::BizError("password.require.special".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: Self::default_min_length(),
+            max_length: Self::default_max_length(),
+            require_upper: false,
+            require_lower: Self::default_require(),
+            require_digit: Self::default_require(),
+            require_special: false,
+        }
+    }
+}
+
+/// 登录会话
+#[derive(Debug, Deserialize, Clone)]
+pub struct SessionConfig {
+    //memory 或 redis，多实例部署时使用 redis
+    #[serde(default = "SessionConfig::default_store")]
+    pub store: String,
+    #[serde(default)]
+    pub redis_url: String,
+    //无操作后过期时间（秒）
+    #[serde(default = "SessionConfig::default_ttl")]
+    pub ttl: u64,
+    //memory 时最多保存的会话数
+    #[serde(default = "SessionConfig::default_max_capacity")]
+    pub max_capacity: u64,
+}
+
+impl SessionConfig {
+    fn default_store() -> String {
+        "memory".to_owned()
+    }
+    fn default_ttl() -> u64 {
+        60 * 60 * 2
+    }
+    fn default_max_capacity() -> u64 {
+        10000
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            store: Self::default_store(),
+            redis_url: String::new(),
+            ttl: Self::default_ttl(),
+            max_capacity: Self::default_max_capacity(),
+        }
+    }
+}
+
+/// 控制台登录失败锁定
+#[derive(Debug, Deserialize, Clone)]
+pub struct LoginConfig {
+    //同一用户名允许连续失败的次数，超过后锁定
+    #[serde(default = "LoginConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    //同一客户端 IP 允许连续失败的次数，需考虑多人共用出口 IP
+    #[serde(default = "LoginConfig::default_ip_max_attempts")]
+    pub ip_max_attempts: u32,
+    //首次锁定时间（秒），之后每失败一次翻倍
+    #[serde(default = "LoginConfig::default_lock_time")]
+    pub lock_time: u64,
+    //最长锁定时间（秒）
+    #[serde(default = "LoginConfig::default_max_lock_time")]
+    pub max_lock_time: u64,
+    //最后一次失败后多久清零计数（秒）
+    #[serde(default = "LoginConfig::default_reset_time")]
+    pub reset_time: u64,
+    //可信反向代理的地址，只有直连地址在其中时才按 X-Forwarded-For 取客户端 IP
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl LoginConfig {
+    fn default_max_attempts() -> u32 {
+        5
+    }
+    fn default_ip_max_attempts() -> u32 {
+        20
+    }
+    fn default_lock_time() -> u64 {
+        30
+    }
+    fn default_max_lock_time() -> u64 {
+        60 * 60
+    }
+    fn default_reset_time() -> u64 {
+        60 * 60
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: Self::default_max_attempts(),
+            ip_max_attempts: Self::default_ip_max_attempts(),
+            lock_time: Self::default_lock_time(),
+            max_lock_time: Self::default_max_lock_time(),
+            reset_time: Self::default_reset_time(),
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+/// 控制台两步验证
+#[derive(Debug, Deserialize, Clone)]
+pub struct TotpConfig {
+    //验证器中显示的服务名称
+    #[serde(default = "TotpConfig::default_issuer")]
+    pub issuer: String,
+    //加密保存 TOTP 密钥，base64 编码的 32 字节，为空时不能启用两步验证
+    #[serde(default)]
+    pub encrypt_key: String,
+    //管理员必须启用两步验证，未启用的管理员登录时先完成绑定
+    #[serde(default)]
+    pub require_admin: bool,
+    //每次生成的恢复码个数
+    #[serde(default = "TotpConfig::default_recovery_codes")]
+    pub recovery_codes: usize,
+    //输入密码后完成第二步的时限（秒）
+    #[serde(default = "TotpConfig::default_challenge_ttl")]
+    pub challenge_ttl: u64,
+}
+
+impl TotpConfig {
+    fn default_issuer() -> String {
+        "file-cloud".to_owned()
+    }
+    fn default_recovery_codes() -> usize {
+        10
+    }
+    fn default_challenge_ttl() -> u64 {
+        60 * 5
+    }
+
+    pub fn cipher(&self) -> Result<TotpCipher, AppError> {
+        match self.encrypt_key.is_empty() {
+            true => Err(AppError::BizError("totp.not.configured".to_owned())),
+            false => TotpCipher::new(&self.encrypt_key),
+        }
+    }
+}
+
+impl Default for TotpConfig {
+    fn default() -> Self {
+        Self {
+            issuer: Self::default_issuer(),
+            encrypt_key: String::new(),
+            require_admin: false,
+            recovery_codes: Self::default_recovery_codes(),
+            challenge_ttl: Self::default_challenge_ttl(),
+        }
+    }
+}
+
+/// 数据接口请求签名
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignConfig {
+    //客户端与服务器允许的最大时间偏差（秒），nonce 在两倍时间内不可重复
+    #[serde(default = "SignConfig::default_max_skew")]
+    pub max_skew: u64,
+    //预签名 URL 默认有效期（秒）
+    #[serde(default = "SignConfig::default_presign_expires")]
+    pub presign_expires: u64,
+    //预签名 URL 最长有效期（秒）
+    #[serde(default = "SignConfig::default_presign_max_expires")]
+    pub presign_max_expires: u64,
+    //预签名 URL 的访问地址前缀，为空时返回相对路径
+    #[serde(default)]
+    pub api_url: String,
+    #[serde(default)]
+    pub console_url: String,
+}
+
+impl SignConfig {
+    fn default_max_skew() -> u64 {
+        300
+    }
+    fn default_presign_expires() -> u64 {
+        60 * 60
+    }
+    fn default_presign_max_expires() -> u64 {
+        60 * 60 * 24 * 7
+    }
+}
+
+impl Default for SignConfig {
+    fn default() -> Self {
+        Self {
+            max_skew: Self::default_max_skew(),
+            presign_expires: Self::default_presign_expires(),
+            presign_max_expires: Self::default_presign_max_expires(),
+            api_url: String::new(),
+            console_url: String::new(),
+        }
+    }
+}
+
+/// 数据库迁移，`migrate` 子命令不受 `auto` 影响
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct MigrateConfig {
+    //启动服务时执行未应用的迁移
+    #[serde(default)]
+    pub auto: bool,
+    //迁移后没有管理员时用该账号创建，为空时不创建
+    #[serde(default)]
+    pub admin_user: String,
+    #[serde(default)]
+    pub admin_password: String,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     pub host: String,
@@ -45,7 +394,10 @@ pub struct AppState {
     pub root_path: String,
     pub dir_create_cache: Arc<Cache<String, String>>,
     pub db_path_cache: Arc<Cache<String, String>>,
-    pub session_cache: Arc<Cache<String, UserCache>>,
+    //登录会话
+    pub session_store: Arc<dyn SessionStore>,
+    //分片存储
+    pub storage: Arc<dyn StorageBackend>,
 }
 
 impl AppState {
@@ -1,2 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod config;
 pub use config::*;
+pub mod cli;
+pub use cli::*;
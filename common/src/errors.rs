@@ -1,12 +1,9 @@
-use actix_multipart::form::json;
 use actix_multipart::MultipartError;
-use actix_web::http::StatusCode;
-use actix_web::{http, HttpResponse, Responder, ResponseError};
-use futures_util::future::err;
+use actix_web::{HttpResponse, ResponseError};
 use log::{error, warn};
 use redis::RedisError;
 use serde::Serialize;
-use serde_json::{json, to_string};
+use serde_json::to_string;
 use sqlx::Error;
 use thiserror::Error;
 pub type Result<T> = std::result::Result<T, AppError>;
@@ -47,7 +44,7 @@ pub struct ErrorResponse<'a> {
 impl ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
         // 根据错误类型返回适当的 HTTP 状态码
-        let (code,success, error_type, error_msg) = match *&self {
+        let (code,success, error_type, error_msg) = match self {
             AppError::NotFound(msg) => {
                 error!("InvalidInput: {}", msg);
                 (actix_web::http::StatusCode::NOT_FOUND,false, "Not Found", "".to_string())
@@ -89,7 +86,7 @@ impl ResponseError for AppError {
                 let json = serde_json::json!({ err.code.as_ref(): [msg] });
                 let string = to_string(&json).unwrap();
                 warn!("ValidateError Error: {}", &string);
-                { (actix_web::http::StatusCode::OK, true,"ValidateError", string) }
+                (actix_web::http::StatusCode::OK, true,"ValidateError", string)
             },
         };
 
@@ -1,8 +1,12 @@
 pub mod config;
 pub mod errors;
 pub mod resp;
+pub mod session;
+pub mod storage;
 pub mod util;
 pub use config::*;
 pub use errors::*;
 pub use resp::*;
+pub use session::*;
+pub use storage::*;
 pub use util::*;
@@ -144,11 +144,11 @@ pub fn result_warn_msg(msg: &str) -> Value {
     serde_json::json!({"success":true,"msg":msg})
 }
 pub fn result_list<T: Serialize + Debug>(list: Vec<T>) -> Value {
-    return serde_json::json!({"success":true,"data":list});
+    serde_json::json!({"success":true,"data":list})
 }
 pub fn result_page<T: Serialize + Debug>(page: Page<T>) -> Value {
-    return serde_json::json!({"success":true,"data":{"list":page.data,"total":page.total,"page":page.page_info}});
+    serde_json::json!({"success":true,"data":{"list":page.data,"total":page.total,"page":page.page_info}})
 }
 pub fn result_data<T: Serialize + Debug>(data: T) -> Value {
-    return serde_json::json!({"success":true,"data":data});
+    serde_json::json!({"success":true,"data":data})
 }
@@ -0,0 +1,142 @@
+use crate::{AppError, LoginConfig, LoginFailures, SessionStore};
+use chrono::Local;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USER_KEY_PREFIX: &str = "user:";
+const IP_KEY_PREFIX: &str = "ip:";
+
+/// 控制台登录失败计数，分别按用户名和客户端 IP 统计
+///
+/// 连续失败超过允许次数后锁定，锁定时间从 `lock_time` 开始每次失败翻倍，最长 `max_lock_time`；
+/// 计数保存在会话存储中，redis 时多个实例共享。
+///
+/// 每次校验密码前先用 `attempt` 计入一次失败，成功后由 `succeeded` 撤销，
+/// 并发请求不能绕过次数限制
+#[derive(Clone)]
+pub struct LoginGuard {
+    store: Arc<dyn SessionStore>,
+    config: LoginConfig,
+}
+
+impl LoginGuard {
+    pub fn new(store: Arc<dyn SessionStore>, config: LoginConfig) -> Self {
+        Self { store, config }
+    }
+
+    /// 预占一次尝试，先按失败计数，用户名或 IP 仍在锁定中时返回 `login.locked:剩余秒数`
+    pub async fn attempt(&self, user_name: &str, client_ip: &str) -> Result<(), AppError> {
+        let user_key = Self::user_key(user_name);
+        let ip_key = Self::ip_key(client_ip);
+        let user_before = self.store.login_failures(&user_key).await?;
+        let ip_before = self.store.login_failures(&ip_key).await?;
+        self.ensure_unlocked(&user_before, &ip_before)?;
+        // 计数需保留到最长锁定结束之后
+        let ttl = Duration::from_secs(self.config.reset_time.max(self.config.max_lock_time));
+        let user_after = self.store.add_login_failure(&user_key, ttl).await?;
+        let ip_after = self.store.add_login_failure(&ip_key, ttl).await?;
+        // 并发请求读到的是同一个计数，按自增后的结果只放行剩余的次数
+        if user_after.count > Self::attempt_limit(&user_before, self.config.max_attempts)
+            || ip_after.count > Self::attempt_limit(&ip_before, self.config.ip_max_attempts)
+        {
+            self.ensure_unlocked(&user_after, &ip_after)?;
+            return Err(AppError::BizError(format!("login.locked:{}", self.config.lock_time.max(1))));
+        }
+        Ok(())
+    }
+
+    /// 撤销 `attempt` 计入的失败：清零用户名计数，IP 计数只减去本次，
+    /// 避免用一个有效账号为其他账号的尝试解锁
+    pub async fn succeeded(&self, user_name: &str, client_ip: &str) -> Result<(), AppError> {
+        self.store.clear_login_failures(&Self::user_key(user_name)).await?;
+        self.release_ip(client_ip).await
+    }
+
+    /// 只撤销 IP 上计入的本次尝试，用于两步验证的第一步，用户名计数在第二步成功后清零
+    pub async fn release_ip(&self, client_ip: &str) -> Result<(), AppError> {
+        self.store.release_login_failure(&Self::ip_key(client_ip)).await
+    }
+
+    /// 管理员解锁账号，`client_ip` 不为空时同时解锁该 IP
+    pub async fn unlock(&self, user_name: &str, client_ip: Option<&str>) -> Result<(), AppError> {
+        self.store.clear_login_failures(&Self::user_key(user_name)).await?;
+        if let Some(client_ip) = client_ip.filter(|ip| !ip.is_empty()) {
+            self.store.clear_login_failures(&Self::ip_key(client_ip)).await?;
+        }
+        Ok(())
+    }
+
+    fn ensure_unlocked(&self, user_failures: &LoginFailures, ip_failures: &LoginFailures) -> Result<(), AppError> {
+        let remaining = self
+            .locked_until(user_failures, self.config.max_attempts)
+            .max(self.locked_until(ip_failures, self.config.ip_max_attempts))
+            - Local::now().timestamp();
+        match remaining > 0 {
+            true => Err(AppError::BizError(format!("login.locked:{}", remaining))),
+            false => Ok(()),
+        }
+    }
+
+    /// 本次尝试后允许达到的计数，锁定结束后允许再试一次
+    fn attempt_limit(before: &LoginFailures, max_attempts: u32) -> u32 {
+        max_attempts.max(1).max(before.count + 1)
+    }
+
+    /// 锁定结束的时间戳，未锁定时返回 0
+    fn locked_until(&self, failures: &LoginFailures, max_attempts: u32) -> i64 {
+        if failures.count < max_attempts.max(1) {
+            return 0;
+        }
+        let exponent = (failures.count - max_attempts.max(1)).min(31);
+        let lock_time = self
+            .config
+            .lock_time
+            .saturating_mul(1u64 << exponent)
+            .min(self.config.max_lock_time);
+        failures.last_time + lock_time as i64
+    }
+
+    fn user_key(user_name: &str) -> String {
+        format!("{}{}", USER_KEY_PREFIX, user_name.to_lowercase())
+    }
+
+    fn ip_key(client_ip: &str) -> String {
+        format!("{}{}", IP_KEY_PREFIX, client_ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MokaSessionStore;
+
+    fn guard(max_attempts: u32, ip_max_attempts: u32) -> LoginGuard {
+        let config = LoginConfig {
+            max_attempts,
+            ip_max_attempts,
+            ..Default::default()
+        };
+        LoginGuard::new(Arc::new(MokaSessionStore::new(Duration::from_secs(60), 100)), config)
+    }
+
+    #[tokio::test]
+    async fn concurrent_attempts_cannot_exceed_limit() {
+        let guard = guard(3, 100);
+        let results = futures::future::join_all((0..10).map(|_| guard.attempt("alice", "10.0.0.1"))).await;
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 3);
+        assert!(guard.attempt("alice", "10.0.0.1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn success_releases_only_its_own_attempt() {
+        let guard = guard(3, 2);
+        guard.attempt("alice", "10.0.0.1").await.unwrap();
+        guard.succeeded("alice", "10.0.0.1").await.unwrap();
+        guard.attempt("alice", "10.0.0.1").await.unwrap();
+        guard.succeeded("alice", "10.0.0.1").await.unwrap();
+        // 成功登录不占用 IP 次数，失败的尝试仍然计入
+        guard.attempt("bob", "10.0.0.1").await.unwrap();
+        guard.attempt("carol", "10.0.0.1").await.unwrap();
+        assert!(guard.attempt("dave", "10.0.0.1").await.is_err());
+    }
+}
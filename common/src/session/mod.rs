@@ -0,0 +1,78 @@
+pub mod login_guard;
+pub mod moka_session;
+pub mod redis_session;
+
+pub use login_guard::*;
+pub use moka_session::*;
+pub use redis_session::*;
+
+use crate::{AppError, SessionConfig, UserCache};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// 登录会话存储，key 为登录时生成的 token
+///
+/// 会话在最后一次访问后 `ttl` 秒过期，`get` 命中时顺延
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    /// 查询会话并顺延过期时间
+    async fn get(&self, token: &str) -> Result<Option<UserCache>, AppError>;
+    async fn put(&self, token: &str, user: &UserCache) -> Result<(), AppError>;
+    /// 删除会话，不存在视为成功
+    async fn remove(&self, token: &str) -> Result<(), AppError>;
+
+    /// 用户当前的会话版本，从未吊销过时为 0
+    async fn session_version(&self, user_id: i64) -> Result<u64, AppError>;
+    /// 版本加一，使该用户已登录的会话全部失效，返回新版本
+    async fn revoke_sessions(&self, user_id: i64) -> Result<u64, AppError>;
+
+    /// 查询会话并校验会话版本，已吊销的会话删除后返回 None
+    async fn find(&self, token: &str) -> Result<Option<UserCache>, AppError> {
+        let user = match self.get(token).await? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+        if user.session_version != self.session_version(user.id).await? {
+            self.remove(token).await?;
+            return Ok(None);
+        }
+        Ok(Some(user))
+    }
+
+    /// 查询登录失败记录，不存在时次数为 0
+    async fn login_failures(&self, key: &str) -> Result<LoginFailures, AppError>;
+    /// 失败次数加一，记录在最后一次失败 `ttl` 后过期
+    async fn add_login_failure(&self, key: &str, ttl: Duration) -> Result<LoginFailures, AppError>;
+    async fn clear_login_failures(&self, key: &str) -> Result<(), AppError>;
+    /// 失败次数减一，不改变最后失败时间，记录不存在时不处理
+    async fn release_login_failure(&self, key: &str) -> Result<(), AppError>;
+
+    /// 保存输入密码后待完成两步验证的登录，`ttl` 后过期
+    async fn put_challenge(&self, token: &str, user_id: i64, ttl: Duration) -> Result<(), AppError>;
+    /// 查询待完成的登录，返回用户 id
+    async fn get_challenge(&self, token: &str) -> Result<Option<i64>, AppError>;
+    async fn remove_challenge(&self, token: &str) -> Result<(), AppError>;
+
+    /// 记录请求签名的 nonce，`ttl` 后过期；已存在时返回 false
+    async fn put_nonce(&self, key: &str, ttl: Duration) -> Result<bool, AppError>;
+}
+
+/// 登录失败记录，key 为用户名或客户端 IP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginFailures {
+    pub count: u32,
+    //最后一次失败的时间戳（秒）
+    pub last_time: i64,
+}
+
+/// 按 `[session] store` 创建会话存储，redis 时多个实例共享会话且重启后保留
+pub async fn build_session_store(config: &SessionConfig) -> Result<Arc<dyn SessionStore>, AppError> {
+    let ttl = Duration::from_secs(config.ttl.max(1));
+    match config.store.as_str() {
+        "redis" => Ok(Arc::new(RedisSessionStore::connect(&config.redis_url, ttl).await?)),
+        "memory" => Ok(Arc::new(MokaSessionStore::new(ttl, config.max_capacity))),
+        store => Err(AppError::InvalidInput(format!("session.store.invalid:{}", store))),
+    }
+}
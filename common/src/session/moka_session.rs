@@ -0,0 +1,151 @@
+use crate::{AppError, LoginFailures, SessionStore, UserCache};
+use async_trait::async_trait;
+use chrono::Local;
+use moka::future::Cache;
+use moka::ops::compute::Op;
+use moka::Expiry;
+use std::time::{Duration, Instant};
+
+/// 进程内会话，重启后失效，只适用于单实例部署
+pub struct MokaSessionStore {
+    cache: Cache<String, UserCache>,
+    failures: Cache<String, TtlEntry<LoginFailures>>,
+    challenges: Cache<String, TtlEntry<i64>>,
+    nonces: Cache<String, TtlEntry<()>>,
+    //不设容量上限，淘汰后版本归零会让已吊销的会话重新生效
+    versions: Cache<i64, u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TtlEntry<V> {
+    value: V,
+    ttl: Duration,
+}
+
+/// 按写入时指定的 ttl 过期
+struct TtlExpiry;
+
+impl<V> Expiry<String, TtlEntry<V>> for TtlExpiry {
+    fn expire_after_create(&self, _key: &String, value: &TtlEntry<V>, _created_at: Instant) -> Option<Duration> {
+        Some(value.ttl)
+    }
+
+    fn expire_after_update(
+        &self,
+        _key: &String,
+        value: &TtlEntry<V>,
+        _updated_at: Instant,
+        _duration_until_expiry: Option<Duration>,
+    ) -> Option<Duration> {
+        Some(value.ttl)
+    }
+}
+
+impl MokaSessionStore {
+    pub fn new(ttl: Duration, max_capacity: u64) -> Self {
+        Self {
+            // time_to_idle 在每次读取后重新计时
+            cache: Cache::builder().time_to_idle(ttl).max_capacity(max_capacity).build(),
+            failures: Cache::builder().expire_after(TtlExpiry).max_capacity(max_capacity).build(),
+            challenges: Cache::builder().expire_after(TtlExpiry).max_capacity(max_capacity).build(),
+            nonces: Cache::builder().expire_after(TtlExpiry).build(),
+            versions: Cache::builder().build(),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for MokaSessionStore {
+    async fn get(&self, token: &str) -> Result<Option<UserCache>, AppError> {
+        Ok(self.cache.get(token).await)
+    }
+
+    async fn put(&self, token: &str, user: &UserCache) -> Result<(), AppError> {
+        self.cache.insert(token.to_string(), user.clone()).await;
+        Ok(())
+    }
+
+    async fn remove(&self, token: &str) -> Result<(), AppError> {
+        self.cache.invalidate(token).await;
+        Ok(())
+    }
+
+    async fn session_version(&self, user_id: i64) -> Result<u64, AppError> {
+        Ok(self.versions.get(&user_id).await.unwrap_or_default())
+    }
+
+    async fn revoke_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let entry = self
+            .versions
+            .entry(user_id)
+            .and_upsert_with(|current| std::future::ready(current.map(|entry| entry.into_value()).unwrap_or_default() + 1))
+            .await;
+        Ok(entry.into_value())
+    }
+
+    async fn login_failures(&self, key: &str) -> Result<LoginFailures, AppError> {
+        Ok(self.failures.get(key).await.map(|entry| entry.value).unwrap_or_default())
+    }
+
+    async fn add_login_failure(&self, key: &str, ttl: Duration) -> Result<LoginFailures, AppError> {
+        // 同一 key 的并发更新串行执行，计数不会丢失
+        let entry = self
+            .failures
+            .entry(key.to_string())
+            .and_upsert_with(|current| {
+                let count = current.map(|entry| entry.into_value().value.count).unwrap_or_default();
+                let entry = TtlEntry {
+                    value: LoginFailures {
+                        count: count + 1,
+                        last_time: Local::now().timestamp(),
+                    },
+                    ttl,
+                };
+                std::future::ready(entry)
+            })
+            .await;
+        Ok(entry.into_value().value)
+    }
+
+    async fn clear_login_failures(&self, key: &str) -> Result<(), AppError> {
+        self.failures.invalidate(key).await;
+        Ok(())
+    }
+
+    async fn release_login_failure(&self, key: &str) -> Result<(), AppError> {
+        self.failures
+            .entry(key.to_string())
+            .and_compute_with(|current| {
+                let op = match current {
+                    Some(entry) => {
+                        let mut entry = entry.into_value();
+                        entry.value.count = entry.value.count.saturating_sub(1);
+                        Op::Put(entry)
+                    }
+                    None => Op::Nop,
+                };
+                std::future::ready(op)
+            })
+            .await;
+        Ok(())
+    }
+
+    async fn put_challenge(&self, token: &str, user_id: i64, ttl: Duration) -> Result<(), AppError> {
+        self.challenges.insert(token.to_string(), TtlEntry { value: user_id, ttl }).await;
+        Ok(())
+    }
+
+    async fn get_challenge(&self, token: &str) -> Result<Option<i64>, AppError> {
+        Ok(self.challenges.get(token).await.map(|entry| entry.value))
+    }
+
+    async fn remove_challenge(&self, token: &str) -> Result<(), AppError> {
+        self.challenges.invalidate(token).await;
+        Ok(())
+    }
+
+    async fn put_nonce(&self, key: &str, ttl: Duration) -> Result<bool, AppError> {
+        let entry = self.nonces.entry(key.to_string()).or_insert(TtlEntry { value: (), ttl }).await;
+        Ok(entry.is_fresh())
+    }
+}
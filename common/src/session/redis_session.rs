@@ -0,0 +1,190 @@
+use crate::{AppError, LoginFailures, SessionStore, UserCache};
+use async_trait::async_trait;
+use chrono::Local;
+use redis::aio::ConnectionManager;
+use std::time::Duration;
+
+const KEY_PREFIX: &str = "file-cloud:session:";
+const FAILURE_KEY_PREFIX: &str = "file-cloud:login-failure:";
+const CHALLENGE_KEY_PREFIX: &str = "file-cloud:login-challenge:";
+const VERSION_KEY_PREFIX: &str = "file-cloud:session-version:";
+const NONCE_KEY_PREFIX: &str = "file-cloud:sign-nonce:";
+const RELEASE_FAILURE_SCRIPT: &str = "if redis.call('HGET', KEYS[1], 'count') and tonumber(redis.call('HGET', KEYS[1], 'count')) > 0 then redis.call('HINCRBY', KEYS[1], 'count', -1) end return 0";
+
+/// Redis 会话，值为 `UserCache` 的 JSON，过期由 Redis 的 TTL 控制
+pub struct RedisSessionStore {
+    conn: ConnectionManager,
+    ttl: u64,
+}
+
+impl RedisSessionStore {
+    pub async fn connect(url: &str, ttl: Duration) -> Result<Self, AppError> {
+        let client = redis::Client::open(url)?;
+        let conn = ConnectionManager::new(client).await?;
+        Ok(Self {
+            conn,
+            ttl: ttl.as_secs().max(1),
+        })
+    }
+
+    fn key(token: &str) -> String {
+        format!("{}{}", KEY_PREFIX, token)
+    }
+
+    fn failure_key(key: &str) -> String {
+        format!("{}{}", FAILURE_KEY_PREFIX, key)
+    }
+
+    fn challenge_key(token: &str) -> String {
+        format!("{}{}", CHALLENGE_KEY_PREFIX, token)
+    }
+
+    fn version_key(user_id: i64) -> String {
+        format!("{}{}", VERSION_KEY_PREFIX, user_id)
+    }
+
+    fn nonce_key(key: &str) -> String {
+        format!("{}{}", NONCE_KEY_PREFIX, key)
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn get(&self, token: &str) -> Result<Option<UserCache>, AppError> {
+        let key = Self::key(token);
+        let mut conn = self.conn.clone();
+        // 读取与顺延在同一事务内，会话不存在时 EXPIRE 不生效
+        let (value, _): (Option<String>, bool) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .expire(&key, self.ttl as i64)
+            .query_async(&mut conn)
+            .await?;
+        match value {
+            Some(value) => match serde_json::from_str(&value) {
+                Ok(user) => Ok(Some(user)),
+                Err(e) => Err(AppError::InternalError(format!("session.decode.error:{}", e))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    async fn put(&self, token: &str, user: &UserCache) -> Result<(), AppError> {
+        let value = serde_json::to_string(user).map_err(|e| AppError::InternalError(e.to_string()))?;
+        let mut conn = self.conn.clone();
+        let _: () = redis::cmd("SET")
+            .arg(Self::key(token))
+            .arg(value)
+            .arg("EX")
+            .arg(self.ttl)
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, token: &str) -> Result<(), AppError> {
+        let mut conn = self.conn.clone();
+        let _: () = redis::cmd("DEL").arg(Self::key(token)).query_async(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn session_version(&self, user_id: i64) -> Result<u64, AppError> {
+        let mut conn = self.conn.clone();
+        let version: Option<u64> = redis::cmd("GET").arg(Self::version_key(user_id)).query_async(&mut conn).await?;
+        Ok(version.unwrap_or_default())
+    }
+
+    async fn revoke_sessions(&self, user_id: i64) -> Result<u64, AppError> {
+        let mut conn = self.conn.clone();
+        // 不设置过期，会话在访问时不断顺延，版本需要一直保留
+        let version: u64 = redis::cmd("INCR").arg(Self::version_key(user_id)).query_async(&mut conn).await?;
+        Ok(version)
+    }
+
+    async fn login_failures(&self, key: &str) -> Result<LoginFailures, AppError> {
+        let mut conn = self.conn.clone();
+        let (count, last_time): (Option<u32>, Option<i64>) = redis::cmd("HMGET")
+            .arg(Self::failure_key(key))
+            .arg("count")
+            .arg("last_time")
+            .query_async(&mut conn)
+            .await?;
+        Ok(LoginFailures {
+            count: count.unwrap_or_default(),
+            last_time: last_time.unwrap_or_default(),
+        })
+    }
+
+    async fn add_login_failure(&self, key: &str, ttl: Duration) -> Result<LoginFailures, AppError> {
+        let key = Self::failure_key(key);
+        let last_time = Local::now().timestamp();
+        let mut conn = self.conn.clone();
+        // HINCRBY 保证多实例并发失败时计数准确
+        let (count,): (u32,) = redis::pipe()
+            .atomic()
+            .hincr(&key, "count", 1)
+            .hset(&key, "last_time", last_time)
+            .ignore()
+            .expire(&key, ttl.as_secs().max(1) as i64)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        Ok(LoginFailures { count, last_time })
+    }
+
+    async fn clear_login_failures(&self, key: &str) -> Result<(), AppError> {
+        let mut conn = self.conn.clone();
+        let _: () = redis::cmd("DEL").arg(Self::failure_key(key)).query_async(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn release_login_failure(&self, key: &str) -> Result<(), AppError> {
+        let mut conn = self.conn.clone();
+        // 记录已过期时直接 HINCRBY 会建出没有 TTL 的负数计数
+        let _: () = redis::cmd("EVAL")
+            .arg(RELEASE_FAILURE_SCRIPT)
+            .arg(1)
+            .arg(Self::failure_key(key))
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn put_challenge(&self, token: &str, user_id: i64, ttl: Duration) -> Result<(), AppError> {
+        let mut conn = self.conn.clone();
+        let _: () = redis::cmd("SET")
+            .arg(Self::challenge_key(token))
+            .arg(user_id)
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .query_async(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_challenge(&self, token: &str) -> Result<Option<i64>, AppError> {
+        let mut conn = self.conn.clone();
+        let user_id: Option<i64> = redis::cmd("GET").arg(Self::challenge_key(token)).query_async(&mut conn).await?;
+        Ok(user_id)
+    }
+
+    async fn remove_challenge(&self, token: &str) -> Result<(), AppError> {
+        let mut conn = self.conn.clone();
+        let _: () = redis::cmd("DEL").arg(Self::challenge_key(token)).query_async(&mut conn).await?;
+        Ok(())
+    }
+
+    async fn put_nonce(&self, key: &str, ttl: Duration) -> Result<bool, AppError> {
+        let mut conn = self.conn.clone();
+        // SET NX 在多个实例间只有一个成功
+        let result: Option<String> = redis::cmd("SET")
+            .arg(Self::nonce_key(key))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .query_async(&mut conn)
+            .await?;
+        Ok(result.is_some())
+    }
+}
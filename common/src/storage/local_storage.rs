@@ -0,0 +1,92 @@
+use crate::{build_id, AppError, StorageBackend};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{Datelike, Timelike};
+use moka::future::Cache;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// 本地文件存储，目录结构：root/年/一年中的第几天/一天中的第几分钟/hash
+pub struct LocalStorage {
+    root: String,
+    dir_create_cache: Arc<Cache<String, String>>,
+}
+
+impl LocalStorage {
+    pub fn new(root: &str, dir_create_cache: Arc<Cache<String, String>>) -> Self {
+        Self {
+            root: root.to_string(),
+            dir_create_cache,
+        }
+    }
+
+    ///生成文件目录
+    async fn build_dir_name(&self) -> Result<String, AppError> {
+        let now = chrono::Local::now();
+        //一年中的第几天（1-366）
+        let day_index = now.date_naive().ordinal();
+        //一天中的第几分钟
+        let minutes_of_day = now.hour() * 60 + now.minute();
+        let dir_name = format!("{}/{}/{}/{}", self.root, now.year(), day_index, minutes_of_day);
+        if self.dir_create_cache.get(&dir_name).await.is_none() {
+            tokio::fs::create_dir_all(&dir_name).await?;
+            self.dir_create_cache.insert(dir_name.clone(), "1".to_string()).await;
+        }
+        Ok(dir_name)
+    }
+}
+
+fn not_found(key: &str, e: std::io::Error) -> AppError {
+    match e.kind() {
+        ErrorKind::NotFound => AppError::NotFound(format!("chunk.not.found:{}", key)),
+        _ => e.into(),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn new_key(&self, hash: &str) -> Result<String, AppError> {
+        Ok(format!("{}/{}", self.build_dir_name().await?, hash))
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError> {
+        if let Some(parent) = Path::new(key).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // 先写临时文件再重命名，避免读到写了一半的分片
+        let tmp_path = format!("{}.{}.tmp", key, build_id());
+        if let Err(e) = tokio::fs::write(&tmp_path, data).await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(e.into());
+        }
+        tokio::fs::rename(&tmp_path, key).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, AppError> {
+        let data = tokio::fs::read(key).await.map_err(|e| not_found(key, e))?;
+        Ok(Bytes::from(data))
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, AppError> {
+        let mut file = tokio::fs::File::open(key).await.map_err(|e| not_found(key, e))?;
+        file.seek(SeekFrom::Start(start)).await?;
+        let mut buffer = Vec::with_capacity(end.saturating_sub(start) as usize);
+        file.take(end.saturating_sub(start)).read_to_end(&mut buffer).await?;
+        Ok(Bytes::from(buffer))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match tokio::fs::remove_file(key).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        Ok(tokio::fs::try_exists(key).await?)
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{AppError, StorageBackend};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// 内存存储，用于测试
+#[derive(Default)]
+pub struct MemoryStorage {
+    chunks: RwLock<HashMap<String, Bytes>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn find(&self, key: &str) -> Result<Bytes, AppError> {
+        let chunks = self.chunks.read().map_err(|e| AppError::InternalError(e.to_string()))?;
+        match chunks.get(key) {
+            Some(data) => Ok(data.clone()),
+            None => Err(AppError::NotFound(format!("chunk.not.found:{}", key))),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryStorage {
+    async fn new_key(&self, hash: &str) -> Result<String, AppError> {
+        Ok(format!("memory/{}", hash))
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError> {
+        let mut chunks = self.chunks.write().map_err(|e| AppError::InternalError(e.to_string()))?;
+        chunks.insert(key.to_string(), Bytes::copy_from_slice(data));
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Bytes, AppError> {
+        self.find(key)
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, AppError> {
+        let data = self.find(key)?;
+        let end = (end as usize).min(data.len());
+        let start = (start as usize).min(end);
+        Ok(data.slice(start..end))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        let mut chunks = self.chunks.write().map_err(|e| AppError::InternalError(e.to_string()))?;
+        chunks.remove(key);
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, AppError> {
+        let chunks = self.chunks.read().map_err(|e| AppError::InternalError(e.to_string()))?;
+        Ok(chunks.contains_key(key))
+    }
+}
@@ -0,0 +1,25 @@
+pub mod local_storage;
+pub mod memory_storage;
+
+pub use local_storage::*;
+pub use memory_storage::*;
+
+use crate::AppError;
+use async_trait::async_trait;
+use bytes::Bytes;
+
+/// 分片存储，handler 只通过该 trait 读写分片，key 即 `FileItemDto.path`
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// 为新分片分配存储 key
+    async fn new_key(&self, hash: &str) -> Result<String, AppError>;
+    /// 写入分片，写入完成前其他请求不可见
+    async fn put(&self, key: &str, data: &[u8]) -> Result<(), AppError>;
+    /// 读取整个分片
+    async fn get(&self, key: &str) -> Result<Bytes, AppError>;
+    /// 读取分片的 [start, end) 区间
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<Bytes, AppError>;
+    /// 删除分片，分片不存在视为成功
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+    async fn exists(&self, key: &str) -> Result<bool, AppError>;
+}
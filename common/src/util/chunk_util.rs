@@ -0,0 +1,17 @@
+use crate::AppError;
+use log::error;
+use sha2::{Digest, Sha256};
+
+/// 分片内容的 sha256，作为分片的唯一标识
+pub fn chunk_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// 校验读取的分片内容，旧数据没有 hash 时跳过
+pub fn verify_chunk(key: &str, hash: &str, data: &[u8]) -> Result<(), AppError> {
+    if hash.is_empty() || chunk_hash(data) == hash {
+        return Ok(());
+    }
+    error!("chunk {} checksum mismatch, expected {}", key, hash);
+    Err(AppError::InternalError(format!("chunk.checksum.mismatch:{}", key)))
+}
@@ -14,6 +14,9 @@ pub struct UserCache {
     pub is_admin: bool,
     pub user_name: String,
     pub bucket_list: Vec<BucketCache>,
+    //登录时用户的会话版本，与存储中的版本不一致时会话已被吊销
+    #[serde(default)]
+    pub session_version: u64,
 }
 impl UserCache{
 
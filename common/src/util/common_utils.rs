@@ -11,8 +11,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use walkdir::{DirEntry, WalkDir};
 
 
-use crate::{AppError, AppState, UserCache};
-use actix_web::{web, HttpRequest};
+use crate::{AppError, AppState, LoginConfig, UserCache};
+use actix_web::http::header;
+use actix_web::{web, HttpMessage, HttpRequest};
 use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
 use tempfile::tempdir;
@@ -34,14 +35,13 @@ pub fn build_id() -> String {
 
 pub fn build_snow_id() -> i64 {
     let mut generator = SafeSnowflake::new(1, 1);
-   return generator.generate() as i64;
+    generator.generate() as i64
 }
 pub fn build_md5(content: &str) -> String {
     let mut hasher = Md5::new();
     hasher.update(content);
     let result = hasher.finalize();
-    let hex_string = encode(result);
-    hex_string
+    encode(result)
 }
 
 
@@ -96,38 +96,70 @@ impl SafeSnowflake {
 
 
 
-pub async fn  get_session_user(
+/// 从 `Authorization: Bearer <token>` 中取出 token
+pub fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    let token = token.trim();
+    match scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty() {
+        true => Some(token.to_string()),
+        false => None,
+    }
+}
+
+/// 客户端 IP，直连地址在 `[login] trusted_proxies` 中时取 X-Forwarded-For
+///
+/// 从右往左跳过可信代理，取第一个不可信的地址，客户端自己带上的值不会被采用
+pub fn client_ip(req: &HttpRequest) -> String {
+    let peer = req.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+    let trusted_proxies = match req.app_data::<web::Data<LoginConfig>>() {
+        Some(config) => config.trusted_proxies.as_slice(),
+        None => return peer,
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let forwarded: Vec<&str> = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .collect();
+    match forwarded.into_iter().rev().find(|ip| !trusted_proxies.iter().any(|proxy| proxy == ip)) {
+        Some(ip) => ip.to_string(),
+        None => peer,
+    }
+}
+
+/// 当前登录用户，认证中间件已校验过的请求直接取中间件放入的会话
+pub async fn get_session_user(
     state: &web::Data<AppState>,
     req: HttpRequest,
 ) -> Result<UserCache, AppError> {
-    if(1==1){
-        return Ok(UserCache {
-            id: 1,
-            user_name: "admin".to_string(),
-            is_admin: true,
-            bucket_list: vec![],
-        });
-    }
-    let mut token_value = "";
-    let option = req.headers().get("Authorization");
-    match option {
-        Some(auth_value) => {
-            if let Ok(auth_str) = auth_value.to_str() {
-                if auth_str.starts_with("Bearer ") {
-                    let token_key = &auth_str[9..];
-                    let option = state.session_cache.get(token_key).await;
-                    if let Some(user_cache) = option {
-                        return Ok(user_cache.clone());
-                    }
-                }
-            }
-        }
-        None => {
-            return Err(AppError::NoRight("token.is.null".to_owned()));
-        }
+    match find_session_user(state, &req).await? {
+        Some(user_cache) => Ok(user_cache),
+        None => Err(AppError::NoRight("token.is.null".to_owned())),
     }
+}
 
-    return Err(AppError::NoRight("token.is.null".to_owned()));
+/// 当前登录用户，未携带 token 时返回 None，用于公开资源的可选登录
+pub async fn find_session_user(
+    state: &web::Data<AppState>,
+    req: &HttpRequest,
+) -> Result<Option<UserCache>, AppError> {
+    if let Some(user_cache) = req.extensions().get::<UserCache>() {
+        return Ok(Some(user_cache.clone()));
+    }
+    let token = match bearer_token(req) {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    match state.session_store.find(&token).await? {
+        Some(user_cache) => Ok(Some(user_cache)),
+        None => Err(AppError::NoRight("token.is.expired".to_owned())),
+    }
 }
 
 
@@ -206,6 +238,36 @@ pub fn zip_dir_to_tempfile(
 
     // 遍历并压缩
     let walkdir = WalkDir::new(src_dir).into_iter();
-    zip_dir(&mut walkdir.filter_map(|e| e.ok()), src_dir, file, method).map_err(|e|AppError::InternalError(e.to_string()));
+    zip_dir(&mut walkdir.filter_map(|e| e.ok()), src_dir, file, method).map_err(|e|AppError::InternalError(e.to_string()))?;
     Ok(temp_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn request(peer: &str, forwarded: &str) -> HttpRequest {
+        let config = LoginConfig {
+            trusted_proxies: vec!["10.0.0.1".to_string()],
+            ..Default::default()
+        };
+        TestRequest::default()
+            .peer_addr(format!("{}:8080", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-For", forwarded))
+            .app_data(web::Data::new(config))
+            .to_http_request()
+    }
+
+    #[test]
+    fn client_ip_ignores_forwarded_from_untrusted_peer() {
+        assert_eq!(client_ip(&request("192.168.1.9", "1.2.3.4")), "192.168.1.9");
+    }
+
+    #[test]
+    fn client_ip_skips_spoofed_forwarded_entries() {
+        // 客户端自带 1.2.3.4，代理追加真实地址 5.6.7.8
+        assert_eq!(client_ip(&request("10.0.0.1", "1.2.3.4, 5.6.7.8")), "5.6.7.8");
+        assert_eq!(client_ip(&request("10.0.0.1", "5.6.7.8, 10.0.0.1")), "5.6.7.8");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::AppError;
+use actix_web::http::header::HeaderMap;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// 上传时边接收边计算文件摘要
+#[derive(Default)]
+pub struct FileHasher {
+    sha256: Sha256,
+    md5: Md5,
+}
+
+impl FileHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        self.md5.update(data);
+    }
+
+    pub fn finish(self) -> FileDigest {
+        FileDigest {
+            sha256: hex::encode(self.sha256.finalize()),
+            md5: hex::encode(self.md5.finalize()),
+        }
+    }
+}
+
+/// 文件摘要，十六进制小写
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileDigest {
+    pub sha256: String,
+    pub md5: String,
+}
+
+impl FileDigest {
+    pub fn new(sha256: &str, md5: &str) -> Self {
+        Self {
+            sha256: sha256.to_string(),
+            md5: md5.to_string(),
+        }
+    }
+
+    /// 强 ETag，使用 sha256
+    pub fn etag(&self) -> Option<String> {
+        match self.sha256.is_empty() {
+            true => None,
+            false => Some(format!("\"{}\"", self.sha256)),
+        }
+    }
+
+    /// `Digest` 响应头，RFC 3230：`sha-256=<base64>,md5=<base64>`
+    pub fn digest_header(&self) -> Option<String> {
+        let mut values = Vec::new();
+        if let Ok(sha256) = hex::decode(&self.sha256) && !sha256.is_empty() {
+            values.push(format!("sha-256={}", STANDARD.encode(sha256)));
+        }
+        if let Ok(md5) = hex::decode(&self.md5) && !md5.is_empty() {
+            values.push(format!("md5={}", STANDARD.encode(md5)));
+        }
+        match values.is_empty() {
+            true => None,
+            false => Some(values.join(",")),
+        }
+    }
+}
+
+/// 客户端上传时提供的摘要，上传完成后校验
+///
+/// 支持 `X-Checksum-Sha256`、`X-Checksum-Md5`（十六进制或 base64）、
+/// `Digest: sha-256=<base64>,md5=<base64>` 与 `Content-MD5`
+#[derive(Debug, Clone, Default)]
+pub struct ExpectedDigest {
+    pub sha256: Option<String>,
+    pub md5: Option<String>,
+}
+
+impl ExpectedDigest {
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let mut expected = ExpectedDigest::default();
+        if let Some(value) = header_str(headers, "digest")? {
+            for item in value.split(',') {
+                let (algorithm, value) = match item.trim().split_once('=') {
+                    Some(pair) => pair,
+                    None => return Err(AppError::InvalidInput("checksum.invalid".to_owned())),
+                };
+                match algorithm.to_lowercase().as_str() {
+                    "sha-256" => expected.sha256 = Some(decode_digest(value, 32)?),
+                    "md5" => expected.md5 = Some(decode_digest(value, 16)?),
+                    _ => {}
+                }
+            }
+        }
+        if let Some(value) = header_str(headers, "content-md5")? {
+            expected.md5 = Some(decode_digest(value, 16)?);
+        }
+        if let Some(value) = header_str(headers, "x-checksum-sha256")? {
+            expected.sha256 = Some(decode_digest(value, 32)?);
+        }
+        if let Some(value) = header_str(headers, "x-checksum-md5")? {
+            expected.md5 = Some(decode_digest(value, 16)?);
+        }
+        Ok(expected)
+    }
+
+    pub fn verify(&self, digest: &FileDigest) -> Result<(), AppError> {
+        let sha256_ok = self.sha256.as_ref().is_none_or(|sha256| sha256 == &digest.sha256);
+        let md5_ok = self.md5.as_ref().is_none_or(|md5| md5 == &digest.md5);
+        match sha256_ok && md5_ok {
+            true => Ok(()),
+            false => Err(AppError::InvalidInput("checksum.mismatch".to_owned())),
+        }
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Result<Option<&'a str>, AppError> {
+    match headers.get(name) {
+        Some(value) => match value.to_str() {
+            Ok(value) => Ok(Some(value.trim())),
+            Err(_) => Err(AppError::InvalidInput("checksum.invalid".to_owned())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// 十六进制或 base64 编码的摘要，统一转为十六进制小写
+fn decode_digest(value: &str, len: usize) -> Result<String, AppError> {
+    let value = value.trim();
+    if value.len() == len * 2 && let Ok(bytes) = hex::decode(value) {
+        return Ok(hex::encode(bytes));
+    }
+    match STANDARD.decode(value) {
+        Ok(bytes) if bytes.len() == len => Ok(hex::encode(bytes)),
+        _ => Err(AppError::InvalidInput("checksum.invalid".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    const EMPTY_MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn decode_hex_and_base64() {
+        assert_eq!(decode_digest(&EMPTY_MD5.to_uppercase(), 16).unwrap(), EMPTY_MD5);
+        assert_eq!(decode_digest("1B2M2Y8AsgTpgAmY7PhCfg==", 16).unwrap(), EMPTY_MD5);
+        assert_eq!(decode_digest("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=", 32).unwrap(), EMPTY_SHA256);
+        // 长度不对或不是合法编码
+        assert!(decode_digest(EMPTY_MD5, 32).is_err());
+        assert!(decode_digest("1B2M2Y8AsgTpgAmY7PhCfg==", 32).is_err());
+        assert!(decode_digest("not-a-digest", 16).is_err());
+    }
+
+    #[test]
+    fn expected_digest_from_headers() {
+        let digest = FileHasher::new().finish();
+        assert_eq!(digest, FileDigest::new(EMPTY_SHA256, EMPTY_MD5));
+
+        let expected = ExpectedDigest::from_headers(&headers(&[(
+            "digest",
+            "SHA-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=, md5=1B2M2Y8AsgTpgAmY7PhCfg==",
+        )]))
+        .unwrap();
+        assert_eq!(expected.sha256.as_deref(), Some(EMPTY_SHA256));
+        assert_eq!(expected.md5.as_deref(), Some(EMPTY_MD5));
+        assert!(expected.verify(&digest).is_ok());
+
+        let expected = ExpectedDigest::from_headers(&headers(&[("x-checksum-md5", "00000000000000000000000000000000")])).unwrap();
+        assert!(expected.sha256.is_none());
+        assert!(expected.verify(&digest).is_err());
+
+        assert!(ExpectedDigest::from_headers(&headers(&[("content-md5", "abc")])).is_err());
+        assert!(ExpectedDigest::from_headers(&headers(&[("digest", "sha-256")])).is_err());
+    }
+
+    #[test]
+    fn digest_header_and_etag() {
+        let digest = FileDigest::new(EMPTY_SHA256, EMPTY_MD5);
+        assert_eq!(digest.etag(), Some(format!("\"{}\"", EMPTY_SHA256)));
+        assert_eq!(
+            digest.digest_header().as_deref(),
+            Some("sha-256=47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=,md5=1B2M2Y8AsgTpgAmY7PhCfg==")
+        );
+        assert_eq!(FileDigest::default().etag(), None);
+        assert_eq!(FileDigest::default().digest_header(), None);
+    }
+}
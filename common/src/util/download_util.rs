@@ -0,0 +1,335 @@
+use crate::{build_id, verify_chunk, AppError, StorageBackend};
+use async_stream::stream;
+use bytes::Bytes;
+use futures::Stream;
+use std::sync::Arc;
+
+//单个请求最多处理的区间数，超出时按整个文件返回
+const MAX_RANGES: usize = 64;
+
+/// 字节区间，`end` 包含在内
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    //没有 Range 或 Range 无法解析，返回整个文件
+    Full,
+    Partial(Vec<ByteRange>),
+    //区间全部超出文件大小，返回 416
+    Unsatisfiable,
+}
+
+/// 文件的一个分片，`key` 为存储 key，`size` 为分片大小，`hash` 为分片 sha256
+#[derive(Debug, Clone)]
+pub struct ChunkRef {
+    pub key: String,
+    pub size: u64,
+    pub hash: String,
+}
+
+/// 解析 `Range: bytes=0-99,200-,-50`
+pub fn parse_range(header: Option<&str>, size: u64) -> RangeRequest {
+    let value = match header.and_then(|h| h.trim().strip_prefix("bytes=")) {
+        Some(value) => value,
+        None => return RangeRequest::Full,
+    };
+    let mut ranges = Vec::new();
+    for spec in value.split(',') {
+        let (first, last) = match spec.trim().split_once('-') {
+            Some(pair) => pair,
+            None => return RangeRequest::Full,
+        };
+        let range = match (first.trim(), last.trim()) {
+            ("", "") => return RangeRequest::Full,
+            // 最后 n 个字节
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(_) if size == 0 => None,
+                Ok(n) => Some(ByteRange {
+                    start: size.saturating_sub(n),
+                    end: size - 1,
+                }),
+                Err(_) => return RangeRequest::Full,
+            },
+            (start, end) => {
+                let start = match start.parse::<u64>() {
+                    Ok(start) => start,
+                    Err(_) => return RangeRequest::Full,
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                match start < size {
+                    true => Some(ByteRange {
+                        start,
+                        end: end.min(size - 1),
+                    }),
+                    false => None,
+                }
+            }
+        };
+        if let Some(range) = range {
+            ranges.push(range);
+        }
+    }
+    let ranges = merge_ranges(ranges);
+    if ranges.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+    match ranges.is_empty() {
+        true => RangeRequest::Unsatisfiable,
+        false => RangeRequest::Partial(ranges),
+    }
+}
+
+/// 合并重叠或相邻的区间，避免重复区间放大响应
+fn merge_ranges(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// 按分片大小把区间映射到分片，逐个分片读取并校验
+pub fn chunk_stream(
+    storage: Arc<dyn StorageBackend>,
+    chunks: Vec<ChunkRef>,
+    range: ByteRange,
+) -> impl Stream<Item = Result<Bytes, AppError>> + 'static {
+    stream! {
+        let mut offset: u64 = 0;
+        for chunk in chunks {
+            let chunk_start = offset;
+            let chunk_end = offset + chunk.size;
+            offset = chunk_end;
+            if chunk.size == 0 || chunk_end <= range.start {
+                continue;
+            }
+            if chunk_start > range.end {
+                break;
+            }
+            let from = range.start.max(chunk_start) - chunk_start;
+            let to = (range.end + 1).min(chunk_end) - chunk_start;
+            match read_chunk(storage.as_ref(), &chunk, from, to).await {
+                Ok(data) => yield Ok(data),
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// 读取分片的 [from, to) 区间；有 hash 的分片整块读取校验后再截取，分片最大 4MB
+pub async fn read_chunk(storage: &dyn StorageBackend, chunk: &ChunkRef, from: u64, to: u64) -> Result<Bytes, AppError> {
+    let data = match chunk.hash.is_empty() {
+        true if from == 0 && to == chunk.size => storage.get(&chunk.key).await?,
+        true => storage.get_range(&chunk.key, from, to).await?,
+        false => {
+            let data = storage.get(&chunk.key).await?;
+            if data.len() as u64 != chunk.size {
+                return Err(AppError::InternalError(format!("chunk.size.mismatch:{}", chunk.key)));
+            }
+            verify_chunk(&chunk.key, &chunk.hash, &data)?;
+            data.slice(from as usize..to as usize)
+        }
+    };
+    if data.len() as u64 != to - from {
+        return Err(AppError::InternalError(format!("chunk.size.mismatch:{}", chunk.key)));
+    }
+    Ok(data)
+}
+
+/// 多区间响应 `multipart/byteranges`
+pub struct MultiRangeBody {
+    pub boundary: String,
+    pub content_length: u64,
+    parts: Vec<(String, ByteRange)>,
+}
+
+impl MultiRangeBody {
+    pub fn new(ranges: Vec<ByteRange>, content_type: &str, size: u64) -> Self {
+        let boundary = build_id().replace('-', "");
+        let parts: Vec<(String, ByteRange)> = ranges
+            .into_iter()
+            .map(|range| {
+                let head = format!(
+                    "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, range.start, range.end, size
+                );
+                (head, range)
+            })
+            .collect();
+        // 每段数据后有 \r\n，结尾为 --boundary--\r\n
+        let content_length = parts.iter().map(|(head, range)| head.len() as u64 + range.length() + 2).sum::<u64>()
+            + boundary.len() as u64
+            + 6;
+        Self {
+            boundary,
+            content_length,
+            parts,
+        }
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    pub fn into_stream(
+        self,
+        storage: Arc<dyn StorageBackend>,
+        chunks: Vec<ChunkRef>,
+    ) -> impl Stream<Item = Result<Bytes, AppError>> + 'static {
+        stream! {
+            for (head, range) in self.parts {
+                yield Ok(Bytes::from(head));
+                for await data in chunk_stream(storage.clone(), chunks.clone(), range) {
+                    let failed = data.is_err();
+                    yield data;
+                    if failed {
+                        return;
+                    }
+                }
+                yield Ok(Bytes::from_static(b"\r\n"));
+            }
+            yield Ok(Bytes::from(format!("--{}--\r\n", self.boundary)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_hash, MemoryStorage};
+    use futures::StreamExt;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn parse_single_and_suffix() {
+        assert_eq!(parse_range(None, 100), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=0-9"), 100), RangeRequest::Partial(vec![range(0, 9)]));
+        assert_eq!(parse_range(Some("bytes=-10"), 100), RangeRequest::Partial(vec![range(90, 99)]));
+        assert_eq!(parse_range(Some("bytes=-200"), 100), RangeRequest::Partial(vec![range(0, 99)]));
+        assert_eq!(parse_range(Some("bytes=50-"), 100), RangeRequest::Partial(vec![range(50, 99)]));
+        assert_eq!(parse_range(Some("bytes=90-500"), 100), RangeRequest::Partial(vec![range(90, 99)]));
+    }
+
+    #[test]
+    fn parse_unsatisfiable_and_malformed() {
+        assert_eq!(parse_range(Some("bytes=100-"), 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-0"), 100), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-5"), 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=9-0"), 100), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=a-b"), 100), RangeRequest::Full);
+        assert_eq!(parse_range(Some("bytes=-"), 100), RangeRequest::Full);
+        assert_eq!(parse_range(Some("items=0-9"), 100), RangeRequest::Full);
+    }
+
+    #[test]
+    fn parse_merges_overlapping() {
+        assert_eq!(
+            parse_range(Some("bytes=50-59,0-9,5-14,15-19,30-"), 100),
+            RangeRequest::Partial(vec![range(0, 19), range(30, 99)])
+        );
+        // 重复的区间合并后不会超出上限
+        let header = format!("bytes={}", vec!["0-99"; MAX_RANGES * 2].join(","));
+        assert_eq!(parse_range(Some(&header), 1000), RangeRequest::Partial(vec![range(0, 99)]));
+    }
+
+    #[test]
+    fn parse_too_many_ranges() {
+        let specs: Vec<String> = (0..=MAX_RANGES as u64).map(|i| format!("{}-{}", i * 10, i * 10)).collect();
+        let header = format!("bytes={}", specs.join(","));
+        assert_eq!(parse_range(Some(&header), 10_000), RangeRequest::Full);
+    }
+
+    async fn stored_chunks(storage: &MemoryStorage, parts: &[&[u8]], with_hash: bool) -> Vec<ChunkRef> {
+        let mut chunks = Vec::new();
+        for (i, data) in parts.iter().enumerate() {
+            let key = format!("chunk-{}", i);
+            storage.put(&key, data).await.unwrap();
+            chunks.push(ChunkRef {
+                key,
+                size: data.len() as u64,
+                hash: match with_hash {
+                    true => chunk_hash(data),
+                    false => String::new(),
+                },
+            });
+        }
+        chunks
+    }
+
+    async fn collect(storage: Arc<dyn StorageBackend>, chunks: Vec<ChunkRef>, range: ByteRange) -> Result<Vec<u8>, AppError> {
+        let mut data = Vec::new();
+        let mut stream = Box::pin(chunk_stream(storage, chunks, range));
+        while let Some(item) = stream.next().await {
+            data.extend_from_slice(&item?);
+        }
+        Ok(data)
+    }
+
+    #[tokio::test]
+    async fn chunk_stream_across_chunks() {
+        for with_hash in [true, false] {
+            let storage = Arc::new(MemoryStorage::new());
+            let chunks = stored_chunks(&storage, &[b"0123", b"", b"4567", b"89"], with_hash).await;
+            let storage: Arc<dyn StorageBackend> = storage;
+            assert_eq!(collect(storage.clone(), chunks.clone(), range(0, 9)).await.unwrap(), b"0123456789");
+            assert_eq!(collect(storage.clone(), chunks.clone(), range(2, 6)).await.unwrap(), b"23456");
+            assert_eq!(collect(storage.clone(), chunks.clone(), range(4, 7)).await.unwrap(), b"4567");
+            assert_eq!(collect(storage, chunks, range(9, 9)).await.unwrap(), b"9");
+        }
+    }
+
+    #[tokio::test]
+    async fn read_chunk_rejects_corrupted_data() {
+        let storage = MemoryStorage::new();
+        let chunks = stored_chunks(&storage, &[b"0123"], true).await;
+        storage.put("chunk-0", b"0124").await.unwrap();
+        assert!(read_chunk(&storage, &chunks[0], 0, 2).await.is_err());
+        storage.put("chunk-0", b"012").await.unwrap();
+        assert!(read_chunk(&storage, &chunks[0], 0, 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn multi_range_body_length() {
+        let storage = Arc::new(MemoryStorage::new());
+        let chunks = stored_chunks(&storage, &[b"0123", b"4567", b"89"], true).await;
+        let body = MultiRangeBody::new(vec![range(0, 1), range(5, 8)], "text/plain", 10);
+        let content_length = body.content_length;
+        let boundary = body.boundary.clone();
+        let mut data = Vec::new();
+        let mut stream = Box::pin(body.into_stream(storage, chunks));
+        while let Some(item) = stream.next().await {
+            data.extend_from_slice(&item.unwrap());
+        }
+        assert_eq!(data.len() as u64, content_length);
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.contains("Content-Range: bytes 5-8/10\r\n\r\n5678\r\n"));
+        assert!(text.ends_with(&format!("--{}--\r\n", boundary)));
+    }
+}
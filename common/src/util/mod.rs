@@ -7,10 +7,20 @@ pub use self::value_pare::*;
 
 pub mod common_utils;
 pub mod zip_util;
+pub mod chunk_util;
+pub mod digest_util;
+pub mod password_util;
+pub mod sign_util;
+pub mod totp_util;
  pub mod date_util;
 pub mod download_util;
 pub use download_util::*;
 pub use date_util::*;
 pub use common_utils::*;
 pub use zip_util::*;
+pub use chunk_util::*;
+pub use digest_util::*;
+pub use password_util::*;
+pub use sign_util::*;
+pub use totp_util::*;
 
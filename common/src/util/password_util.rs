@@ -0,0 +1,74 @@
+use crate::{build_md5, AppError};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+
+/// 密码校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordMatch {
+    Invalid,
+    Valid,
+    //旧数据的 MD5 摘要匹配，需要升级为 argon2id
+    Legacy,
+}
+
+impl PasswordMatch {
+    pub fn is_match(&self) -> bool {
+        *self != PasswordMatch::Invalid
+    }
+}
+
+/// argon2id 加随机盐，PHC 字符串格式：`$argon2id$v=19$m=...,t=...,p=...$salt$hash`
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => Err(AppError::InternalError(format!("password.hash.error:{}", e))),
+    }
+}
+
+/// 校验密码，兼容旧数据的无盐 MD5
+pub fn verify_password(password: &str, stored: &str) -> PasswordMatch {
+    if stored.starts_with('$') {
+        let hash = match PasswordHash::new(stored) {
+            Ok(hash) => hash,
+            Err(_) => return PasswordMatch::Invalid,
+        };
+        return match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => PasswordMatch::Valid,
+            Err(_) => PasswordMatch::Invalid,
+        };
+    }
+    match !stored.is_empty() && build_md5(password).eq_ignore_ascii_case(stored) {
+        true => PasswordMatch::Legacy,
+        false => PasswordMatch::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn argon2_hash_and_verify() {
+        let hash = hash_password("Secret123").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        // 随机盐，两次结果不同
+        assert_ne!(hash, hash_password("Secret123").unwrap());
+        assert_eq!(verify_password("Secret123", &hash), PasswordMatch::Valid);
+        assert_eq!(verify_password("secret123", &hash), PasswordMatch::Invalid);
+        assert_eq!(verify_password("Secret123", "$argon2id$broken"), PasswordMatch::Invalid);
+    }
+
+    #[test]
+    fn legacy_md5() {
+        let legacy = build_md5("Secret123");
+        assert_eq!(verify_password("Secret123", &legacy), PasswordMatch::Legacy);
+        assert_eq!(verify_password("Secret123", &legacy.to_uppercase()), PasswordMatch::Legacy);
+        assert_eq!(verify_password("Secret124", &legacy), PasswordMatch::Invalid);
+        assert_eq!(verify_password("", ""), PasswordMatch::Invalid);
+        assert!(PasswordMatch::Legacy.is_match());
+        assert!(PasswordMatch::Valid.is_match());
+        assert!(!PasswordMatch::Invalid.is_match());
+    }
+}
@@ -0,0 +1,470 @@
+use crate::{AppError, SessionStore};
+use actix_web::HttpRequest;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGN_ALGORITHM: &str = "FC-HMAC-SHA256";
+pub const SIGN_UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+pub const SIGN_TIMESTAMP_HEADER: &str = "x-fc-timestamp";
+pub const SIGN_NONCE_HEADER: &str = "x-fc-nonce";
+pub const SIGN_CONTENT_SHA256_HEADER: &str = "x-fc-content-sha256";
+//必须参与签名的请求头
+const REQUIRED_HEADERS: [&str; 4] = ["host", SIGN_TIMESTAMP_HEADER, SIGN_NONCE_HEADER, SIGN_CONTENT_SHA256_HEADER];
+const MAX_NONCE_LEN: usize = 128;
+//预签名 URL 的查询参数
+pub const PRESIGN_ALGORITHM: &str = "FC-HMAC-SHA256-PRESIGN";
+pub const PRESIGN_CREDENTIAL: &str = "X-Fc-Credential";
+pub const PRESIGN_EXPIRES: &str = "X-Fc-Expires";
+pub const PRESIGN_METHOD: &str = "X-Fc-Method";
+pub const PRESIGN_SIGNATURE: &str = "X-Fc-Signature";
+//预签名上传的目标目录
+pub const PRESIGN_PATH: &str = "X-Fc-Path";
+//RFC 3986 非保留字符不编码
+const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// 数据接口的请求签名
+///
+/// `Authorization: FC-HMAC-SHA256 Credential=<access_key>, SignedHeaders=host;x-fc-content-sha256;x-fc-nonce;x-fc-timestamp, Signature=<hex>`
+///
+/// 签名为 `hex(HMAC-SHA256(secret_key, string_to_sign))`，string_to_sign 按行拼接：
+/// 算法、请求方法、路径（原样）、规范化的查询串、`name:value` 形式的签名头（每个一行）、
+/// 签名头列表、时间戳（Unix 秒）、nonce、请求体 sha256（十六进制或 `UNSIGNED-PAYLOAD`）
+#[derive(Debug, Clone)]
+pub struct SignedRequest {
+    pub access_key: String,
+    signed_headers: Vec<String>,
+    signature: String,
+    timestamp: i64,
+    nonce: String,
+    content_sha256: String,
+}
+
+impl SignedRequest {
+    /// 解析请求中的签名，没有 Authorization 头时返回 None
+    pub fn from_request(req: &HttpRequest) -> Result<Option<SignedRequest>, AppError> {
+        let value = match req.headers().get("Authorization") {
+            Some(value) => value.to_str().map_err(|_| AppError::NoRight("sign.malformed".to_owned()))?,
+            None => return Ok(None),
+        };
+        let fields = match value.strip_prefix(SIGN_ALGORITHM) {
+            Some(fields) => fields,
+            None => return Err(AppError::NoRight("sign.algorithm.unsupported".to_owned())),
+        };
+        let mut access_key = "";
+        let mut signed_headers = "";
+        let mut signature = "";
+        for field in fields.split(',') {
+            match field.trim().split_once('=') {
+                Some(("Credential", v)) => access_key = v,
+                Some(("SignedHeaders", v)) => signed_headers = v,
+                Some(("Signature", v)) => signature = v,
+                _ => {}
+            }
+        }
+        let signed_headers: Vec<String> = signed_headers.split(';').filter(|h| !h.is_empty()).map(|h| h.to_lowercase()).collect();
+        if access_key.is_empty() || signature.is_empty() || REQUIRED_HEADERS.iter().any(|h| !signed_headers.iter().any(|s| s == h)) {
+            return Err(AppError::NoRight("sign.malformed".to_owned()));
+        }
+        let timestamp = header_value(req, SIGN_TIMESTAMP_HEADER)
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or_else(|| AppError::NoRight("sign.timestamp.invalid".to_owned()))?;
+        let nonce = header_value(req, SIGN_NONCE_HEADER)
+            .filter(|v| !v.is_empty() && v.len() <= MAX_NONCE_LEN)
+            .ok_or_else(|| AppError::NoRight("sign.nonce.invalid".to_owned()))?;
+        let content_sha256 = header_value(req, SIGN_CONTENT_SHA256_HEADER)
+            .ok_or_else(|| AppError::NoRight("sign.content.sha256.missing".to_owned()))?;
+        Ok(Some(SignedRequest {
+            access_key: access_key.to_string(),
+            signed_headers,
+            signature: signature.to_lowercase(),
+            timestamp,
+            nonce,
+            content_sha256,
+        }))
+    }
+
+    pub fn string_to_sign(&self, req: &HttpRequest) -> String {
+        let mut canonical_headers = String::new();
+        for name in &self.signed_headers {
+            let mut values: Vec<String> = req
+                .headers()
+                .get_all(name.as_str())
+                .filter_map(|v| v.to_str().ok())
+                .map(|v| v.split_whitespace().collect::<Vec<&str>>().join(" "))
+                .collect();
+            // HTTP/2 请求没有 host 头
+            if values.is_empty() && name == "host" {
+                values.push(req.connection_info().host().to_string());
+            }
+            canonical_headers.push_str(&format!("{}:{}\n", name, values.join(",")));
+        }
+        format!(
+            "{}\n{}\n{}\n{}\n{}{}\n{}\n{}\n{}",
+            SIGN_ALGORITHM,
+            req.method().as_str(),
+            req.uri().path(),
+            canonical_query(req.query_string(), false),
+            canonical_headers,
+            self.signed_headers.join(";"),
+            self.timestamp,
+            self.nonce,
+            self.content_sha256
+        )
+    }
+}
+
+/// 请求体的校验方式，由 `x-fc-content-sha256` 决定
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BodyHash {
+    Unsigned,
+    Sha256(String),
+}
+
+impl BodyHash {
+    /// 读取完请求体后校验
+    pub fn verify(&self, body_sha256: &str) -> Result<(), AppError> {
+        match self {
+            BodyHash::Sha256(expected) if expected != body_sha256 => Err(AppError::NoRight("sign.content.sha256.mismatch".to_owned())),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn verify_body(&self, body: &[u8]) -> Result<(), AppError> {
+        match self {
+            BodyHash::Unsigned => Ok(()),
+            BodyHash::Sha256(_) => self.verify(&hex::encode(Sha256::digest(body))),
+        }
+    }
+}
+
+/// 校验签名、时间窗口与 nonce，nonce 在时间窗口内只能使用一次
+///
+/// nonce 记录在会话存储中，redis 时多个实例共享
+pub struct RequestVerifier {
+    max_skew: i64,
+    store: Arc<dyn SessionStore>,
+}
+
+impl RequestVerifier {
+    pub fn new(max_skew: Duration, store: Arc<dyn SessionStore>) -> Self {
+        Self {
+            max_skew: max_skew.as_secs().max(1) as i64,
+            store,
+        }
+    }
+
+    pub async fn verify(&self, req: &HttpRequest, signed: &SignedRequest, secret_key: &str) -> Result<BodyHash, AppError> {
+        if (Utc::now().timestamp() - signed.timestamp).abs() > self.max_skew {
+            return Err(AppError::NoRight("sign.time.skewed".to_owned()));
+        }
+        verify_hmac(secret_key.as_bytes(), signed.string_to_sign(req).as_bytes(), &signed.signature)?;
+        // 签名通过后再记录 nonce，避免伪造请求占用；超出时间窗口的请求直接拒绝，nonce 只需保留两个窗口
+        let ttl = Duration::from_secs(self.max_skew as u64 * 2);
+        if !self.store.put_nonce(&format!("{}:{}", signed.access_key, signed.nonce), ttl).await? {
+            return Err(AppError::NoRight("sign.nonce.replayed".to_owned()));
+        }
+        match signed.content_sha256.as_str() {
+            SIGN_UNSIGNED_PAYLOAD => Ok(BodyHash::Unsigned),
+            hash if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => Ok(BodyHash::Sha256(hash.to_lowercase())),
+            _ => Err(AppError::NoRight("sign.content.sha256.invalid".to_owned())),
+        }
+    }
+}
+
+/// 预签名 URL，在有效期内无需其他凭证即可按指定方法访问
+///
+/// `<path>?X-Fc-Credential=<access_key>&X-Fc-Expires=<unix 秒>&X-Fc-Method=<method>&...&X-Fc-Signature=<hex>`
+///
+/// string_to_sign 按行拼接：算法、请求方法、路径、去掉签名后规范化的查询串，
+/// 查询串中的其他参数（如上传目录）同样受签名保护
+#[derive(Debug, Clone)]
+pub struct PresignedRequest {
+    pub access_key: String,
+    pub expires: i64,
+    method: String,
+    signature: String,
+    params: Vec<(String, String)>,
+}
+
+impl PresignedRequest {
+    /// 生成预签名 URL 的路径与查询串，`params` 为需要一同签名的业务参数
+    pub fn sign(method: &str, path: &str, params: &[(&str, String)], access_key: &str, secret_key: &str, expires: i64) -> String {
+        let mut pairs: Vec<(String, String)> = params.iter().map(|(name, value)| (name.to_string(), value.clone())).collect();
+        pairs.push((PRESIGN_CREDENTIAL.to_string(), access_key.to_string()));
+        pairs.push((PRESIGN_EXPIRES.to_string(), expires.to_string()));
+        pairs.push((PRESIGN_METHOD.to_string(), method.to_uppercase()));
+        pairs.sort();
+        let query = encode_query(&pairs);
+        let string_to_sign = format!("{}\n{}\n{}\n{}", PRESIGN_ALGORITHM, method.to_uppercase(), path, query);
+        format!("{}?{}&{}={}", path, query, PRESIGN_SIGNATURE, sign_hmac(secret_key, &string_to_sign))
+    }
+
+    /// 解析查询串中的签名，没有 `X-Fc-Signature` 时返回 None
+    pub fn from_request(req: &HttpRequest) -> Result<Option<PresignedRequest>, AppError> {
+        let params = query_pairs(req.query_string());
+        let find = |name: &str| params.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+        let signature = match find(PRESIGN_SIGNATURE) {
+            Some(signature) => signature,
+            None => return Ok(None),
+        };
+        let access_key = find(PRESIGN_CREDENTIAL).filter(|v| !v.is_empty());
+        let method = find(PRESIGN_METHOD).filter(|v| !v.is_empty());
+        let expires = find(PRESIGN_EXPIRES).and_then(|v| v.parse::<i64>().ok());
+        match (access_key, method, expires) {
+            (Some(access_key), Some(method), Some(expires)) => Ok(Some(PresignedRequest {
+                access_key,
+                expires,
+                method,
+                signature: signature.to_lowercase(),
+                params,
+            })),
+            _ => Err(AppError::NoRight("sign.malformed".to_owned())),
+        }
+    }
+
+    /// 读取签名中的业务参数
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn verify(&self, req: &HttpRequest, secret_key: &str) -> Result<(), AppError> {
+        if Utc::now().timestamp() > self.expires {
+            return Err(AppError::NoRight("sign.expired".to_owned()));
+        }
+        if !self.method.eq_ignore_ascii_case(req.method().as_str()) {
+            return Err(AppError::NoRight("sign.method.mismatch".to_owned()));
+        }
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            PRESIGN_ALGORITHM,
+            req.method().as_str(),
+            req.uri().path(),
+            canonical_query(req.query_string(), true)
+        );
+        verify_hmac(secret_key.as_bytes(), string_to_sign.as_bytes(), &self.signature)
+    }
+}
+
+/// 客户端签名，`hex(HMAC-SHA256(secret_key, string_to_sign))`
+pub fn sign_hmac(secret_key: &str, string_to_sign: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(secret_key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(string_to_sign.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn verify_hmac(key: &[u8], data: &[u8], signature: &str) -> Result<(), AppError> {
+    let expected = hex::decode(signature).map_err(|_| AppError::NoRight("sign.mismatch".to_owned()))?;
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.verify_slice(&expected).map_err(|_| AppError::NoRight("sign.mismatch".to_owned()))
+}
+
+fn header_value(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers().get(name).and_then(|v| v.to_str().ok()).map(|v| v.trim().to_string())
+}
+
+/// 解析查询串，参数名和值均已解码
+fn query_pairs(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode_str(&name.replace('+', " ")).decode_utf8_lossy().to_string(),
+                percent_decode_str(&value.replace('+', " ")).decode_utf8_lossy().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn encode_query(pairs: &[(String, String)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", utf8_percent_encode(name, QUERY_ENCODE_SET), utf8_percent_encode(value, QUERY_ENCODE_SET)))
+        .collect::<Vec<String>>()
+        .join("&")
+}
+
+/// 解码后重新编码并排序，与客户端的编码方式无关
+fn canonical_query(query: &str, presigned: bool) -> String {
+    let mut pairs: Vec<(String, String)> = query_pairs(query)
+        .into_iter()
+        .filter(|(name, _)| !(presigned && name == PRESIGN_SIGNATURE))
+        .collect();
+    pairs.sort();
+    encode_query(&pairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MokaSessionStore;
+    use actix_web::http::Method;
+    use actix_web::test::TestRequest;
+
+    const ACCESS_KEY: &str = "ak";
+    const SECRET_KEY: &str = "sk";
+    const SIGNED_HEADERS: &str = "host;x-fc-content-sha256;x-fc-nonce;x-fc-timestamp";
+
+    fn denied<T: std::fmt::Debug>(result: Result<T, AppError>) -> String {
+        match result {
+            Err(AppError::NoRight(msg)) => msg,
+            other => panic!("expected NoRight, got {:?}", other),
+        }
+    }
+
+    /// 按客户端的方式签名，`sign_uri` 与实际请求不同时用于模拟篡改
+    fn signed_request(sign_uri: &str, uri: &str, timestamp: i64, nonce: &str, content_sha256: &str) -> HttpRequest {
+        let (path, query) = sign_uri.split_once('?').unwrap_or((sign_uri, ""));
+        let string_to_sign = format!(
+            "{}\nPOST\n{}\n{}\nhost:api.example.com\nx-fc-content-sha256:{}\nx-fc-nonce:{}\nx-fc-timestamp:{}\n{}\n{}\n{}\n{}",
+            SIGN_ALGORITHM,
+            path,
+            canonical_query(query, false),
+            content_sha256,
+            nonce,
+            timestamp,
+            SIGNED_HEADERS,
+            timestamp,
+            nonce,
+            content_sha256
+        );
+        let authorization = format!(
+            "{} Credential={}, SignedHeaders={}, Signature={}",
+            SIGN_ALGORITHM,
+            ACCESS_KEY,
+            SIGNED_HEADERS,
+            sign_hmac(SECRET_KEY, &string_to_sign)
+        );
+        TestRequest::with_uri(uri)
+            .method(Method::POST)
+            .insert_header(("Host", "api.example.com"))
+            .insert_header((SIGN_TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((SIGN_NONCE_HEADER, nonce))
+            .insert_header((SIGN_CONTENT_SHA256_HEADER, content_sha256))
+            .insert_header(("Authorization", authorization))
+            .to_http_request()
+    }
+
+    fn verifier() -> RequestVerifier {
+        RequestVerifier::new(Duration::from_secs(300), Arc::new(MokaSessionStore::new(Duration::from_secs(60), 100)))
+    }
+
+    #[test]
+    fn string_to_sign_uses_canonical_query_and_signed_headers() {
+        let req = signed_request("/upload/b", "/upload/b?b=2&a=1%201", 1700000000, "n1", SIGN_UNSIGNED_PAYLOAD);
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(
+            signed.string_to_sign(&req),
+            "FC-HMAC-SHA256\nPOST\n/upload/b\na=1%201&b=2\nhost:api.example.com\nx-fc-content-sha256:UNSIGNED-PAYLOAD\n\
+             x-fc-nonce:n1\nx-fc-timestamp:1700000000\nhost;x-fc-content-sha256;x-fc-nonce;x-fc-timestamp\n1700000000\nn1\nUNSIGNED-PAYLOAD"
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_accepts_signed_request_once() {
+        let verifier = verifier();
+        let now = Utc::now().timestamp();
+        let req = signed_request("/upload/b?x=1", "/upload/b?x=1", now, "n1", SIGN_UNSIGNED_PAYLOAD);
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(verifier.verify(&req, &signed, SECRET_KEY).await.unwrap(), BodyHash::Unsigned);
+        assert_eq!(denied(verifier.verify(&req, &signed, SECRET_KEY).await), "sign.nonce.replayed");
+    }
+
+    #[tokio::test]
+    async fn verify_rejects_tampered_skewed_and_wrong_key() {
+        let verifier = verifier();
+        let now = Utc::now().timestamp();
+        let req = signed_request("/upload/b?x=1", "/upload/b?x=2", now, "n1", SIGN_UNSIGNED_PAYLOAD);
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(denied(verifier.verify(&req, &signed, SECRET_KEY).await), "sign.mismatch");
+
+        let req = signed_request("/upload/b", "/upload/b", now - 301, "n2", SIGN_UNSIGNED_PAYLOAD);
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(denied(verifier.verify(&req, &signed, SECRET_KEY).await), "sign.time.skewed");
+
+        let req = signed_request("/upload/b", "/upload/b", now, "n3", SIGN_UNSIGNED_PAYLOAD);
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(denied(verifier.verify(&req, &signed, "other").await), "sign.mismatch");
+        // 签名失败的请求不占用 nonce
+        assert!(verifier.verify(&req, &signed, SECRET_KEY).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_returns_body_hash() {
+        let body = b"hello";
+        let hash = hex::encode(Sha256::digest(body));
+        let req = signed_request("/upload/b", "/upload/b", Utc::now().timestamp(), "n1", &hash.to_uppercase());
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        let body_hash = verifier().verify(&req, &signed, SECRET_KEY).await.unwrap();
+        assert_eq!(body_hash, BodyHash::Sha256(hash));
+        assert!(body_hash.verify_body(body).is_ok());
+        assert_eq!(denied(body_hash.verify_body(b"other")), "sign.content.sha256.mismatch");
+        assert!(BodyHash::Unsigned.verify_body(b"other").is_ok());
+    }
+
+    #[tokio::test]
+    async fn verify_rejects_invalid_content_sha256() {
+        let req = signed_request("/upload/b", "/upload/b", Utc::now().timestamp(), "n1", "abc");
+        let signed = SignedRequest::from_request(&req).unwrap().unwrap();
+        assert_eq!(denied(verifier().verify(&req, &signed, SECRET_KEY).await), "sign.content.sha256.invalid");
+    }
+
+    #[test]
+    fn from_request_requires_all_signed_headers() {
+        let req = TestRequest::with_uri("/upload/b")
+            .insert_header(("Authorization", "FC-HMAC-SHA256 Credential=ak, SignedHeaders=host;x-fc-nonce, Signature=00"))
+            .to_http_request();
+        assert_eq!(denied(SignedRequest::from_request(&req)), "sign.malformed");
+        let req = TestRequest::with_uri("/upload/b").to_http_request();
+        assert!(SignedRequest::from_request(&req).unwrap().is_none());
+    }
+
+    #[test]
+    fn verify_hmac_rejects_malformed_signature() {
+        let signature = sign_hmac(SECRET_KEY, "data");
+        assert!(verify_hmac(SECRET_KEY.as_bytes(), b"data", &signature).is_ok());
+        assert_eq!(denied(verify_hmac(SECRET_KEY.as_bytes(), b"data", "not-hex")), "sign.mismatch");
+        assert_eq!(denied(verify_hmac(SECRET_KEY.as_bytes(), b"data", &signature[..10])), "sign.mismatch");
+    }
+
+    fn presigned_request(method: Method, url: &str) -> (HttpRequest, PresignedRequest) {
+        let req = TestRequest::with_uri(url).method(method).to_http_request();
+        let presigned = PresignedRequest::from_request(&req).unwrap().unwrap();
+        (req, presigned)
+    }
+
+    #[test]
+    fn presigned_request_round_trips() {
+        let expires = Utc::now().timestamp() + 60;
+        let url = PresignedRequest::sign("POST", "/upload/b", &[(PRESIGN_PATH, "a b/c".to_string())], ACCESS_KEY, SECRET_KEY, expires);
+        let (req, presigned) = presigned_request(Method::POST, &url);
+        assert_eq!(presigned.access_key, ACCESS_KEY);
+        assert_eq!(presigned.param(PRESIGN_PATH), Some("a b/c"));
+        assert!(presigned.verify(&req, SECRET_KEY).is_ok());
+        assert_eq!(denied(presigned.verify(&req, "other")), "sign.mismatch");
+    }
+
+    #[test]
+    fn presigned_request_rejects_tampering() {
+        let expires = Utc::now().timestamp() + 60;
+        let url = PresignedRequest::sign("POST", "/upload/b", &[(PRESIGN_PATH, "a".to_string())], ACCESS_KEY, SECRET_KEY, expires);
+        let (req, presigned) = presigned_request(Method::GET, &url);
+        assert_eq!(denied(presigned.verify(&req, SECRET_KEY)), "sign.method.mismatch");
+        let (req, presigned) = presigned_request(Method::POST, &url.replace("X-Fc-Path=a", "X-Fc-Path=b"));
+        assert_eq!(denied(presigned.verify(&req, SECRET_KEY)), "sign.mismatch");
+        let (req, presigned) = presigned_request(Method::POST, &url.replace("/upload/b", "/upload/c"));
+        assert_eq!(denied(presigned.verify(&req, SECRET_KEY)), "sign.mismatch");
+
+        let url = PresignedRequest::sign("GET", "/download/1", &[], ACCESS_KEY, SECRET_KEY, Utc::now().timestamp() - 1);
+        let (req, presigned) = presigned_request(Method::GET, &url);
+        assert_eq!(denied(presigned.verify(&req, SECRET_KEY)), "sign.expired");
+    }
+}
@@ -0,0 +1,135 @@
+use crate::AppError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const NONCE_LEN: usize = 12;
+//恢复码不含容易混淆的 0/O、1/I
+const RECOVERY_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const RECOVERY_CODE_LEN: usize = 10;
+
+/// TOTP 密钥的加密，AES-256-GCM，保存为 base64(nonce || 密文)
+pub struct TotpCipher {
+    cipher: Aes256Gcm,
+}
+
+impl TotpCipher {
+    /// `key` 为 base64 编码的 32 字节密钥
+    pub fn new(key: &str) -> Result<Self, AppError> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|_| AppError::InternalError("totp.encrypt.key.invalid".to_owned()))?;
+        let cipher = Aes256Gcm::new_from_slice(&key)
+            .map_err(|_| AppError::InternalError("totp.encrypt.key.invalid".to_owned()))?;
+        Ok(Self { cipher })
+    }
+
+    pub fn encrypt(&self, secret: &[u8]) -> Result<String, AppError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut value = nonce.to_vec();
+        let encrypted = self
+            .cipher
+            .encrypt(&nonce, secret)
+            .map_err(|_| AppError::InternalError("totp.encrypt.error".to_owned()))?;
+        value.extend_from_slice(&encrypted);
+        Ok(STANDARD.encode(value))
+    }
+
+    pub fn decrypt(&self, value: &str) -> Result<Vec<u8>, AppError> {
+        let value = STANDARD
+            .decode(value)
+            .map_err(|_| AppError::InternalError("totp.decrypt.error".to_owned()))?;
+        if value.len() <= NONCE_LEN {
+            return Err(AppError::InternalError("totp.decrypt.error".to_owned()));
+        }
+        let (nonce, encrypted) = value.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| AppError::InternalError("totp.decrypt.error".to_owned()))
+    }
+}
+
+/// 160 位随机密钥
+pub fn generate_totp_secret() -> Result<Vec<u8>, AppError> {
+    Secret::generate_secret()
+        .to_bytes()
+        .map_err(|e| AppError::InternalError(format!("totp.secret.error:{:?}", e)))
+}
+
+/// SHA1、6 位、30 秒，允许前后各一个时间步的偏差，兼容常见验证器
+pub fn build_totp(secret: Vec<u8>, issuer: &str, account_name: &str) -> Result<TOTP, AppError> {
+    // otpauth URI 中 `:` 用于分隔服务名与账号
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(issuer.replace(':', "_")),
+        account_name.replace(':', "_"),
+    )
+    .map_err(|e| AppError::InternalError(format!("totp.build.error:{}", e)))
+}
+
+/// 校验当前时间的验证码，返回匹配的时间步，用于拒绝重复使用
+pub fn verify_totp(totp: &TOTP, code: &str) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    totp_step_at(totp, code.trim(), now)
+}
+
+fn totp_step_at(totp: &TOTP, code: &str, time: u64) -> Option<i64> {
+    let step = time / totp.step;
+    // 与 build_totp 的偏差一致，前后各一个时间步
+    (step.saturating_sub(totp.skew as u64)..=step + totp.skew as u64)
+        .find(|step| constant_time_eq(totp.generate(step * totp.step).as_bytes(), code.as_bytes()))
+        .map(|step| step as i64)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// 一次性恢复码，形如 `ABCDE-FGHJK`
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    let mut rng = rand::rng();
+    (0..count)
+        .map(|_| {
+            let code: String = (0..RECOVERY_CODE_LEN)
+                .map(|_| RECOVERY_CODE_CHARS[rng.random_range(0..RECOVERY_CODE_CHARS.len())] as char)
+                .collect();
+            format!("{}-{}", &code[..RECOVERY_CODE_LEN / 2], &code[RECOVERY_CODE_LEN / 2..])
+        })
+        .collect()
+}
+
+/// 恢复码只保存摘要，忽略大小写和分隔符
+pub fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    hex::encode(Sha256::digest(code.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totp_step_accepts_adjacent_steps_only() {
+        let totp = build_totp(vec![7u8; 20], "file-cloud", "alice").unwrap();
+        let time = 1_700_000_000u64;
+        let step = (time / 30) as i64;
+        assert_eq!(totp_step_at(&totp, &totp.generate(time), time), Some(step));
+        assert_eq!(totp_step_at(&totp, &totp.generate(time - 30), time), Some(step - 1));
+        assert_eq!(totp_step_at(&totp, &totp.generate(time + 30), time), Some(step + 1));
+        assert_eq!(totp_step_at(&totp, &totp.generate(time - 60), time), None);
+        assert_eq!(totp_step_at(&totp, "", time), None);
+    }
+}
@@ -11,6 +11,7 @@ pub enum ValueType {
     LONG,
     ENUM,
 }
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct ValueItem {
     value_type: ValueType,
@@ -1,6 +1,9 @@
 #![allow(unused_variables)]
 #![allow(dead_code)]
+use crate::AppError;
 use anyhow::Context;
+use bytes::Bytes;
+use chrono::{Datelike, NaiveDateTime, Timelike};
 use std::io::prelude::*;
 use zip::{result::ZipError, write::SimpleFileOptions};
 
@@ -62,3 +65,270 @@ pub fn do_zip(src_dir: &Path, dst_file: &Path, method: zip::CompressionMethod) -
 
     Ok(())
 }
+
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+//通用标志：bit 3 使用数据描述符，bit 11 文件名为 UTF-8
+const ZIP_FLAGS: u16 = 0x0008 | 0x0800;
+
+struct ZipRecord {
+    name: String,
+    size: u64,
+    crc: u32,
+    offset: u64,
+    zip64: bool,
+    dos_time: u16,
+    dos_date: u16,
+}
+
+/// 流式 ZIP 写入，不压缩（STORE），不需要 Seek，超过 4GB 时自动使用 zip64
+///
+/// 只生成 ZIP 结构的字节，文件内容由调用方直接输出，内存中只保留中央目录记录
+pub struct ZipStreamWriter {
+    offset: u64,
+    records: Vec<ZipRecord>,
+    current: Option<(ZipRecord, crc32fast::Hasher, u64)>,
+}
+
+impl Default for ZipStreamWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZipStreamWriter {
+    pub fn new() -> Self {
+        Self {
+            offset: 0,
+            records: Vec::new(),
+            current: None,
+        }
+    }
+
+    /// 开始写入文件，返回本地文件头；`size` 必须与随后写入的数据长度一致
+    pub fn start_file(&mut self, name: &str, size: u64, modified: &NaiveDateTime) -> Result<Bytes, AppError> {
+        if self.current.is_some() {
+            return Err(AppError::InternalError("zip.file.not.finished".to_owned()));
+        }
+        let (dos_time, dos_date) = dos_date_time(modified);
+        let record = ZipRecord {
+            name: name.to_string(),
+            size,
+            crc: 0,
+            offset: self.offset,
+            zip64: size >= ZIP64_LIMIT || self.offset >= ZIP64_LIMIT,
+            dos_time,
+            dos_date,
+        };
+        let mut buf = Vec::with_capacity(50 + name.len());
+        put_u32(&mut buf, 0x0403_4b50);
+        put_u16(&mut buf, version_needed(record.zip64));
+        put_u16(&mut buf, ZIP_FLAGS);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, record.dos_time);
+        put_u16(&mut buf, record.dos_date);
+        // crc 与大小写在数据描述符中
+        put_u32(&mut buf, 0);
+        let size32 = if record.zip64 { ZIP64_LIMIT as u32 } else { 0 };
+        put_u32(&mut buf, size32);
+        put_u32(&mut buf, size32);
+        put_u16(&mut buf, name.len() as u16);
+        put_u16(&mut buf, if record.zip64 { 20 } else { 0 });
+        buf.extend_from_slice(name.as_bytes());
+        if record.zip64 {
+            put_u16(&mut buf, 0x0001);
+            put_u16(&mut buf, 16);
+            put_u64(&mut buf, 0);
+            put_u64(&mut buf, 0);
+        }
+        self.offset += buf.len() as u64;
+        self.current = Some((record, crc32fast::Hasher::new(), 0));
+        Ok(Bytes::from(buf))
+    }
+
+    /// 记录文件内容，数据本身由调用方输出
+    pub fn write(&mut self, data: &[u8]) {
+        if let Some((_, hasher, written)) = self.current.as_mut() {
+            hasher.update(data);
+            *written += data.len() as u64;
+            self.offset += data.len() as u64;
+        }
+    }
+
+    /// 结束当前文件，返回数据描述符
+    pub fn finish_file(&mut self) -> Result<Bytes, AppError> {
+        let (mut record, hasher, written) = match self.current.take() {
+            Some(current) => current,
+            None => return Err(AppError::InternalError("zip.file.not.started".to_owned())),
+        };
+        if written != record.size {
+            return Err(AppError::InternalError(format!("zip.file.size.mismatch:{}", record.name)));
+        }
+        record.crc = hasher.finalize();
+        let mut buf = Vec::with_capacity(24);
+        put_u32(&mut buf, 0x0807_4b50);
+        put_u32(&mut buf, record.crc);
+        if record.zip64 {
+            put_u64(&mut buf, record.size);
+            put_u64(&mut buf, record.size);
+        } else {
+            put_u32(&mut buf, record.size as u32);
+            put_u32(&mut buf, record.size as u32);
+        }
+        self.offset += buf.len() as u64;
+        self.records.push(record);
+        Ok(Bytes::from(buf))
+    }
+
+    /// 写入中央目录与结束记录
+    pub fn finish(self) -> Result<Bytes, AppError> {
+        if self.current.is_some() {
+            return Err(AppError::InternalError("zip.file.not.finished".to_owned()));
+        }
+        let cd_offset = self.offset;
+        let mut buf = Vec::new();
+        for record in &self.records {
+            put_u32(&mut buf, 0x0201_4b50);
+            // 3 表示 unix
+            put_u16(&mut buf, (3 << 8) | 45);
+            put_u16(&mut buf, version_needed(record.zip64));
+            put_u16(&mut buf, ZIP_FLAGS);
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, record.dos_time);
+            put_u16(&mut buf, record.dos_date);
+            put_u32(&mut buf, record.crc);
+            if record.zip64 {
+                put_u32(&mut buf, ZIP64_LIMIT as u32);
+                put_u32(&mut buf, ZIP64_LIMIT as u32);
+            } else {
+                put_u32(&mut buf, record.size as u32);
+                put_u32(&mut buf, record.size as u32);
+            }
+            put_u16(&mut buf, record.name.len() as u16);
+            put_u16(&mut buf, if record.zip64 { 28 } else { 0 });
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, 0);
+            put_u16(&mut buf, 0);
+            // -rw-r--r--
+            put_u32(&mut buf, 0o100644 << 16);
+            put_u32(&mut buf, if record.zip64 { ZIP64_LIMIT as u32 } else { record.offset as u32 });
+            buf.extend_from_slice(record.name.as_bytes());
+            if record.zip64 {
+                put_u16(&mut buf, 0x0001);
+                put_u16(&mut buf, 24);
+                put_u64(&mut buf, record.size);
+                put_u64(&mut buf, record.size);
+                put_u64(&mut buf, record.offset);
+            }
+        }
+        let cd_size = buf.len() as u64;
+        let entries = self.records.len() as u64;
+        if entries >= 0xFFFF || cd_offset >= ZIP64_LIMIT || cd_size >= ZIP64_LIMIT {
+            let zip64_eocd_offset = cd_offset + cd_size;
+            put_u32(&mut buf, 0x0606_4b50);
+            put_u64(&mut buf, 44);
+            put_u16(&mut buf, (3 << 8) | 45);
+            put_u16(&mut buf, 45);
+            put_u32(&mut buf, 0);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, entries);
+            put_u64(&mut buf, entries);
+            put_u64(&mut buf, cd_size);
+            put_u64(&mut buf, cd_offset);
+            put_u32(&mut buf, 0x0706_4b50);
+            put_u32(&mut buf, 0);
+            put_u64(&mut buf, zip64_eocd_offset);
+            put_u32(&mut buf, 1);
+        }
+        put_u32(&mut buf, 0x0605_4b50);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, 0);
+        put_u16(&mut buf, entries.min(0xFFFF) as u16);
+        put_u16(&mut buf, entries.min(0xFFFF) as u16);
+        put_u32(&mut buf, cd_size.min(ZIP64_LIMIT) as u32);
+        put_u32(&mut buf, cd_offset.min(ZIP64_LIMIT) as u32);
+        put_u16(&mut buf, 0);
+        Ok(Bytes::from(buf))
+    }
+}
+
+fn version_needed(zip64: bool) -> u16 {
+    if zip64 { 45 } else { 20 }
+}
+
+/// DOS 时间格式，最早为 1980 年
+fn dos_date_time(time: &NaiveDateTime) -> (u16, u16) {
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = ((time.hour() as u16) << 11) | ((time.minute() as u16) << 5) | ((time.second() as u16) / 2);
+    let dos_date = (((time.year() - 1980).min(127) as u16) << 9) | ((time.month() as u16) << 5) | (time.day() as u16);
+    (dos_time, dos_date)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::io::Cursor;
+
+    fn modified() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2025, 6, 1).unwrap().and_hms_opt(12, 30, 20).unwrap()
+    }
+
+    #[test]
+    fn stream_writer_round_trip() {
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"hello"), ("目录/b.bin", &[0u8, 1, 2, 3, 255]), ("empty", b"")];
+        let mut writer = ZipStreamWriter::new();
+        let mut out = Vec::new();
+        for (name, data) in files {
+            out.extend_from_slice(&writer.start_file(name, data.len() as u64, &modified()).unwrap());
+            // 分两次写入
+            let (head, tail) = data.split_at(data.len() / 2);
+            writer.write(head);
+            out.extend_from_slice(head);
+            writer.write(tail);
+            out.extend_from_slice(tail);
+            out.extend_from_slice(&writer.finish_file().unwrap());
+        }
+        out.extend_from_slice(&writer.finish().unwrap());
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(out)).unwrap();
+        assert_eq!(archive.len(), files.len());
+        for (name, data) in files {
+            let mut file = archive.by_name(name).unwrap();
+            assert_eq!(file.compression(), zip::CompressionMethod::Stored);
+            let mut content = Vec::new();
+            file.read_to_end(&mut content).unwrap();
+            assert_eq!(content, data);
+            let time = file.last_modified().unwrap();
+            assert_eq!((time.year(), time.month(), time.day()), (2025, 6, 1));
+            assert_eq!((time.hour(), time.minute(), time.second()), (12, 30, 20));
+        }
+    }
+
+    #[test]
+    fn stream_writer_rejects_misuse() {
+        let mut writer = ZipStreamWriter::new();
+        assert!(writer.finish_file().is_err());
+        writer.start_file("a", 3, &modified()).unwrap();
+        assert!(writer.start_file("b", 1, &modified()).is_err());
+        writer.write(b"ab");
+        assert!(writer.finish_file().is_err());
+
+        let mut writer = ZipStreamWriter::new();
+        writer.start_file("a", 1, &modified()).unwrap();
+        assert!(writer.finish().is_err());
+    }
+}
@@ -0,0 +1,7 @@
+DROP TABLE IF EXISTS path_del_task;
+DROP TABLE IF EXISTS file_info;
+DROP TABLE IF EXISTS path_info;
+DROP VIEW IF EXISTS user_bucket_right;
+DROP TABLE IF EXISTS user_bucket;
+DROP TABLE IF EXISTS bucket;
+DROP TABLE IF EXISTS user_info;
@@ -0,0 +1,97 @@
+-- 用户、bucket 与目录、文件
+-- 表已存在时直接失败，不在结构不一致的旧库上标记为已迁移
+CREATE TABLE user_info (
+    id BIGINT NOT NULL,
+    is_admin TINYINT(1) NOT NULL DEFAULT 0,
+    user_name VARCHAR(64) NOT NULL,
+    password VARCHAR(255) NOT NULL,
+    access_key VARCHAR(64) NOT NULL,
+    secret_key VARCHAR(255) NOT NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_user_info_user_name (user_name),
+    UNIQUE KEY uk_user_info_access_key (access_key)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE bucket (
+    id BIGINT NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    quota BIGINT NOT NULL DEFAULT 0,
+    current_quota BIGINT NOT NULL DEFAULT 0,
+    pub_read TINYINT(1) NOT NULL DEFAULT 0,
+    pub_write TINYINT(1) NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_bucket_name (name)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE user_bucket (
+    id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    bucket_id BIGINT NOT NULL,
+    user_right INT NOT NULL DEFAULT 0,
+    permissions INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_user_bucket (user_id, bucket_id),
+    KEY idx_user_bucket_bucket_id (bucket_id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+-- 旧版按用户密钥查询 bucket 权限
+CREATE VIEW user_bucket_right AS
+SELECT
+    user_info.access_key,
+    user_info.secret_key,
+    bucket.name AS bucket_name,
+    user_bucket.user_right AS `right`
+FROM user_bucket
+INNER JOIN user_info ON user_bucket.user_id = user_info.id
+INNER JOIN bucket ON user_bucket.bucket_id = bucket.id;
+
+CREATE TABLE path_info (
+    id BIGINT NOT NULL,
+    bucket_id BIGINT NOT NULL,
+    root TINYINT(1) NOT NULL DEFAULT 0,
+    path VARCHAR(128) NOT NULL,
+    parent BIGINT NOT NULL DEFAULT 0,
+    full_path VARCHAR(512) NOT NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    -- 同一目录下不能重名；已删除目录下待清理的子目录 parent 不同，不影响重新创建同名目录
+    UNIQUE KEY uk_path_info_name (bucket_id, parent, path),
+    KEY idx_path_info_full_path (bucket_id, full_path)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE file_info (
+    id BIGINT NOT NULL,
+    root TINYINT(1) NOT NULL DEFAULT 0,
+    bucket_id BIGINT NOT NULL,
+    path_ref BIGINT NOT NULL DEFAULT 0,
+    name VARCHAR(128) NOT NULL,
+    full_path VARCHAR(512) NOT NULL DEFAULT '',
+    file_type ENUM('image', 'svg', 'normal', 'text', 'doc', 'script', 'zip', 'video', 'audio', 'excel', 'dir') NOT NULL,
+    items JSON NOT NULL,
+    image_type ENUM('webp', 'svg', 'gif', 'jpg', 'jpeg', 'png', 'tiff', 'tif', 'bmp', 'none') NOT NULL,
+    size INT UNSIGNED NOT NULL DEFAULT 0,
+    sha256 CHAR(64) NOT NULL DEFAULT '',
+    md5 CHAR(32) NOT NULL DEFAULT '',
+    -- 0 待生成 1 已生成 2 失败 3 不生成
+    thumbnail_status INT NOT NULL DEFAULT 0,
+    thumbnails JSON NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_file_info_key (bucket_id, full_path, name),
+    KEY idx_file_info_path_ref (path_ref),
+    KEY idx_file_info_thumbnail (thumbnail_status, id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE path_del_task (
+    id BIGINT NOT NULL,
+    path_id BIGINT NOT NULL,
+    bucket_id BIGINT NOT NULL,
+    full_path VARCHAR(512) NOT NULL,
+    del_file_status TINYINT(1) NOT NULL DEFAULT 0,
+    del_path_status TINYINT(1) NOT NULL DEFAULT 0,
+    lock_time DATETIME NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
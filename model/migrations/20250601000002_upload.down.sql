@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS upload_part;
+DROP TABLE IF EXISTS upload_session;
+DROP TABLE IF EXISTS file_chunk;
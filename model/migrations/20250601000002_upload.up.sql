@@ -0,0 +1,33 @@
+-- 内容寻址分片与断点续传
+CREATE TABLE file_chunk (
+    hash CHAR(64) NOT NULL,
+    path VARCHAR(255) NOT NULL,
+    size INT UNSIGNED NOT NULL,
+    ref_count INT NOT NULL DEFAULT 1,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (hash)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE upload_session (
+    id BIGINT NOT NULL,
+    bucket_id BIGINT NOT NULL,
+    path_ref BIGINT NOT NULL DEFAULT 0,
+    full_path VARCHAR(512) NOT NULL DEFAULT '',
+    name VARCHAR(128) NOT NULL,
+    -- 0 上传中 1 已完成 2 已取消
+    status INT NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_upload_session_status (status, create_time)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE upload_part (
+    id BIGINT NOT NULL,
+    upload_id BIGINT NOT NULL,
+    part_number INT NOT NULL,
+    items JSON NOT NULL,
+    size INT UNSIGNED NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_upload_part (upload_id, part_number)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
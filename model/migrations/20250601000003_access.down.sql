@@ -0,0 +1,9 @@
+DROP TABLE IF EXISTS audit_log;
+DROP TABLE IF EXISTS api_key;
+DROP TABLE IF EXISTS user_group_member;
+DROP TABLE IF EXISTS user_group;
+DROP TABLE IF EXISTS path_grant;
+DROP TABLE IF EXISTS user_recovery_code;
+ALTER TABLE user_info
+    DROP COLUMN totp_enabled,
+    DROP COLUMN totp_secret;
@@ -0,0 +1,78 @@
+-- 目录授权、用户组、API 密钥、两步验证与审计
+ALTER TABLE user_info
+    ADD COLUMN totp_secret VARCHAR(255) NOT NULL DEFAULT '' AFTER secret_key,
+    ADD COLUMN totp_enabled TINYINT(1) NOT NULL DEFAULT 0 AFTER totp_secret;
+
+CREATE TABLE user_recovery_code (
+    id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_user_recovery_code (user_id, code_hash)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE path_grant (
+    id BIGINT NOT NULL,
+    bucket_id BIGINT NOT NULL,
+    path_id BIGINT NOT NULL,
+    -- 0 用户 1 用户组
+    subject_type INT NOT NULL,
+    subject_id BIGINT NOT NULL,
+    -- 0 允许 1 拒绝
+    effect INT NOT NULL DEFAULT 0,
+    permissions INT NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_path_grant_path (path_id, subject_type, subject_id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE user_group (
+    id BIGINT NOT NULL,
+    name VARCHAR(64) NOT NULL,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE user_group_member (
+    id BIGINT NOT NULL,
+    group_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_user_group_member (group_id, user_id),
+    KEY idx_user_group_member_user_id (user_id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE api_key (
+    id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL,
+    label VARCHAR(64) NOT NULL DEFAULT '',
+    access_key VARCHAR(64) NOT NULL,
+    secret_key VARCHAR(255) NOT NULL,
+    -- 0 为不限制
+    bucket_id BIGINT NOT NULL DEFAULT 0,
+    read_only TINYINT(1) NOT NULL DEFAULT 0,
+    expire_time DATETIME NULL,
+    last_used_time DATETIME NULL,
+    revoked TINYINT(1) NOT NULL DEFAULT 0,
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    UNIQUE KEY uk_api_key_access_key (access_key),
+    KEY idx_api_key_user_id (user_id)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
+
+CREATE TABLE audit_log (
+    id BIGINT NOT NULL,
+    actor_id BIGINT NOT NULL DEFAULT 0,
+    action VARCHAR(32) NOT NULL,
+    bucket_id BIGINT NOT NULL DEFAULT 0,
+    target_id BIGINT NOT NULL DEFAULT 0,
+    target VARCHAR(512) NOT NULL DEFAULT '',
+    client_ip VARCHAR(64) NOT NULL DEFAULT '',
+    outcome VARCHAR(16) NOT NULL,
+    detail VARCHAR(512) NOT NULL DEFAULT '',
+    create_time DATETIME NOT NULL,
+    PRIMARY KEY (id),
+    KEY idx_audit_log_create_time (create_time),
+    KEY idx_audit_log_actor_id (actor_id, create_time)
+) ENGINE = InnoDB DEFAULT CHARSET = utf8mb4;
@@ -0,0 +1,2 @@
+ALTER TABLE user_info
+    DROP COLUMN totp_last_step;
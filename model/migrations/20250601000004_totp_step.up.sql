@@ -0,0 +1,3 @@
+-- 最后一次使用的 TOTP 时间步，同一验证码只能使用一次
+ALTER TABLE user_info
+    ADD COLUMN totp_last_step BIGINT NOT NULL DEFAULT 0 AFTER totp_enabled;
@@ -0,0 +1,196 @@
+use crate::{AuditLog, BaseRepository, QueryParam, Repository};
+use actix_web::{HttpMessage, HttpRequest};
+use common::{build_snow_id, build_time, client_ip, AppError, OrderType, Page, PageInfo, UserCache};
+use log::error;
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use strum_macros::{AsRefStr, EnumString};
+
+/// 审计的操作类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, AsRefStr)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    LoginUnlock,
+    Upload,
+    UploadAbort,
+    Download,
+    DownloadPath,
+    FileDelete,
+    PathDelete,
+    Mkdir,
+    BucketSave,
+    BucketDelete,
+    UserNew,
+    UserDelete,
+    UserChangeKey,
+    UserViewKey,
+    UserChangePassword,
+    ApiKeyCreate,
+    ApiKeyRevoke,
+    TotpEnable,
+    TotpDisable,
+    TotpReset,
+    TotpRecoveryCodes,
+    RightBind,
+    RightDelete,
+    PathGrantSave,
+    PathGrantDelete,
+    GroupSave,
+    GroupDelete,
+    GroupMember,
+}
+
+/// 一条待写入的审计记录，handler 在执行过程中逐步补充对象信息，结束后按结果写入
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    action: AuditAction,
+    actor_id: i64,
+    bucket_id: i64,
+    target_id: i64,
+    target: String,
+    client_ip: String,
+    outcome: &'static str,
+    detail: String,
+}
+
+impl AuditEntry {
+    pub const SUCCESS: &'static str = "success";
+    pub const DENIED: &'static str = "denied";
+    pub const FAILED: &'static str = "failed";
+
+    /// 认证中间件已放入会话时取会话用户；经可信反向代理部署时取 X-Forwarded-For 中的客户端地址
+    pub fn new(req: &HttpRequest, action: AuditAction) -> Self {
+        let actor_id = req.extensions().get::<UserCache>().map(|user| user.id).unwrap_or_default();
+        let client_ip = client_ip(req);
+        Self {
+            action,
+            actor_id,
+            bucket_id: 0,
+            target_id: 0,
+            target: String::new(),
+            client_ip,
+            outcome: Self::SUCCESS,
+            detail: String::new(),
+        }
+    }
+
+    pub fn actor(&mut self, actor_id: i64) -> &mut Self {
+        self.actor_id = actor_id;
+        self
+    }
+
+    pub fn bucket(&mut self, bucket_id: i64) -> &mut Self {
+        self.bucket_id = bucket_id;
+        self
+    }
+
+    pub fn target(&mut self, target_id: i64, target: &str) -> &mut Self {
+        self.target_id = target_id;
+        self.target = target.chars().take(512).collect();
+        self
+    }
+
+    /// 无权限记为 denied，其他错误记为 failed
+    pub fn outcome<T>(&mut self, result: &Result<T, AppError>) -> &mut Self {
+        match result {
+            Ok(_) => self.succeeded(),
+            Err(e) => self.failed(matches!(e, AppError::NoRight(_)), &e.to_string()),
+        }
+    }
+
+    pub fn succeeded(&mut self) -> &mut Self {
+        self.outcome = Self::SUCCESS;
+        self.detail.clear();
+        self
+    }
+
+    pub fn failed(&mut self, denied: bool, detail: &str) -> &mut Self {
+        self.outcome = match denied {
+            true => Self::DENIED,
+            false => Self::FAILED,
+        };
+        self.detail = detail.chars().take(512).collect();
+        self
+    }
+}
+
+/// 审计日志查询条件，均为可选
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditQuery {
+    pub actor_id: Option<i64>,
+    pub action: Option<AuditAction>,
+    pub bucket_id: Option<i64>,
+    pub target_id: Option<i64>,
+    pub outcome: Option<String>,
+    //yyyy-MM-dd HH:mm:ss
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+}
+
+pub struct AuditLogRepository {
+    pub dao: BaseRepository<AuditLog>,
+}
+
+impl AuditLogRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool, "audit_log"),
+        }
+    }
+
+    /// 写入失败只记录日志，不影响业务请求
+    pub async fn record(&self, entry: &AuditEntry) {
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("id", build_snow_id().to_string());
+        params.insert("actor_id", entry.actor_id.to_string());
+        params.insert("action", entry.action.as_ref().to_string());
+        params.insert("bucket_id", entry.bucket_id.to_string());
+        params.insert("target_id", entry.target_id.to_string());
+        params.insert("target", entry.target.clone());
+        params.insert("client_ip", entry.client_ip.clone());
+        params.insert("outcome", entry.outcome.to_string());
+        params.insert("detail", entry.detail.clone());
+        params.insert("create_time", build_time().await);
+        if let Err(e) = self.dao.insert(params).await {
+            error!("audit log {:?} save error: {}", entry, e);
+        }
+    }
+
+    /// 按时间倒序分页查询
+    pub async fn query(&self, query: &AuditQuery, page: &PageInfo) -> Result<Page<AuditLog>, AppError> {
+        let mut params = vec![];
+        if let Some(actor_id) = query.actor_id {
+            params.push(QueryParam::eq("actor_id", actor_id.to_string().as_str()));
+        }
+        if let Some(action) = query.action {
+            params.push(QueryParam::eq("action", action.as_ref()));
+        }
+        if let Some(bucket_id) = query.bucket_id {
+            params.push(QueryParam::eq("bucket_id", bucket_id.to_string().as_str()));
+        }
+        if let Some(target_id) = query.target_id {
+            params.push(QueryParam::eq("target_id", target_id.to_string().as_str()));
+        }
+        if let Some(outcome) = &query.outcome {
+            params.push(QueryParam::eq("outcome", outcome));
+        }
+        match (&query.start_time, &query.end_time) {
+            (Some(start), Some(end)) => params.push(QueryParam::between("create_time", start, end)),
+            (Some(start), None) => params.push(QueryParam::gt("create_time", start)),
+            (None, Some(end)) => params.push(QueryParam::lt("create_time", end)),
+            (None, None) => {}
+        }
+        let page_info = PageInfo {
+            index: page.index,
+            page_size: page.page_size,
+            order_column: "create_time".to_owned(),
+            order_type: OrderType::DESC,
+        };
+        self.dao.query_by_page(params, &page_info).await
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{ApiKey, BaseRepository, Bucket, PathGrant, PathGrantRepository, PathRepository, QueryParam, Repository, UserBucket, UserGroupRepository, UserInfo};
+use common::{AppError, UserCache};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use strum_macros::{AsRefStr, EnumString};
+
+/// bucket 上的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, AsRefStr)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Action {
+    //下载、列表、缩略图
+    Read,
+    //上传、创建目录
+    Write,
+    //删除文件和目录
+    Delete,
+    //生成预签名地址
+    Share,
+    //管理 bucket 配置和用户授权，拥有全部权限
+    Admin,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [Action::Read, Action::Write, Action::Delete, Action::Share, Action::Admin];
+
+    fn bit(self) -> i32 {
+        match self {
+            Action::Read => 1,
+            Action::Write => 1 << 1,
+            Action::Delete => 1 << 2,
+            Action::Share => 1 << 3,
+            Action::Admin => 1 << 4,
+        }
+    }
+}
+
+/// 用户在 bucket 上的权限集合，按位保存在 `user_bucket.permissions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PermissionSet(i32);
+
+impl PermissionSet {
+    pub const NONE: PermissionSet = PermissionSet(0);
+
+    pub fn from_bits(bits: i32) -> Self {
+        PermissionSet(bits)
+    }
+
+    pub fn from_actions(actions: &[Action]) -> Self {
+        PermissionSet(actions.iter().fold(0, |bits, action| bits | action.bit()))
+    }
+
+    /// 旧版 `user_right`：0 读 1 写 2 读写
+    pub fn from_legacy_right(right: i32) -> Self {
+        match right {
+            0 => Self::from_actions(&[Action::Read]),
+            1 => Self::from_actions(&[Action::Write]),
+            2 => Self::from_actions(&[Action::Read, Action::Write, Action::Delete]),
+            _ => Self::NONE,
+        }
+    }
+
+    /// 写入 `user_right` 的兼容值，既不可读也不可写时为 -1，旧版按无权限处理
+    pub fn legacy_right(&self) -> i32 {
+        match (self.allows(Action::Read), self.allows(Action::Write)) {
+            (true, false) => 0,
+            (false, true) => 1,
+            (true, true) => 2,
+            (false, false) => -1,
+        }
+    }
+
+    pub fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub fn union(self, other: PermissionSet) -> Self {
+        PermissionSet(self.0 | other.0)
+    }
+
+    pub fn remove(self, other: PermissionSet) -> Self {
+        PermissionSet(self.0 & !other.0)
+    }
+
+    pub fn allows(&self, action: Action) -> bool {
+        self.0 & Action::Admin.bit() != 0 || self.0 & action.bit() != 0
+    }
+
+    pub fn actions(&self) -> Vec<Action> {
+        Action::ALL.iter().copied().filter(|action| self.0 & action.bit() != 0).collect()
+    }
+}
+
+impl UserBucket {
+    /// 未迁移的记录 permissions 为 0，按 user_right 换算
+    pub fn permission_set(&self) -> PermissionSet {
+        match self.permissions {
+            0 => PermissionSet::from_legacy_right(self.user_right),
+            bits => PermissionSet::from_bits(bits),
+        }
+    }
+}
+
+/// API 密钥的使用范围，在用户本身的权限之上再做限制
+#[derive(Debug, Clone, Copy)]
+pub struct KeyScope {
+    //0 为不限制
+    pub bucket_id: i64,
+    pub read_only: bool,
+}
+
+impl KeyScope {
+    /// 不在范围内的 bucket 只保留公开权限，只读密钥只保留读权限
+    fn limit(&self, bucket: &Bucket, permissions: PermissionSet) -> PermissionSet {
+        let mut permissions = match self.bucket_id {
+            0 => permissions,
+            bucket_id if bucket_id == bucket.id => permissions,
+            _ => public_permissions(bucket),
+        };
+        if self.read_only {
+            permissions = match permissions.allows(Action::Read) {
+                true => PermissionSet::from_actions(&[Action::Read]),
+                false => PermissionSet::NONE,
+            };
+        }
+        permissions
+    }
+}
+
+/// 请求的用户，控制台会话与数据接口的 access key 统一转换
+#[derive(Debug, Clone, Copy)]
+pub struct Subject {
+    pub id: i64,
+    pub is_admin: bool,
+    //通过 API 密钥访问时的范围，控制台会话与旧版用户密钥为空
+    pub scope: Option<KeyScope>,
+}
+
+impl Subject {
+    pub fn with_key(self, key: &ApiKey) -> Self {
+        Subject {
+            scope: Some(KeyScope { bucket_id: key.bucket_id, read_only: key.read_only }),
+            ..self
+        }
+    }
+
+    fn limit(user: Option<Subject>, bucket: &Bucket, permissions: PermissionSet) -> PermissionSet {
+        match user.and_then(|user| user.scope) {
+            Some(scope) => scope.limit(bucket, permissions),
+            None => permissions,
+        }
+    }
+}
+
+impl From<&UserCache> for Subject {
+    fn from(user: &UserCache) -> Self {
+        Subject { id: user.id, is_admin: user.is_admin, scope: None }
+    }
+}
+
+impl From<&UserInfo> for Subject {
+    fn from(user: &UserInfo) -> Self {
+        Subject { id: user.id, is_admin: user.is_admin, scope: None }
+    }
+}
+
+/// 公开读、公开写的 bucket 对所有人开放的权限
+fn public_permissions(bucket: &Bucket) -> PermissionSet {
+    let mut permissions = PermissionSet::NONE;
+    if bucket.pub_read {
+        permissions = permissions.union(PermissionSet::from_actions(&[Action::Read]));
+    }
+    if bucket.pub_write {
+        permissions = permissions.union(PermissionSet::from_actions(&[Action::Write]));
+    }
+    permissions
+}
+
+/// 统一的 bucket 与目录权限校验，无权限时返回 `NoRight("no.right")`
+///
+/// 管理员拥有全部权限；公开读、公开写的 bucket 分别对所有人（包括未登录）开放读、写，删除等其他操作不受公开设置影响
+///
+/// 目录授权由上级目录继承到全部子目录和文件，越深的目录优先，同一目录上拒绝优先于允许；
+/// 管理员与拥有 bucket 管理权限的用户不受目录授权限制
+///
+/// 通过 API 密钥访问时，最终权限再按密钥的 bucket 与只读范围收窄
+pub struct AuthService {
+    bucket_dao: BaseRepository<Bucket>,
+    user_bucket_dao: BaseRepository<UserBucket>,
+    path_rep: PathRepository,
+    path_grant_rep: PathGrantRepository,
+    user_group_rep: UserGroupRepository,
+}
+
+impl AuthService {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            bucket_dao: BaseRepository::new(pool.clone(), "bucket"),
+            user_bucket_dao: BaseRepository::new(pool.clone(), "user_bucket"),
+            path_rep: PathRepository::new(pool.clone()),
+            path_grant_rep: PathGrantRepository::new(pool.clone()),
+            user_group_rep: UserGroupRepository::new(pool),
+        }
+    }
+
+    /// 用户在 bucket 上的全部权限
+    pub async fn permissions(&self, user: Option<Subject>, bucket: &Bucket) -> Result<PermissionSet, AppError> {
+        let permissions = self.bucket_permissions(user, bucket).await?;
+        Ok(Subject::limit(user, bucket, permissions))
+    }
+
+    async fn bucket_permissions(&self, user: Option<Subject>, bucket: &Bucket) -> Result<PermissionSet, AppError> {
+        let mut permissions = public_permissions(bucket);
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(permissions),
+        };
+        if user.is_admin {
+            return Ok(PermissionSet::from_actions(&[Action::Admin]));
+        }
+        let rights = self
+            .user_bucket_dao
+            .query_by_params(vec![
+                QueryParam::eq("user_id", user.id.to_string().as_str()),
+                QueryParam::eq("bucket_id", bucket.id.to_string().as_str()),
+            ])
+            .await?;
+        for right in rights.iter() {
+            permissions = permissions.union(right.permission_set());
+        }
+        Ok(permissions)
+    }
+
+    pub async fn authorize(&self, user: Option<Subject>, bucket: &Bucket, action: Action) -> Result<(), AppError> {
+        match self.permissions(user, bucket).await?.allows(action) {
+            true => Ok(()),
+            false => Err(AppError::NoRight("no.right".to_owned())),
+        }
+    }
+
+    /// 按 bucket id 校验，bucket 不存在时同样返回无权限
+    pub async fn authorize_id(&self, user: Option<Subject>, bucket_id: i64, action: Action) -> Result<Bucket, AppError> {
+        let bucket = self.find_bucket(bucket_id).await?;
+        self.authorize(user, &bucket, action).await?;
+        Ok(bucket)
+    }
+
+    /// 用户在目录上的有效权限，`dir` 为目录 full_path（如 `deliveries/acme`），空字符串为 bucket 根目录
+    pub async fn path_permissions(&self, user: Option<Subject>, bucket: &Bucket, dir: &str) -> Result<PermissionSet, AppError> {
+        let permissions = self.granted_path_permissions(user, bucket, dir).await?;
+        Ok(Subject::limit(user, bucket, permissions))
+    }
+
+    async fn granted_path_permissions(&self, user: Option<Subject>, bucket: &Bucket, dir: &str) -> Result<PermissionSet, AppError> {
+        let permissions = self.bucket_permissions(user, bucket).await?;
+        let user = match user {
+            Some(user) if !permissions.allows(Action::Admin) => user,
+            _ => return Ok(permissions),
+        };
+        let full_paths = ancestor_paths(dir);
+        if full_paths.is_empty() {
+            return Ok(permissions);
+        }
+        let paths = self.path_rep.find_by_full_paths(bucket.id, &full_paths).await?;
+        let depths: HashMap<i64, usize> = paths.iter().map(|path| (path.id, path.full_path.matches('/').count())).collect();
+        let path_ids: Vec<i64> = depths.keys().copied().collect();
+        let group_ids = self.user_group_rep.group_ids(user.id).await?;
+        let grants = self.path_grant_rep.list_for_subject(&path_ids, user.id, &group_ids).await?;
+        Ok(apply_grants(permissions, &depths, &grants))
+    }
+
+    pub async fn authorize_path(&self, user: Option<Subject>, bucket: &Bucket, dir: &str, action: Action) -> Result<(), AppError> {
+        match self.path_permissions(user, bucket, dir).await?.allows(action) {
+            true => Ok(()),
+            false => Err(AppError::NoRight("no.right".to_owned())),
+        }
+    }
+
+    /// 按目录 id 校验，`path_ref` 为 0 时为 bucket 根目录，返回 bucket 与目录 full_path
+    pub async fn authorize_path_ref(&self, user: Option<Subject>, bucket_id: i64, path_ref: i64, action: Action) -> Result<(Bucket, String), AppError> {
+        let (bucket, dir) = self.resolve_path_ref(bucket_id, path_ref).await?;
+        self.authorize_path(user, &bucket, &dir, action).await?;
+        Ok((bucket, dir))
+    }
+
+    /// 取出 bucket 与目录 full_path，不做权限校验；目录不属于该 bucket 时返回 `NotFound("path.not.found")`
+    pub async fn resolve_path_ref(&self, bucket_id: i64, path_ref: i64) -> Result<(Bucket, String), AppError> {
+        let bucket = self.find_bucket(bucket_id).await?;
+        let dir = match path_ref {
+            0 => String::new(),
+            path_ref => match self.path_rep.dao.find_by_id(path_ref).await {
+                Ok(path) if path.bucket_id == bucket_id => path.full_path,
+                Ok(_) | Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("path.not.found".to_owned())),
+                Err(e) => return Err(e),
+            },
+        };
+        Ok((bucket, dir))
+    }
+
+    /// 批量校验多个目录（列表、打包下载）时使用，同一目录只查询一次
+    pub fn path_permission_cache<'a>(&'a self, user: Option<Subject>, bucket: &'a Bucket) -> PathPermissionCache<'a> {
+        PathPermissionCache {
+            auth_service: self,
+            user,
+            bucket,
+            dirs: HashMap::new(),
+        }
+    }
+
+    async fn find_bucket(&self, bucket_id: i64) -> Result<Bucket, AppError> {
+        match self.bucket_dao.find_by_id(bucket_id).await {
+            Ok(bucket) => Ok(bucket),
+            Err(AppError::DBError(sqlx::Error::RowNotFound)) => Err(AppError::NoRight("no.right".to_owned())),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// 按目录缓存有效权限，子目录上的拒绝授权同样生效
+pub struct PathPermissionCache<'a> {
+    auth_service: &'a AuthService,
+    user: Option<Subject>,
+    bucket: &'a Bucket,
+    dirs: HashMap<String, PermissionSet>,
+}
+
+impl PathPermissionCache<'_> {
+    /// `dir` 为目录 full_path，结尾的 `/` 会被忽略
+    pub async fn allows(&mut self, dir: &str, action: Action) -> Result<bool, AppError> {
+        let dir = dir.trim_end_matches('/');
+        if let Some(permissions) = self.dirs.get(dir) {
+            return Ok(permissions.allows(action));
+        }
+        let permissions = self.auth_service.path_permissions(self.user, self.bucket, dir).await?;
+        self.dirs.insert(dir.to_string(), permissions);
+        Ok(permissions.allows(action))
+    }
+}
+
+/// 在 bucket 权限上叠加目录授权，`depths` 为授权所在目录的深度
+///
+/// 按深度汇总 (允许, 拒绝)，从根目录逐级覆盖，同一深度上拒绝优先
+fn apply_grants(mut permissions: PermissionSet, depths: &HashMap<i64, usize>, grants: &[PathGrant]) -> PermissionSet {
+    let mut levels: BTreeMap<usize, (PermissionSet, PermissionSet)> = BTreeMap::new();
+    for grant in grants.iter() {
+        let depth = depths.get(&grant.path_id).copied().unwrap_or_default();
+        let level = levels.entry(depth).or_default();
+        match grant.effect {
+            PathGrant::EFFECT_DENY => level.1 = level.1.union(PermissionSet::from_bits(grant.permissions)),
+            _ => level.0 = level.0.union(PermissionSet::from_bits(grant.permissions)),
+        }
+    }
+    for (allow, deny) in levels.into_values() {
+        permissions = permissions.union(allow).remove(deny);
+    }
+    permissions
+}
+
+/// 目录自身及全部上级目录的 full_path，`a/b/c` 返回 `[a, a/b, a/b/c]`
+fn ancestor_paths(dir: &str) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for item in dir.split('/').filter(|item| !item.is_empty()) {
+        match result.last() {
+            Some(parent) => result.push(format!("{}/{}", parent, item)),
+            None => result.push(item.to_string()),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permission_set_round_trips_through_user_bucket() {
+        for bits in 0..(1 << Action::ALL.len()) {
+            let permissions = PermissionSet::from_bits(bits);
+            let row = UserBucket {
+                id: 1,
+                user_id: 1,
+                bucket_id: 1,
+                user_right: permissions.legacy_right(),
+                permissions: permissions.bits(),
+            };
+            assert_eq!(row.permission_set(), permissions, "bits {:05b}", bits);
+        }
+    }
+
+    #[test]
+    fn empty_permission_set_has_no_legacy_right() {
+        assert_eq!(PermissionSet::NONE.legacy_right(), -1);
+        assert_eq!(PermissionSet::from_legacy_right(PermissionSet::NONE.legacy_right()), PermissionSet::NONE);
+    }
+
+    fn grant(path_id: i64, effect: i32, action: Action) -> PathGrant {
+        PathGrant {
+            id: path_id,
+            bucket_id: 1,
+            path_id,
+            subject_type: PathGrant::SUBJECT_USER,
+            subject_id: 1,
+            effect,
+            permissions: PermissionSet::from_actions(&[action]).bits(),
+            create_time: chrono::NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn child_deny_overrides_parent_allow() {
+        // 1: a  2: a/secret  3: a/secret/open
+        let depths = HashMap::from([(1, 0), (2, 1), (3, 2)]);
+        let parent = grant(1, PathGrant::EFFECT_ALLOW, Action::Read);
+        let child = grant(2, PathGrant::EFFECT_DENY, Action::Read);
+        let grandchild = grant(3, PathGrant::EFFECT_ALLOW, Action::Read);
+
+        let sibling = apply_grants(PermissionSet::NONE, &depths, std::slice::from_ref(&parent));
+        assert!(sibling.allows(Action::Read));
+        let denied = apply_grants(PermissionSet::NONE, &depths, &[parent.clone(), child.clone()]);
+        assert!(!denied.allows(Action::Read));
+        let reopened = apply_grants(PermissionSet::NONE, &depths, &[child.clone(), parent.clone(), grandchild]);
+        assert!(reopened.allows(Action::Read));
+    }
+
+    #[test]
+    fn deny_wins_on_same_level() {
+        let depths = HashMap::from([(1, 0)]);
+        let grants = [grant(1, PathGrant::EFFECT_ALLOW, Action::Read), grant(1, PathGrant::EFFECT_DENY, Action::Read)];
+        let bucket_read = PermissionSet::from_actions(&[Action::Read]);
+        assert!(!apply_grants(bucket_read, &depths, &grants).allows(Action::Read));
+    }
+}
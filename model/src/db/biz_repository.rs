@@ -1,10 +1,10 @@
-use crate::{query_by_sql, BaseRepository, Bucket, FileInfo, FileItemDto, PathDelTask, PathInfo, QueryParam, Repository, UserBucket, UserBucketRight, UserInfo};
-use common::{build_md5, build_snow_id, build_time, AppError};
+use crate::{escape_like, query_by_sql, with_transaction, ApiKey, BaseRepository, Bucket, PermissionSet, FileChunk, FileInfo, FileItemDto, FileThumbnail, FileType, PathDelTask, PathGrant, PathInfo, QueryParam, Repository, UploadPart, UploadSession, UserBucket, UserBucketRight, UserGroup, UserGroupMember, UserInfo, UserRecoveryCode, Subject};
+use common::{build_id, build_snow_id, build_time, chunk_hash, hash_password, verify_password, AppError, PasswordMatch, StorageBackend};
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::types::Json;
-use sqlx::{FromRow, MySql, MySqlPool, Transaction};
+use sqlx::{Acquire, FromRow, MySql, MySqlPool, Transaction};
 use std::collections::HashMap;
 use std::{str, sync::Arc};
 use chrono::Local;
@@ -20,44 +20,194 @@ impl PathDelTaskRepository {
             dao: BaseRepository::new(pool, "path_del_task"),
         }
     }
+    /// 创建删除任务并删除目录记录，子目录与文件由 app-job 的 path_del 任务删除
     pub async fn create(&self, task: PathDelTask, path_rep: &PathRepository) -> Result<(), AppError> {
-        let mut tx: Transaction<'_, MySql> = self.dao.pool.begin().await?;
         let mut params: HashMap<&str, String> = HashMap::new();
         params.insert("id", task.id.to_string());
         params.insert("path_id", task.path_id.to_string());
+        params.insert("bucket_id", task.bucket_id.to_string());
+        params.insert("full_path", task.full_path.clone());
         params.insert("del_file_status", "0".to_owned());
         params.insert("del_path_status", "0".to_owned());
-        params.insert("create_time", build_time().await.to_owned());
-        self.dao.insert(params).await?;
-        path_rep.dao.del_by_id(task.path_id).await?;
-        tx.commit().await?;
+        params.insert("create_time", build_time().await);
+        with_transaction(&self.dao.pool, |mut tx| async move {
+            self.dao.insert_with(&mut tx, params).await?;
+            path_rep.dao.del_by_id_with(&mut tx, task.path_id).await?;
+            Ok(((), tx))
+        }).await
+    }
+
+    /// 未完成的任务
+    pub async fn list_pending(&self, limit: i64) -> Result<Vec<PathDelTask>, AppError> {
+        let query = format!(
+            "SELECT * FROM {} WHERE del_file_status = 0 OR del_path_status = 0 ORDER BY id ASC LIMIT ?",
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, PathDelTask>(&query)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list)
+    }
+
+    /// 占用或续期任务，未被占用或占用超过 `lease_secs` 秒时成功
+    pub async fn lock(&self, id: i64, lease_secs: i64) -> Result<bool, AppError> {
+        let query = format!(
+            "UPDATE {} SET lock_time = NOW() WHERE id = ? AND (lock_time IS NULL OR lock_time < NOW() - INTERVAL ? SECOND)",
+            self.dao.table_name
+        );
+        let result = sqlx::query(&query).bind(id).bind(lease_secs).execute(&*self.dao.pool).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// 处理期间续期占用
+    pub async fn renew(&self, id: i64) -> Result<(), AppError> {
+        let query = format!("UPDATE {} SET lock_time = NOW() WHERE id = ?", self.dao.table_name);
+        sqlx::query(&query).bind(id).execute(&*self.dao.pool).await?;
+        Ok(())
+    }
+
+    /// 释放占用，其他 worker 可立即接手
+    pub async fn unlock(&self, id: i64) -> Result<(), AppError> {
+        let query = format!("UPDATE {} SET lock_time = NULL WHERE id = ?", self.dao.table_name);
+        sqlx::query(&query).bind(id).execute(&*self.dao.pool).await?;
+        Ok(())
+    }
+
+    pub async fn change_status(&self, id: i64, del_file_status: bool, del_path_status: bool) -> Result<(), AppError> {
+        let query = format!(
+            "UPDATE {} SET del_file_status = ?, del_path_status = ? WHERE id = ?",
+            self.dao.table_name
+        );
+        sqlx::query(&query)
+            .bind(del_file_status)
+            .bind(del_path_status)
+            .bind(id)
+            .execute(&*self.dao.pool)
+            .await?;
         Ok(())
     }
 }
 
 pub struct UserRepository {
     pub dao: BaseRepository<UserInfo>,
+    recovery_dao: BaseRepository<UserRecoveryCode>,
 }
 
 impl UserRepository {
     pub fn new(pool: Arc<MySqlPool>) -> Self {
         Self {
-            dao: BaseRepository::new(pool, "user_info"),
+            dao: BaseRepository::new(pool.clone(), "user_info"),
+            recovery_dao: BaseRepository::new(pool, "user_recovery_code"),
         }
     }
     pub async fn find_by_name(&self, user_name: String) -> Result<UserInfo, AppError> {
         return self.dao.find_by_one(vec![QueryParam::eq("user_name", user_name.as_str())]).await;
     }
-    pub async fn login(&self, user_name: &String, password: &String) -> Result<UserInfo, AppError> {
-        let user_result = self.dao.query_by_params(vec![QueryParam::eq("user_name", user_name.as_str())]).await?;
-        if user_result.len() > 0 {
+    pub async fn login(&self, user_name: &str, password: &str) -> Result<UserInfo, AppError> {
+        let user_result = self.dao.query_by_params(vec![QueryParam::eq("user_name", user_name)]).await?;
+        if !user_result.is_empty() {
             let info = &user_result[0];
-            if info.password == build_md5(password) {
-                return Ok(info.clone());
+            match verify_password(password, &info.password) {
+                PasswordMatch::Valid => return Ok(info.clone()),
+                PasswordMatch::Legacy => {
+                    // 旧的 MD5 密码在登录成功时升级
+                    let mut info = info.clone();
+                    info.password = hash_password(password)?;
+                    self.change_password(info.id, &info.password).await?;
+                    return Ok(info);
+                }
+                PasswordMatch::Invalid => {}
             }
         }
         Err(AppError::BizError("username.or.password.error".to_string()))
     }
+
+    /// 保存已经过 `hash_password` 处理的密码
+    pub async fn change_password(&self, id: i64, password_hash: &str) -> Result<(), AppError> {
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("password", password_hash.to_string());
+        self.dao.change(id, params).await
+    }
+    /// 保存待确认的 TOTP 密钥，确认前不影响登录
+    pub async fn save_totp_secret(&self, id: i64, encrypted_secret: &str) -> Result<(), AppError> {
+        let query = format!("UPDATE {} SET totp_secret = ?, totp_enabled = 0 WHERE id = ? AND totp_enabled = 0", self.dao.table_name);
+        let result = sqlx::query(&query).bind(encrypted_secret).bind(id).execute(&*self.dao.pool).await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::BizError("totp.already.enabled".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// 启用两步验证并替换恢复码
+    pub async fn enable_totp(&self, id: i64, code_hashes: &[String]) -> Result<(), AppError> {
+        let query = format!("UPDATE {} SET totp_enabled = 1 WHERE id = ? AND totp_secret <> ''", self.dao.table_name);
+        sqlx::query(&query).bind(id).execute(&*self.dao.pool).await?;
+        self.replace_recovery_codes(id, code_hashes).await
+    }
+
+    /// 关闭两步验证，同时删除密钥和恢复码
+    pub async fn disable_totp(&self, id: i64) -> Result<(), AppError> {
+        let query = format!("UPDATE {} SET totp_secret = '', totp_enabled = 0 WHERE id = ?", self.dao.table_name);
+        sqlx::query(&query).bind(id).execute(&*self.dao.pool).await?;
+        self.replace_recovery_codes(id, &[]).await
+    }
+
+    pub async fn replace_recovery_codes(&self, user_id: i64, code_hashes: &[String]) -> Result<(), AppError> {
+        let query = format!("DELETE FROM {} WHERE user_id = ?", self.recovery_dao.table_name);
+        sqlx::query(&query).bind(user_id).execute(&*self.recovery_dao.pool).await?;
+        for code_hash in code_hashes {
+            let mut params: HashMap<&str, String> = HashMap::new();
+            params.insert("id", build_snow_id().to_string());
+            params.insert("user_id", user_id.to_string());
+            params.insert("code_hash", code_hash.clone());
+            params.insert("create_time", build_time().await);
+            self.recovery_dao.insert(params).await?;
+        }
+        Ok(())
+    }
+
+    /// 记录使用的验证码时间步，只能前进，同一验证码并发使用时只有一次成功
+    pub async fn use_totp_step(&self, id: i64, step: i64) -> Result<bool, AppError> {
+        let query = format!("UPDATE {} SET totp_last_step = ? WHERE id = ? AND totp_last_step < ?", self.dao.table_name);
+        let result = sqlx::query(&query).bind(step).bind(id).bind(step).execute(&*self.dao.pool).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// 使用恢复码，删除成功才算有效，同一个恢复码并发使用时只有一次成功
+    pub async fn use_recovery_code(&self, user_id: i64, code_hash: &str) -> Result<bool, AppError> {
+        let query = format!("DELETE FROM {} WHERE user_id = ? AND code_hash = ?", self.recovery_dao.table_name);
+        let result = sqlx::query(&query).bind(user_id).bind(code_hash).execute(&*self.recovery_dao.pool).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn recovery_code_count(&self, user_id: i64) -> Result<i64, AppError> {
+        self.recovery_dao.query_by_count(vec![QueryParam::eq("user_id", user_id.to_string().as_str())]).await
+    }
+
+    /// 没有管理员时创建，返回是否创建；用户名已被普通用户占用时返回 `user.exists`
+    pub async fn create_admin_if_absent(&self, user_name: &str, password_hash: &str) -> Result<bool, AppError> {
+        if self.dao.query_by_count(vec![QueryParam::eq("is_admin", "1")]).await? > 0 {
+            return Ok(false);
+        }
+        if self.dao.query_by_count(vec![QueryParam::eq("user_name", user_name)]).await? > 0 {
+            return Err(AppError::BizError(format!("user.exists:{}", user_name)));
+        }
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("id", build_snow_id().to_string());
+        params.insert("is_admin", "1".to_owned());
+        params.insert("user_name", user_name.to_string());
+        params.insert("password", password_hash.to_string());
+        params.insert("access_key", build_id());
+        params.insert("secret_key", build_id());
+        params.insert("create_time", build_time().await);
+        self.dao.insert(params).await?;
+        Ok(true)
+    }
+
+    pub async fn find_by_access_key(&self, access_key: &str) -> Result<UserInfo, AppError> {
+        return self.dao.find_by_one(vec![QueryParam::eq("access_key", access_key)]).await;
+    }
 }
 
 pub struct PathRepository {
@@ -72,25 +222,112 @@ impl PathRepository {
     }
 
     pub async fn new_path(&self, path: &String, pid: &i64, bucket_id: &i64) -> Result<i64, AppError> {
-        let mut params: HashMap<&str, String> = HashMap::new();
-        let mut full_path = String::new();
-        if pid != &0 {
-            let parent_info = self.dao.find_by_id(pid.clone()).await?;
-            full_path = format!("{}/{}", parent_info.path, &path);
-            params.insert("root", "0".to_owned());
-        } else {
-            params.insert("root", "1".to_owned());
-            full_path = path.clone();
+        self.new_path_with(&*self.dao.pool, path, pid, bucket_id).await
+    }
+
+    /// 同一目录下已有同名目录时返回已有目录的 id，并发创建同一目录时不会重复
+    pub async fn new_path_with<'c, A>(&self, db: A, path: &String, pid: &i64, bucket_id: &i64) -> Result<i64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let full_path = match *pid {
+            0 => path.clone(),
+            _ => {
+                let parent_info = self.dao.find_by_id_with(&mut *conn, *pid).await?;
+                format!("{}/{}", parent_info.full_path, &path)
+            }
+        };
+        let id = build_snow_id();
+        // 冲突时 LAST_INSERT_ID(id) 返回已有记录的 id，新插入时为 0
+        let query = format!(
+            r#"
+            INSERT INTO {} (id, bucket_id, root, path, parent, full_path, create_time) VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON DUPLICATE KEY UPDATE id = LAST_INSERT_ID(id)
+                "#,
+            self.dao.table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(id)
+            .bind(bucket_id)
+            .bind(*pid == 0)
+            .bind(path)
+            .bind(pid)
+            .bind(full_path)
+            .bind(build_time().await)
+            .execute(&mut *conn)
+            .await?;
+        match result.last_insert_id() {
+            0 => Ok(id),
+            existing => Ok(existing as i64),
         }
-        params.insert("bucket_id", bucket_id.to_string());
-        params.insert("path", path.to_string());
-        let i = build_snow_id();
-        params.insert("id", i.to_string());
-        params.insert("parent", pid.to_string());
-        params.insert("full_path", full_path);
-        params.insert("create_time", build_time().await);
-        self.dao.insert(params).await?;
-        return Ok(i);
+    }
+
+    /// 删除目录下的全部子目录，每次最多删除 `limit` 条，返回删除数量
+    ///
+    /// 按 parent 逐级查找，不按 full_path 匹配，删除后重新创建的同名目录不受影响；
+    /// 先删除最深的目录，中断后剩余的子目录仍能从 `path_id` 找到
+    pub async fn del_children(&self, bucket_id: i64, path_id: i64, limit: i64) -> Result<u64, AppError> {
+        let query = format!(
+            r#"
+            WITH RECURSIVE sub (id, depth) AS (
+                SELECT id, 1 FROM {table} WHERE bucket_id = ? AND parent = ?
+                UNION ALL
+                SELECT p.id, sub.depth + 1 FROM {table} p INNER JOIN sub ON p.parent = sub.id
+            )
+            SELECT id FROM sub ORDER BY depth DESC, id ASC LIMIT ?
+                "#,
+            table = self.dao.table_name
+        );
+        let ids: Vec<i64> = sqlx::query_scalar(&query)
+            .bind(bucket_id)
+            .bind(path_id)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let query = format!("DELETE FROM {} WHERE id IN ({})", self.dao.table_name, vec!["?"; ids.len()].join(", "));
+        let mut sql_query = sqlx::query(&query);
+        for id in ids.iter() {
+            sql_query = sql_query.bind(id);
+        }
+        sql_query.execute(&*self.dao.pool).await?;
+        Ok(ids.len() as u64)
+    }
+
+    /// 按 `full_path/` 的字节序列出前缀匹配的目录，cursor 为包含的下界
+    pub async fn list_by_key(&self, bucket_id: i64, prefix: &str, cursor: &str, limit: i64) -> Result<Vec<PathInfo>, AppError> {
+        let query = format!(
+            r#"
+            SELECT * FROM {} WHERE bucket_id = ? AND BINARY CONCAT(full_path, '/') LIKE ?
+            AND BINARY CONCAT(full_path, '/') >= ? ORDER BY BINARY CONCAT(full_path, '/') ASC LIMIT ?
+                "#,
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, PathInfo>(&query)
+            .bind(bucket_id)
+            .bind(format!("{}%", escape_like(prefix)))
+            .bind(cursor)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list)
+    }
+
+    /// 按 full_path 批量查询目录，用于取出某个目录的全部上级目录
+    pub async fn find_by_full_paths(&self, bucket_id: i64, full_paths: &[String]) -> Result<Vec<PathInfo>, AppError> {
+        if full_paths.is_empty() {
+            return Ok(vec![]);
+        }
+        let placeholders = vec!["?"; full_paths.len()].join(", ");
+        let query = format!("SELECT * FROM {} WHERE bucket_id = ? AND full_path IN ({})", self.dao.table_name, placeholders);
+        let mut query = sqlx::query_as::<_, PathInfo>(&query).bind(bucket_id);
+        for full_path in full_paths {
+            query = query.bind(full_path);
+        }
+        Ok(query.fetch_all(&*self.dao.pool).await?)
     }
 }
 
@@ -107,6 +344,85 @@ impl BucketRepository {
     pub async fn find_by_name(&self, name: &String) -> Result<Bucket, AppError> {
         return self.dao.find_by_one(vec![QueryParam::eq("name", name.to_string().as_str())]).await;
     }
+
+    /// 占用配额，超出时不修改并返回 `quota.exceeded`；检查与累加在同一条语句内完成
+    pub async fn reserve_quota(&self, bucket_id: i64, size: i64) -> Result<(), AppError> {
+        self.reserve_quota_with(&*self.dao.pool, bucket_id, size).await
+    }
+
+    /// 传入事务时与文件记录一起提交，写入失败时随事务回滚
+    pub async fn reserve_quota_with<'c, A>(&self, db: A, bucket_id: i64, size: i64) -> Result<(), AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        if size <= 0 {
+            return Ok(());
+        }
+        let query = format!(
+            "UPDATE {} SET current_quota = current_quota + ? WHERE id = ? AND (quota = 0 OR current_quota + ? <= quota)",
+            self.dao.table_name
+        );
+        let mut conn = db.acquire().await?;
+        let result = sqlx::query(&query)
+            .bind(size)
+            .bind(bucket_id)
+            .bind(size)
+            .execute(&mut *conn)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::BizError("quota.exceeded".to_owned()));
+        }
+        Ok(())
+    }
+
+    /// 只检查剩余配额，不占用；上传过程中提前拒绝超出配额的文件，入库时再用 `reserve_quota_with` 占用
+    pub async fn check_quota(&self, bucket_id: i64, size: i64) -> Result<(), AppError> {
+        if size <= 0 {
+            return Ok(());
+        }
+        let bucket = self.dao.find_by_id(bucket_id).await?;
+        match bucket.quota == 0 || bucket.current_quota + size <= bucket.quota {
+            true => Ok(()),
+            false => Err(AppError::BizError("quota.exceeded".to_owned())),
+        }
+    }
+
+    /// 归还配额
+    pub async fn release_quota(&self, bucket_id: i64, size: i64) -> Result<(), AppError> {
+        if size <= 0 {
+            return Ok(());
+        }
+        let query = format!("UPDATE {} SET current_quota = GREATEST(current_quota - ?, 0) WHERE id = ?", self.dao.table_name);
+        sqlx::query(&query).bind(size).bind(bucket_id).execute(&*self.dao.pool).await?;
+        Ok(())
+    }
+
+    /// 按 file_info 与未完成的断点续传分片重新计算已用空间
+    pub async fn recalc_quota(&self, bucket_id: i64) -> Result<i64, AppError> {
+        let query = format!(
+            r#"
+            UPDATE {} SET current_quota = (
+                SELECT COALESCE(SUM(size), 0) FROM file_info WHERE bucket_id = ?
+            ) + (
+                SELECT COALESCE(SUM(p.size), 0) FROM upload_part p
+                JOIN upload_session s ON p.upload_id = s.id
+                WHERE s.bucket_id = ? AND s.status = ?
+            ) WHERE id = ?
+                "#,
+            self.dao.table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(bucket_id)
+            .bind(bucket_id)
+            .bind(UploadSession::UPLOADING)
+            .bind(bucket_id)
+            .execute(&*self.dao.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound("bucket.not.found".to_owned()));
+        }
+        Ok(self.dao.find_by_id(bucket_id).await?.current_quota)
+    }
 }
 
 pub struct FileRepository {
@@ -124,40 +440,109 @@ impl FileRepository {
         params: HashMap<&str, String>,
         items: &Vec<FileItemDto>,
     ) -> Result<(), AppError> {
-        let mut keys: Vec<&str> = params.keys().cloned().collect();
-        let values: Vec<String> = params.values().cloned().collect();
-        keys.push("items");
-        let placeholders = vec!["?"; keys.len()].join(", ");
+        self.insert_with(&*self.dao.pool, params, items).await
+    }
+
+    pub async fn insert_with<'c, A>(
+        &self,
+        db: A,
+        params: HashMap<&str, String>,
+        items: &Vec<FileItemDto>,
+    ) -> Result<(), AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let keys: Vec<&str> = params.keys().cloned().collect();
+        let mut columns = Vec::with_capacity(keys.len() + 1);
+        for key in &keys {
+            columns.push(format!("`{}`", self.dao.column(key)?));
+        }
+        columns.push(format!("`{}`", self.dao.column("items")?));
+        let placeholders = vec!["?"; columns.len()].join(", ");
         let query = format!(
             "INSERT INTO {} ({}) VALUES ({})",
             self.dao.table_name,
-            keys.join(", "),
+            columns.join(", "),
             placeholders
         );
 
         let mut sql_query = sqlx::query(&query);
-        for value in values {
-            sql_query = sql_query.bind(value);
+        for key in keys {
+            sql_query = sql_query.bind(&params[key]);
         }
         sql_query = sql_query.bind(Json(items));
-        sql_query.execute(&*self.dao.pool.clone()).await?;
-        Ok(())
+        let mut conn = db.acquire().await?;
+        match sql_query.execute(&mut *conn).await {
+            Ok(_) => Ok(()),
+            // 同一目录下已有同名文件（uk_file_info_key），覆盖写需要调用方先删除旧文件
+            Err(sqlx::Error::Database(db_err)) if db_err.is_unique_violation() => Err(AppError::BizError("file.exists".to_owned())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+
+    /// 删除文件记录并归还配额，返回是否删除；并发删除同一文件时只归还一次
+    pub async fn delete_file(&self, file: &FileInfo) -> Result<bool, AppError> {
+        self.delete_file_with(&*self.dao.pool, file).await
     }
 
+    /// 传入事务时在保存点内执行，随外层事务一起提交
+    pub async fn delete_file_with<'c, A>(&self, db: A, file: &FileInfo) -> Result<bool, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut tx = db.begin().await?;
+        let query = format!("DELETE FROM {} WHERE id = ?", self.dao.table_name);
+        let result = sqlx::query(&query).bind(file.id).execute(&mut *tx).await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE bucket SET current_quota = GREATEST(current_quota - ?, 0) WHERE id = ?")
+            .bind(file.size as i64)
+            .bind(file.bucket_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// 目录及其子目录下的文件，按 parent 逐级查找子目录，删除后重新创建的同名目录不受影响
+    pub async fn list_under_path(&self, bucket_id: i64, path_id: i64, limit: i64) -> Result<Vec<FileInfo>, AppError> {
+        let query = format!(
+            r#"
+            WITH RECURSIVE sub (id) AS (
+                SELECT CAST(? AS SIGNED)
+                UNION ALL
+                SELECT p.id FROM path_info p INNER JOIN sub ON p.parent = sub.id
+            )
+            SELECT * FROM {} WHERE bucket_id = ? AND path_ref IN (SELECT id FROM sub) ORDER BY id ASC LIMIT ?
+                "#,
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, FileInfo>(&query)
+            .bind(path_id)
+            .bind(bucket_id)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list)
+    }
 
-    pub async fn path_size(&self, full_path: &str) -> Result<i64, AppError> {
+    pub async fn path_size(&self, bucket_id: i64, full_path: &str) -> Result<i64, AppError> {
         let query = format!(
             r#"
            SELECT
                 COALESCE(SUM(file_info.size), 0)
             FROM
-                file_info
+                {}
             WHERE
-                file_info.full_path LIKE '{}%'
+                file_info.bucket_id = ? AND file_info.full_path LIKE ?
                 "#,
-            full_path
+            self.dao.table_name
         );
-        let mut sql_query = sqlx::query_scalar::<_, Decimal>(&query);
+        let sql_query = sqlx::query_scalar::<_, Decimal>(&query)
+            .bind(bucket_id)
+            .bind(format!("{}%", escape_like(full_path)));
 
         let result = sql_query.fetch_one(&*self.dao.pool).await?;
         Ok(result.to_i64().unwrap())
@@ -167,14 +552,380 @@ impl FileRepository {
     pub async fn path_file_list(&self, full_path: &str, max_id: i64, bucket_id: i64) -> Result<Vec<FileInfo>, AppError> {
         let query = format!(
             r#"
-            SELECT * from {} where bucket_id ={} and full_path LIKE '{}/%' and id>{} order by id asc
+            SELECT * from {} where bucket_id = ? and full_path LIKE ? and id > ? order by id asc
                 "#,
-            self.dao.table_name, bucket_id, full_path, max_id
+            self.dao.table_name
         );
         let list_result = sqlx::query_as::<_, FileInfo>(&query)
+            .bind(bucket_id)
+            .bind(format!("{}/%", escape_like(full_path)))
+            .bind(max_id)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list_result)
+    }
+
+    pub async fn find_by_key(&self, bucket_id: i64, full_path: &str, name: &str) -> Result<Option<FileInfo>, AppError> {
+        self.find_by_key_with(&*self.dao.pool, bucket_id, full_path, name).await
+    }
+
+    pub async fn find_by_key_with<'c, A>(&self, db: A, bucket_id: i64, full_path: &str, name: &str) -> Result<Option<FileInfo>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let list = self.dao.query_by_params_with(db, vec![
+            QueryParam::eq("bucket_id", bucket_id.to_string().as_str()),
+            QueryParam::eq("full_path", full_path),
+            QueryParam::eq("name", name),
+        ]).await?;
+        Ok(list.into_iter().next())
+    }
+
+    /// 按 `full_path + name` 的字节序列出前缀匹配的文件，cursor 为包含的下界
+    pub async fn list_by_key(&self, bucket_id: i64, prefix: &str, cursor: &str, limit: i64) -> Result<Vec<FileInfo>, AppError> {
+        let query = format!(
+            r#"
+            SELECT * FROM {} WHERE bucket_id = ? AND BINARY CONCAT(full_path, name) LIKE ?
+            AND BINARY CONCAT(full_path, name) >= ? ORDER BY BINARY CONCAT(full_path, name) ASC LIMIT ?
+                "#,
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, FileInfo>(&query)
+            .bind(bucket_id)
+            .bind(format!("{}%", escape_like(prefix)))
+            .bind(cursor)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list)
+    }
+
+    /// 待生成缩略图的图片，按 id 升序，`max_id` 为不包含的下界
+    pub async fn list_thumbnail_pending(&self, max_id: i64, limit: i64) -> Result<Vec<FileInfo>, AppError> {
+        let query = format!(
+            "SELECT * FROM {} WHERE file_type = ? AND thumbnail_status = ? AND id > ? ORDER BY id ASC LIMIT ?",
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, FileInfo>(&query)
+            .bind(FileType::IMAGE.as_ref())
+            .bind(FileInfo::THUMBNAIL_PENDING)
+            .bind(max_id)
+            .bind(limit)
+            .fetch_all(&*self.dao.pool)
+            .await?;
+        Ok(list)
+    }
+
+    /// 保存缩略图，仍为待生成状态时成功；返回 false 时调用方负责释放缩略图分片
+    pub async fn save_thumbnails(&self, id: i64, status: i32, thumbnails: &Vec<FileThumbnail>) -> Result<bool, AppError> {
+        let query = format!(
+            "UPDATE {} SET thumbnail_status = ?, thumbnails = ? WHERE id = ? AND thumbnail_status = ?",
+            self.dao.table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(status)
+            .bind(Json(thumbnails))
+            .bind(id)
+            .bind(FileInfo::THUMBNAIL_PENDING)
+            .execute(&*self.dao.pool)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+}
+pub struct ChunkRepository {
+    pub dao: BaseRepository<FileChunk>,
+}
+
+impl ChunkRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool, "file_chunk"),
+        }
+    }
+
+    /// 保存分片，相同内容只写一次，引用计数 +1
+    pub async fn save_chunk(&self, storage: &dyn StorageBackend, data: &[u8]) -> Result<FileItemDto, AppError> {
+        let hash = chunk_hash(data);
+        let key = storage.new_key(&hash).await?;
+        // 先占用引用再写文件，避免引用归零的删除与本次写入交错
+        let query = format!(
+            r#"
+            INSERT INTO {} (hash, path, size, ref_count, create_time) VALUES (?, ?, ?, 1, ?)
+            ON DUPLICATE KEY UPDATE ref_count = ref_count + 1
+                "#,
+            self.dao.table_name
+        );
+        sqlx::query(&query)
+            .bind(&hash)
+            .bind(&key)
+            .bind(data.len() as u32)
+            .bind(build_time().await)
+            .execute(&*self.dao.pool)
+            .await?;
+        // 已存在的分片沿用原来的 key
+        let query = format!("SELECT path FROM {} WHERE hash = ?", self.dao.table_name);
+        let path: String = sqlx::query_scalar(&query).bind(&hash).fetch_one(&*self.dao.pool).await?;
+        // AppError 不是 Send，释放引用前先转为字符串，保证返回的 future 是 Send
+        let exists = storage.exists(&path).await.map_err(|e| e.to_string());
+        let write_result = match exists {
+            Ok(true) => Ok(()),
+            Ok(false) => storage.put(&path, data).await.map_err(|e| e.to_string()),
+            Err(message) => Err(message),
+        };
+        if let Err(message) = write_result {
+            self.release(storage, &hash).await?;
+            return Err(AppError::InternalError(message));
+        }
+        Ok(FileItemDto {
+            path,
+            size: data.len() as u32,
+            hash,
+        })
+    }
+
+    /// 引用计数 -1，归零时删除记录与分片文件
+    pub async fn release(&self, storage: &dyn StorageBackend, hash: &str) -> Result<(), AppError> {
+        let mut tx: Transaction<'_, MySql> = self.dao.pool.begin().await?;
+        let query = format!("SELECT * FROM {} WHERE hash = ? FOR UPDATE", self.dao.table_name);
+        let chunk = sqlx::query_as::<_, FileChunk>(&query)
+            .bind(hash)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let chunk = match chunk {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+        if chunk.ref_count > 1 {
+            let query = format!("UPDATE {} SET ref_count = ref_count - 1 WHERE hash = ?", self.dao.table_name);
+            sqlx::query(&query).bind(hash).execute(&mut *tx).await?;
+        } else {
+            let query = format!("DELETE FROM {} WHERE hash = ?", self.dao.table_name);
+            sqlx::query(&query).bind(hash).execute(&mut *tx).await?;
+            // 持有行锁时删除文件，并发的 save_chunk 会等待提交后重新写入
+            storage.delete(&chunk.path).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// 占用 bucket 配额后保存分片，保存失败时归还配额
+    pub async fn save_bucket_chunk(&self, storage: &dyn StorageBackend, bucket_rep: &BucketRepository, bucket_id: i64, data: &[u8]) -> Result<FileItemDto, AppError> {
+        bucket_rep.reserve_quota(bucket_id, data.len() as i64).await?;
+        match self.save_chunk(storage, data).await {
+            Ok(item) => Ok(item),
+            Err(e) => {
+                bucket_rep.release_quota(bucket_id, data.len() as i64).await?;
+                Err(e)
+            }
+        }
+    }
+
+    /// 保存待入库文件的分片，只检查配额不占用，`total` 为包含本分片在内已接收的大小
+    ///
+    /// 配额在写入文件记录的事务中占用，失败时用 `release_items` 释放分片
+    pub async fn save_pending_chunk(&self, storage: &dyn StorageBackend, bucket_rep: &BucketRepository, bucket_id: i64, total: usize, data: &[u8]) -> Result<FileItemDto, AppError> {
+        bucket_rep.check_quota(bucket_id, total as i64).await?;
+        self.save_chunk(storage, data).await
+    }
+
+    /// 释放未入库的分片并归还其占用的配额
+    pub async fn release_bucket_items(&self, storage: &dyn StorageBackend, bucket_rep: &BucketRepository, bucket_id: i64, items: &[FileItemDto]) -> Result<(), AppError> {
+        let size: i64 = items.iter().map(|item| item.size as i64).sum();
+        bucket_rep.release_quota(bucket_id, size).await?;
+        self.release_items(storage, items).await
+    }
+
+    /// 释放文件的全部分片，旧数据没有 hash 时直接删除文件
+    pub async fn release_items(&self, storage: &dyn StorageBackend, items: &[FileItemDto]) -> Result<(), AppError> {
+        for item in items {
+            if item.hash.is_empty() {
+                storage.delete(&item.path).await?;
+            } else {
+                self.release(storage, &item.hash).await?;
+            }
+        }
+        Ok(())
+    }
+}
+pub struct UploadSessionRepository {
+    pub dao: BaseRepository<UploadSession>,
+}
+
+impl UploadSessionRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool, "upload_session"),
+        }
+    }
+
+    pub async fn create(&self, bucket_id: &i64, path_ref: &i64, full_path: &str, name: &str) -> Result<i64, AppError> {
+        self.create_with(&*self.dao.pool, bucket_id, path_ref, full_path, name).await
+    }
+
+    pub async fn create_with<'c, A>(&self, db: A, bucket_id: &i64, path_ref: &i64, full_path: &str, name: &str) -> Result<i64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let id = build_snow_id();
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("id", id.to_string());
+        params.insert("bucket_id", bucket_id.to_string());
+        params.insert("path_ref", path_ref.to_string());
+        params.insert("full_path", full_path.to_string());
+        params.insert("name", name.to_string());
+        params.insert("status", UploadSession::UPLOADING.to_string());
+        params.insert("create_time", build_time().await);
+        self.dao.insert_with(db, params).await?;
+        Ok(id)
+    }
+
+    pub async fn change_status(&self, id: i64, status: i32) -> Result<(), AppError> {
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("status", status.to_string());
+        self.dao.change(id, params).await
+    }
+
+    /// 在事务中锁定会话，保存分片与完成上传串行执行；会话不存在时返回 None
+    pub async fn lock_with<'c, A>(&self, db: A, id: i64) -> Result<Option<UploadSession>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {} WHERE id = ? FOR UPDATE", self.dao.table_name);
+        let session = sqlx::query_as::<_, UploadSession>(&query).bind(id).fetch_optional(&mut *conn).await?;
+        Ok(session)
+    }
+
+    /// 关闭上传中的会话，返回是否由本次调用关闭；多个 worker 并发时只有一个成功
+    pub async fn close(&self, id: i64, status: i32) -> Result<bool, AppError> {
+        self.close_with(&*self.dao.pool, id, status).await
+    }
+
+    pub async fn close_with<'c, A>(&self, db: A, id: i64, status: i32) -> Result<bool, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("UPDATE {} SET status = ? WHERE id = ? AND status = ?", self.dao.table_name);
+        let result = sqlx::query(&query)
+            .bind(status)
+            .bind(id)
+            .bind(UploadSession::UPLOADING)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// 需要清理的会话：创建时间早于 `before` 仍在上传中的，以及已取消但分片未释放完的
+    pub async fn list_expired(&self, before: &str, limit: i64) -> Result<Vec<UploadSession>, AppError> {
+        let query = format!(
+            r#"
+            SELECT * FROM {} s WHERE (s.status = ? AND s.create_time < ?)
+            OR (s.status = ? AND EXISTS (SELECT 1 FROM upload_part p WHERE p.upload_id = s.id))
+            ORDER BY s.id ASC LIMIT ?
+                "#,
+            self.dao.table_name
+        );
+        let list = sqlx::query_as::<_, UploadSession>(&query)
+            .bind(UploadSession::UPLOADING)
+            .bind(before)
+            .bind(UploadSession::ABORTED)
+            .bind(limit)
             .fetch_all(&*self.dao.pool)
             .await?;
-        return Ok(list_result);
+        Ok(list)
+    }
+}
+
+pub struct UploadPartRepository {
+    pub dao: BaseRepository<UploadPart>,
+}
+
+impl UploadPartRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool, "upload_part"),
+        }
+    }
+
+    /// 保存分片，同一分片号重复上传时覆盖，返回被覆盖的旧分片
+    ///
+    /// 调用方需要在同一事务中先用 `UploadSessionRepository::lock_with` 锁定会话；
+    /// 文件大小保存在 `file_info.size`（INT UNSIGNED），全部分片合计超过 4GB 时返回 `file.too.large`
+    pub async fn save_part_with<'c, A>(&self, db: A, upload_id: i64, part_number: i32, items: &Vec<FileItemDto>, size: u32) -> Result<Option<UploadPart>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {} WHERE upload_id = ? ORDER BY part_number ASC", self.dao.table_name);
+        let parts = sqlx::query_as::<_, UploadPart>(&query).bind(upload_id).fetch_all(&mut *conn).await?;
+        let others: u64 = parts.iter().filter(|part| part.part_number != part_number).map(|part| part.size as u64).sum();
+        if others + size as u64 > u32::MAX as u64 {
+            return Err(AppError::InvalidInput("file.too.large".to_owned()));
+        }
+        if let Some(old) = parts.into_iter().find(|part| part.part_number == part_number) {
+            let query = format!("UPDATE {} SET items = ?, size = ?, create_time = ? WHERE id = ?", self.dao.table_name);
+            sqlx::query(&query)
+                .bind(Json(items))
+                .bind(size)
+                .bind(build_time().await)
+                .bind(old.id)
+                .execute(&mut *conn)
+                .await?;
+            return Ok(Some(old));
+        }
+        let query = format!(
+            "INSERT INTO {} (id, upload_id, part_number, items, size, create_time) VALUES (?, ?, ?, ?, ?, ?)",
+            self.dao.table_name
+        );
+        sqlx::query(&query)
+            .bind(build_snow_id())
+            .bind(upload_id)
+            .bind(part_number)
+            .bind(Json(items))
+            .bind(size)
+            .bind(build_time().await)
+            .execute(&mut *conn)
+            .await?;
+        Ok(None)
+    }
+
+    pub async fn list_parts(&self, upload_id: i64) -> Result<Vec<UploadPart>, AppError> {
+        self.list_parts_with(&*self.dao.pool, upload_id).await
+    }
+
+    pub async fn list_parts_with<'c, A>(&self, db: A, upload_id: i64) -> Result<Vec<UploadPart>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {} WHERE upload_id = ? ORDER BY part_number ASC", self.dao.table_name);
+        let list = sqlx::query_as::<_, UploadPart>(&query)
+            .bind(upload_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(list)
+    }
+
+    /// 删除单个分片记录，返回是否由本次调用删除，删除成功的一方负责释放分片
+    pub async fn del_part(&self, id: i64) -> Result<bool, AppError> {
+        let query = format!("DELETE FROM {} WHERE id = ?", self.dao.table_name);
+        let result = sqlx::query(&query).bind(id).execute(&*self.dao.pool).await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    pub async fn del_by_upload_id(&self, upload_id: i64) -> Result<u64, AppError> {
+        self.del_by_upload_id_with(&*self.dao.pool, upload_id).await
+    }
+
+    pub async fn del_by_upload_id_with<'c, A>(&self, db: A, upload_id: i64) -> Result<u64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("DELETE FROM {} WHERE upload_id = ?", self.dao.table_name);
+        let result = sqlx::query(&query).bind(upload_id).execute(&mut *conn).await?;
+        Ok(result.rows_affected())
     }
 }
 #[derive(Debug, Serialize, Deserialize, FromRow, Validate, Clone)]
@@ -186,6 +937,8 @@ pub struct BucketInfoResult {
     pub user_name: String,
     //0 读 1 写 2 读写
     pub user_right: i32,
+    #[sqlx(default)]
+    pub permissions: i32,
 }
 
 pub struct UserBucketRepository {
@@ -199,19 +952,28 @@ impl UserBucketRepository {
         }
     }
 
-    pub async fn change_right(
+    /// 设置用户在 bucket 上的权限，同时写入兼容的 user_right；权限为空时删除授权记录
+    pub async fn change_permissions(
         &self,
         user_id: i64,
         bucket_id: i64,
-        right: i32,
+        permissions: PermissionSet,
     ) -> Result<(), AppError> {
         let list = self.dao.query_by_params(vec![
             QueryParam::eq("user_id", user_id.to_string().as_str()),
             QueryParam::eq("bucket_id", bucket_id.to_string().as_str())
         ]).await?;
-        if (list.len() == 1) {
+        // permissions 为 0 的记录会按旧版 user_right 换算，不能保留空权限的记录
+        if permissions == PermissionSet::NONE {
+            for item in list.iter() {
+                self.dao.del_by_id(item.id).await?;
+            }
+            return Ok(());
+        }
+        if list.len() == 1 {
             let mut params: HashMap<&str, String> = HashMap::new();
-            params.insert("user_right",right.to_string(),);
+            params.insert("user_right", permissions.legacy_right().to_string());
+            params.insert("permissions", permissions.bits().to_string());
             self.dao.change(list[0].id, params).await?;
             return Ok(());
         } else {
@@ -219,20 +981,22 @@ impl UserBucketRepository {
             params.insert("id", build_snow_id().to_string());
             params.insert("user_id", user_id.to_string());
             params.insert("bucket_id", bucket_id.to_string());
-            params.insert("user_right",right.to_string(),);
+            params.insert("user_right", permissions.legacy_right().to_string());
+            params.insert("permissions", permissions.bits().to_string());
             self.dao.insert(params).await?;
         }
         Ok(())
     }
 
-    pub async fn query_by_user_id_and_bucket_Id(&self, user_id: &i64, bucket_id: &i64) -> Result<Vec<BucketInfoResult>, AppError> {
-        let params: HashMap<&str, String> = HashMap::new();
-        let sql = format!(r#"
+    pub async fn query_by_user_id_and_bucket_id(&self, user_id: &i64, bucket_id: &i64) -> Result<Vec<BucketInfoResult>, AppError> {
+        let sql = r#"
             SELECT distinct
                 user_bucket.id,
                 bucket.id as bucket_id,
-                user_bucket.`right`,
-                bucket.`name`
+                user_info.id as user_id,
+                user_info.user_name,
+                user_bucket.user_right,
+                user_bucket.permissions
             FROM
                 user_bucket
                 INNER JOIN
@@ -244,9 +1008,9 @@ impl UserBucketRepository {
                 ON
                     user_bucket.bucket_id = bucket.id
             WHERE
-                user_bucket.user_id = {} and bucket_id={}
-                "#, user_id, bucket_id);
-        let vec = query_by_sql::<BucketInfoResult>(self.dao.pool.clone(), &sql, vec![]).await?;
+                user_bucket.user_id = ? and bucket_id = ?
+                "#;
+        let vec = query_by_sql::<BucketInfoResult>(self.dao.pool.clone(), sql, vec![user_id.to_string(), bucket_id.to_string()]).await?;
         Ok(vec)
     }
 
@@ -257,13 +1021,14 @@ impl UserBucketRepository {
         if bucket_id == &0 {
             return Err(AppError::InvalidInput("InvalidInput bucketId".to_owned()));
         }
-        let sql = format!(r#"
+        let sql = r#"
          SELECT
             user_bucket.bucket_id,
             user_info.user_name,
             user_info.id as user_id,
             user_bucket.id,
-            user_bucket.user_right
+            user_bucket.user_right,
+            user_bucket.permissions
         FROM
             user_bucket
             INNER JOIN
@@ -271,9 +1036,9 @@ impl UserBucketRepository {
             ON
                 user_bucket.user_id = user_info.id
         WHERE
-            user_bucket.bucket_id = {}
-                "#, bucket_id);
-        let vec = query_by_sql::<BucketInfoResult>(self.dao.pool.clone(), &sql, vec![]).await?;
+            user_bucket.bucket_id = ?
+                "#;
+        let vec = query_by_sql::<BucketInfoResult>(self.dao.pool.clone(), sql, vec![bucket_id.to_string()]).await?;
         Ok(vec)
     }
 }
@@ -289,3 +1054,245 @@ impl UserBucketRightRepository {
         }
     }
 }
+
+pub struct PathGrantRepository {
+    pub dao: BaseRepository<PathGrant>,
+}
+
+impl PathGrantRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool, "path_grant"),
+        }
+    }
+
+    /// 同一目录、同一对象、同一效果只保留一条授权，重复保存时覆盖权限
+    pub async fn save_grant(
+        &self,
+        bucket_id: i64,
+        path_id: i64,
+        subject_type: i32,
+        subject_id: i64,
+        effect: i32,
+        permissions: PermissionSet,
+    ) -> Result<i64, AppError> {
+        let list = self.dao.query_by_params(vec![
+            QueryParam::eq("path_id", path_id.to_string().as_str()),
+            QueryParam::eq("subject_type", subject_type.to_string().as_str()),
+            QueryParam::eq("subject_id", subject_id.to_string().as_str()),
+            QueryParam::eq("effect", effect.to_string().as_str()),
+        ]).await?;
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("permissions", permissions.bits().to_string());
+        if let Some(grant) = list.first() {
+            self.dao.change(grant.id, params).await?;
+            return Ok(grant.id);
+        }
+        let id = build_snow_id();
+        params.insert("id", id.to_string());
+        params.insert("bucket_id", bucket_id.to_string());
+        params.insert("path_id", path_id.to_string());
+        params.insert("subject_type", subject_type.to_string());
+        params.insert("subject_id", subject_id.to_string());
+        params.insert("effect", effect.to_string());
+        params.insert("create_time", build_time().await);
+        self.dao.insert(params).await?;
+        Ok(id)
+    }
+
+    /// 查询目录上对用户本人及其所在用户组生效的授权
+    pub async fn list_for_subject(&self, path_ids: &[i64], user_id: i64, group_ids: &[i64]) -> Result<Vec<PathGrant>, AppError> {
+        if path_ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let path_placeholders = vec!["?"; path_ids.len()].join(", ");
+        let group_condition = match group_ids.is_empty() {
+            true => String::new(),
+            false => format!(" OR (subject_type = ? AND subject_id IN ({}))", vec!["?"; group_ids.len()].join(", ")),
+        };
+        let query = format!(
+            "SELECT * FROM {} WHERE path_id IN ({}) AND ((subject_type = ? AND subject_id = ?){})",
+            self.dao.table_name, path_placeholders, group_condition
+        );
+        let mut query = sqlx::query_as::<_, PathGrant>(&query);
+        for path_id in path_ids {
+            query = query.bind(path_id);
+        }
+        query = query.bind(PathGrant::SUBJECT_USER).bind(user_id);
+        if !group_ids.is_empty() {
+            query = query.bind(PathGrant::SUBJECT_GROUP);
+            for group_id in group_ids {
+                query = query.bind(group_id);
+            }
+        }
+        Ok(query.fetch_all(&*self.dao.pool).await?)
+    }
+}
+
+pub struct UserGroupRepository {
+    pub dao: BaseRepository<UserGroup>,
+    pub member_dao: BaseRepository<UserGroupMember>,
+}
+
+impl UserGroupRepository {
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool.clone(), "user_group"),
+            member_dao: BaseRepository::new(pool, "user_group_member"),
+        }
+    }
+
+    pub async fn group_ids(&self, user_id: i64) -> Result<Vec<i64>, AppError> {
+        let list = self.member_dao.query_by_params(vec![QueryParam::eq("user_id", user_id.to_string().as_str())]).await?;
+        Ok(list.iter().map(|member| member.group_id).collect())
+    }
+
+    pub async fn add_member(&self, group_id: i64, user_id: i64) -> Result<(), AppError> {
+        let count = self.member_dao.query_by_count(vec![
+            QueryParam::eq("group_id", group_id.to_string().as_str()),
+            QueryParam::eq("user_id", user_id.to_string().as_str()),
+        ]).await?;
+        if count > 0 {
+            return Ok(());
+        }
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("id", build_snow_id().to_string());
+        params.insert("group_id", group_id.to_string());
+        params.insert("user_id", user_id.to_string());
+        self.member_dao.insert(params).await?;
+        Ok(())
+    }
+
+    pub async fn remove_member(&self, group_id: i64, user_id: i64) -> Result<u64, AppError> {
+        let query = format!("DELETE FROM {} WHERE group_id = ? AND user_id = ?", self.member_dao.table_name);
+        let result = sqlx::query(&query).bind(group_id).bind(user_id).execute(&*self.member_dao.pool).await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 删除用户组及其成员
+    pub async fn del_group(&self, group_id: i64) -> Result<(), AppError> {
+        let query = format!("DELETE FROM {} WHERE group_id = ?", self.member_dao.table_name);
+        sqlx::query(&query).bind(group_id).execute(&*self.member_dao.pool).await?;
+        self.dao.del_by_id(group_id).await?;
+        Ok(())
+    }
+}
+
+/// 通过 access key 认证的用户，`subject` 已带上密钥的范围
+#[derive(Debug, Clone)]
+pub struct Credential {
+    pub user: UserInfo,
+    pub secret_key: String,
+    pub subject: Subject,
+    pub key: ApiKey,
+}
+
+pub struct ApiKeyRepository {
+    pub dao: BaseRepository<ApiKey>,
+    user_dao: BaseRepository<UserInfo>,
+}
+
+impl ApiKeyRepository {
+    pub const PRESIGN_LABEL: &'static str = "presign";
+
+    pub fn new(pool: Arc<MySqlPool>) -> Self {
+        Self {
+            dao: BaseRepository::new(pool.clone(), "api_key"),
+            user_dao: BaseRepository::new(pool, "user_info"),
+        }
+    }
+
+    /// 返回的记录包含 secret，只在创建时返回给用户
+    pub async fn create(
+        &self,
+        user_id: i64,
+        label: &str,
+        bucket_id: i64,
+        read_only: bool,
+        expire_time: Option<&str>,
+    ) -> Result<ApiKey, AppError> {
+        let id = build_snow_id();
+        let mut params: HashMap<&str, String> = HashMap::new();
+        params.insert("id", id.to_string());
+        params.insert("user_id", user_id.to_string());
+        params.insert("label", label.to_string());
+        params.insert("access_key", build_id());
+        params.insert("secret_key", build_id());
+        params.insert("bucket_id", bucket_id.to_string());
+        params.insert("read_only", (read_only as i32).to_string());
+        if let Some(expire_time) = expire_time {
+            params.insert("expire_time", expire_time.to_string());
+        }
+        params.insert("revoked", "0".to_owned());
+        params.insert("create_time", build_time().await);
+        self.dao.insert(params).await?;
+        self.dao.find_by_id(id).await
+    }
+
+    pub async fn list_by_user(&self, user_id: i64) -> Result<Vec<ApiKey>, AppError> {
+        self.dao.query_by_params(vec![QueryParam::eq("user_id", user_id.to_string().as_str())]).await
+    }
+
+    /// 只能吊销自己的密钥，返回是否吊销成功
+    pub async fn revoke(&self, id: i64, user_id: i64) -> Result<bool, AppError> {
+        let query = format!("UPDATE {} SET revoked = 1 WHERE id = ? AND user_id = ? AND revoked = 0", self.dao.table_name);
+        let result = sqlx::query(&query).bind(id).bind(user_id).execute(&*self.dao.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// 预签名使用的密钥，没有可用的时新建；吊销后之前签发的预签名地址全部失效
+    pub async fn presign_key(&self, user_id: i64) -> Result<ApiKey, AppError> {
+        let now = Local::now().naive_local();
+        let keys = self.dao.query_by_params(vec![
+            QueryParam::eq("user_id", user_id.to_string().as_str()),
+            QueryParam::eq("label", Self::PRESIGN_LABEL),
+            QueryParam::eq("revoked", "0"),
+        ]).await?;
+        let key = keys
+            .into_iter()
+            .find(|key| key.bucket_id == 0 && !key.read_only && key.expire_time.is_none_or(|expire_time| expire_time > now));
+        match key {
+            Some(key) => Ok(key),
+            None => self.create(user_id, Self::PRESIGN_LABEL, 0, false, None).await,
+        }
+    }
+
+    /// 查询可用的密钥及所属用户，已吊销、已过期返回无权限，不存在返回 `NotFound("api_key.not.found")`
+    pub async fn find_credential(&self, access_key: &str) -> Result<Credential, AppError> {
+        let keys = self.dao.query_by_params(vec![QueryParam::eq("access_key", access_key)]).await?;
+        let key = match keys.into_iter().next() {
+            Some(key) => key,
+            None => return Err(AppError::NotFound("api_key.not.found".to_owned())),
+        };
+        if key.revoked {
+            return Err(AppError::NoRight("api_key.revoked".to_owned()));
+        }
+        if key.expire_time.is_some_and(|expire_time| expire_time <= Local::now().naive_local()) {
+            return Err(AppError::NoRight("api_key.expired".to_owned()));
+        }
+        let user = match self.user_dao.find_by_id(key.user_id).await {
+            Ok(user) => user,
+            Err(AppError::DBError(sqlx::Error::RowNotFound)) => return Err(AppError::NotFound("api_key.not.found".to_owned())),
+            Err(e) => return Err(e),
+        };
+        Ok(Credential {
+            secret_key: key.secret_key.clone(),
+            subject: Subject::from(&user).with_key(&key),
+            user,
+            key,
+        })
+    }
+
+    /// 签名校验通过后记录使用时间，一分钟内只更新一次
+    pub async fn mark_used(&self, credential: &Credential) {
+        let key = &credential.key;
+        let now = Local::now().naive_local();
+        if key.last_used_time.is_some_and(|last_used_time| now - last_used_time < chrono::Duration::minutes(1)) {
+            return;
+        }
+        let query = format!("UPDATE {} SET last_used_time = ? WHERE id = ?", self.dao.table_name);
+        if let Err(e) = sqlx::query(&query).bind(now).bind(key.id).execute(&*self.dao.pool).await {
+            log::error!("api key {} update last_used_time error: {}", key.id, e);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::UserRepository;
+use common::{hash_password, AppError, MigrateAction, MigrateConfig, PasswordPolicy};
+use log::info;
+use sqlx::migrate::{AppliedMigration, Migrate, MigrateError, Migrator};
+use sqlx::MySqlPool;
+use std::sync::Arc;
+
+/// 编译时嵌入 model/migrations 下的迁移，已发布的迁移不能修改，只能新增
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// 迁移的应用状态
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+    //已应用的迁移文件被修改过
+    pub changed: bool,
+}
+
+fn migrate_error(e: MigrateError) -> AppError {
+    AppError::InternalError(format!("migrate error: {}", e))
+}
+
+/// 执行 `migrate` 子命令，状态输出到标准输出
+pub async fn run_migrate(
+    pool: &Arc<MySqlPool>,
+    action: MigrateAction,
+    config: &MigrateConfig,
+    password_policy: &PasswordPolicy,
+) -> Result<(), AppError> {
+    match action {
+        MigrateAction::Up => migrate_up(pool, config, password_policy).await,
+        MigrateAction::Down { target } => migrate_down(pool, target).await,
+        MigrateAction::Status => {
+            for status in migrate_status(pool).await? {
+                let state = match (status.applied, status.changed) {
+                    (true, true) => "changed",
+                    (true, false) => "applied",
+                    (false, _) => "pending",
+                };
+                println!("{:<16} {:<8} {}", status.version, state, status.description);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// 执行全部未应用的迁移，然后按配置创建管理员
+pub async fn migrate_up(pool: &Arc<MySqlPool>, config: &MigrateConfig, password_policy: &PasswordPolicy) -> Result<(), AppError> {
+    MIGRATOR.run(&**pool).await.map_err(migrate_error)?;
+    info!("database migrated");
+    bootstrap_admin(pool, config, password_policy).await
+}
+
+/// 回滚版本大于 `target` 的迁移，`target` 为空时只回滚最后一个
+pub async fn migrate_down(pool: &Arc<MySqlPool>, target: Option<i64>) -> Result<(), AppError> {
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let applied = applied_migrations(pool).await?;
+            match applied.len() {
+                0 => return Ok(()),
+                1 => 0,
+                len => applied[len - 2].version,
+            }
+        }
+    };
+    MIGRATOR.undo(&**pool, target).await.map_err(migrate_error)?;
+    info!("database reverted to version {}", target);
+    Ok(())
+}
+
+/// 按版本列出嵌入的迁移及是否已应用
+pub async fn migrate_status(pool: &Arc<MySqlPool>) -> Result<Vec<MigrationStatus>, AppError> {
+    let applied = applied_migrations(pool).await?;
+    let list = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let applied = applied.iter().find(|applied| applied.version == migration.version);
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                applied: applied.is_some(),
+                changed: applied.is_some_and(|applied| applied.checksum != migration.checksum),
+            }
+        })
+        .collect();
+    Ok(list)
+}
+
+/// 已应用的迁移，按版本升序
+async fn applied_migrations(pool: &Arc<MySqlPool>) -> Result<Vec<AppliedMigration>, AppError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await.map_err(migrate_error)?;
+    let mut applied = conn.list_applied_migrations().await.map_err(migrate_error)?;
+    applied.sort_by_key(|applied| applied.version);
+    Ok(applied)
+}
+
+/// 配置了管理员账号且库中还没有管理员时创建，已有管理员时不做修改
+async fn bootstrap_admin(pool: &Arc<MySqlPool>, config: &MigrateConfig, password_policy: &PasswordPolicy) -> Result<(), AppError> {
+    if config.admin_user.is_empty() {
+        return Ok(());
+    }
+    password_policy.check(&config.admin_password)?;
+    let user_rep = UserRepository::new(pool.clone());
+    if user_rep.create_admin_if_absent(&config.admin_user, &hash_password(&config.admin_password)?).await? {
+        info!("bootstrap admin {} created", config.admin_user);
+    }
+    Ok(())
+}
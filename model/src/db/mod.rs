@@ -6,9 +6,17 @@ use sqlx::MySqlPool;
 use std::sync::Arc;
 pub mod repository;
 pub use repository::*;
+pub mod schema;
 pub mod biz_repository;
+#[allow(clippy::module_inception)]
 pub mod date_format;
+pub mod authorization;
+pub mod audit;
+pub mod migrate;
 pub use biz_repository::*;
+pub use authorization::*;
+pub use audit::*;
+pub use migrate::*;
 pub use date_format::*;
 
 pub fn configure(cfg: &mut web::ServiceConfig, pool: Arc<MySqlPool>) {
@@ -16,9 +24,17 @@ pub fn configure(cfg: &mut web::ServiceConfig, pool: Arc<MySqlPool>) {
     let path_rep: PathRepository = PathRepository::new(pool.clone());
     let bucket_rep: BucketRepository = BucketRepository::new(pool.clone());
     let user_bucket_rep: UserBucketRepository = UserBucketRepository::new(pool.clone());
-    let user_bucket_right: UserBucketRepository = UserBucketRepository::new(pool.clone());
+    let user_bucket_right: UserBucketRightRepository = UserBucketRightRepository::new(pool.clone());
     let file_rep: FileRepository = FileRepository::new(pool.clone());
     let del_rep  = PathDelTaskRepository::new(pool.clone());
+    let upload_session_rep = UploadSessionRepository::new(pool.clone());
+    let upload_part_rep = UploadPartRepository::new(pool.clone());
+    let chunk_rep = ChunkRepository::new(pool.clone());
+    let auth_service = AuthService::new(pool.clone());
+    let path_grant_rep = PathGrantRepository::new(pool.clone());
+    let user_group_rep = UserGroupRepository::new(pool.clone());
+    let audit_rep = AuditLogRepository::new(pool.clone());
+    let api_key_rep = ApiKeyRepository::new(pool.clone());
     cfg.app_data(web::Data::new(file_rep));
     cfg.app_data(web::Data::new(user_bucket_rep));
     cfg.app_data(web::Data::new(user_rep));
@@ -26,4 +42,12 @@ pub fn configure(cfg: &mut web::ServiceConfig, pool: Arc<MySqlPool>) {
     cfg.app_data(web::Data::new(path_rep));
     cfg.app_data(web::Data::new(bucket_rep));
     cfg.app_data(web::Data::new(del_rep));
+    cfg.app_data(web::Data::new(upload_session_rep));
+    cfg.app_data(web::Data::new(upload_part_rep));
+    cfg.app_data(web::Data::new(chunk_rep));
+    cfg.app_data(web::Data::new(auth_service));
+    cfg.app_data(web::Data::new(path_grant_rep));
+    cfg.app_data(web::Data::new(user_group_rep));
+    cfg.app_data(web::Data::new(audit_rep));
+    cfg.app_data(web::Data::new(api_key_rep));
 }
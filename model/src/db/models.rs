@@ -1,10 +1,10 @@
-use crate::date_format::date_format;
+use crate::date_format::{date_format, option_date_format};
+use common::{ChunkRef, FileDigest};
 use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::NaiveDateTime;
 use sqlx::types::Json;
 use sqlx::{FromRow, MySqlPool, Type};
 use std::path::Path;
-use std::str::FromStr;
 use sqlx::mysql::MySqlPoolOptions;
 use strum_macros::{AsRefStr, EnumString};
 //查询分页对像
@@ -22,15 +22,36 @@ pub struct UserInfo {
     pub access_key: String,
     #[serde(skip_serializing)]
     pub secret_key: String,
+    //加密后的 TOTP 密钥，未启用但不为空时表示正在绑定
+    #[serde(skip_serializing)]
+    #[sqlx(default)]
+    pub totp_secret: String,
+    #[sqlx(default)]
+    pub totp_enabled: bool,
+    //最后一次使用的验证码时间步
+    #[serde(skip_serializing)]
+    #[sqlx(default)]
+    pub totp_last_step: i64,
     #[serde(with = "date_format")]
     pub create_time: NaiveDateTime,
 }
+/// 两步验证的一次性恢复码，使用后删除
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+pub struct UserRecoveryCode {
+    pub id: i64,
+    pub user_id: i64,
+    pub code_hash: String,
+}
 #[derive(Debug, Serialize, Deserialize, FromRow,Clone)]
 pub struct UserBucket {
     pub id: i64,
     pub user_id: i64,
     pub bucket_id: i64,
+    //旧版权限：0 读 1 写 2 读写，仅在 permissions 为 0 时使用
     pub user_right: i32,
+    //权限集合，见 PermissionSet
+    #[sqlx(default)]
+    pub permissions: i32,
 }
 #[derive(Debug, Serialize, Deserialize, FromRow,Clone)]
 pub struct UserBucketRight {
@@ -51,8 +72,10 @@ pub struct UserBucketRightQueryResult {
 pub struct Bucket {
     pub id: i64,
     pub name: String,
-    pub quota: i32,
-    pub current_quota: i32,
+    //配额（字节），0 表示不限制
+    pub quota: i64,
+    //已用空间（字节），上传前占用，删除文件后归还
+    pub current_quota: i64,
     pub pub_read: bool,
     pub pub_write: bool,
     #[serde(with = "date_format")]
@@ -97,6 +120,82 @@ impl FileType {
             _ => FileType::NORMAL,
         }
     }
+
+    /// 下载时的 Content-Type，按文件类型再细分扩展名
+    pub fn content_type(&self, file_path: &str) -> &'static str {
+        let ext = Path::new(file_path)
+            .extension()
+            .and_then(std::ffi::OsStr::to_str)
+            .unwrap_or("")
+            .to_lowercase();
+        match self {
+            FileType::IMAGE => match ImageType::get_image_type(file_path) {
+                ImageType::JPG | ImageType::JPEG => "image/jpeg",
+                ImageType::PNG => "image/png",
+                ImageType::GIF => "image/gif",
+                ImageType::BMP => "image/bmp",
+                ImageType::TIF | ImageType::TIFF => "image/tiff",
+                ImageType::WEBP => "image/webp",
+                _ => "application/octet-stream",
+            },
+            FileType::SVG => match ext.as_str() {
+                "svg" => "image/svg+xml",
+                "eps" | "ai" => "application/postscript",
+                _ => "application/octet-stream",
+            },
+            FileType::VIDEO => match ext.as_str() {
+                "mp4" => "video/mp4",
+                "mkv" => "video/x-matroska",
+                "avi" => "video/x-msvideo",
+                "mov" => "video/quicktime",
+                "flv" => "video/x-flv",
+                _ => "application/octet-stream",
+            },
+            FileType::AUDIO => match ext.as_str() {
+                "mp3" => "audio/mpeg",
+                "wav" => "audio/wav",
+                "flac" => "audio/flac",
+                "aac" => "audio/aac",
+                _ => "application/octet-stream",
+            },
+            FileType::TEXT => match ext.as_str() {
+                "json" => "application/json",
+                "xml" => "application/xml",
+                "md" => "text/markdown; charset=utf-8",
+                _ => "text/plain; charset=utf-8",
+            },
+            FileType::SCRIPT => match ext.as_str() {
+                "html" => "text/html; charset=utf-8",
+                "css" => "text/css; charset=utf-8",
+                "js" => "text/javascript; charset=utf-8",
+                _ => "text/plain; charset=utf-8",
+            },
+            FileType::ZIP => match ext.as_str() {
+                "zip" => "application/zip",
+                "rar" => "application/vnd.rar",
+                "tar" => "application/x-tar",
+                "gz" => "application/gzip",
+                _ => "application/octet-stream",
+            },
+            FileType::DOC => match ext.as_str() {
+                "pdf" => "application/pdf",
+                "doc" => "application/msword",
+                "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+                "odt" => "application/vnd.oasis.opendocument.text",
+                "rtf" => "application/rtf",
+                _ => "application/octet-stream",
+            },
+            FileType::EXCEL => match ext.as_str() {
+                "xls" => "application/vnd.ms-excel",
+                "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+                "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+                "csv" => "text/csv; charset=utf-8",
+                "tsv" => "text/tab-separated-values; charset=utf-8",
+                _ => "application/octet-stream",
+            },
+            FileType::NORMAL | FileType::DIR => "application/octet-stream",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Type, EnumString, AsRefStr)]
@@ -148,43 +247,230 @@ pub struct FileInfo {
     pub items: Json<Vec<FileItemDto>>,
     pub image_type: ImageType,
     pub size: u32,
+    //文件摘要，旧数据为空
+    #[sqlx(default)]
+    pub sha256: String,
+    #[sqlx(default)]
+    pub md5: String,
+    //缩略图生成状态，见 FileInfo::THUMBNAIL_*
+    #[sqlx(default)]
+    pub thumbnail_status: i32,
+    #[sqlx(default)]
+    pub thumbnails: Option<Json<Vec<FileThumbnail>>>,
     #[serde(with = "date_format")]
     pub create_time: NaiveDateTime,
 }
 
 
-#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone, PartialEq, Eq)]
 pub struct FileItemDto{
     pub path:String,
-    pub size:u32
+    pub size:u32,
+    //分片 sha256，旧数据为空
+    #[serde(default)]
+    pub hash:String
+}
+/// 缩略图，`size` 为配置的最长边（像素），`width`、`height` 为实际尺寸
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileThumbnail {
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub item: FileItemDto,
+}
+/// 内容寻址的分片，相同内容只保存一份，引用计数归零时删除
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileChunk {
+    pub hash: String,
+    pub path: String,
+    pub size: u32,
+    pub ref_count: i32,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
 }
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone, Default)]
 pub struct PathDelTask{
     pub id: i64,
     pub path_id:i64,
+    //目录记录在创建任务时已删除，子目录的 parent 仍指向 path_id，按 path_id 逐级查找子目录和文件
+    pub bucket_id: i64,
+    pub full_path: String,
     pub del_file_status:bool,
     pub del_path_status:bool,
+    //worker 占用任务的时间，超时后其他 worker 可接手
+    #[serde(skip)]
+    pub lock_time: Option<NaiveDateTime>,
     #[serde(with = "date_format")]
     pub create_time: NaiveDateTime,
 }
-impl FileInfo {}
+impl FileInfo {
+    pub const THUMBNAIL_PENDING: i32 = 0;
+    pub const THUMBNAIL_DONE: i32 = 1;
+    pub const THUMBNAIL_FAILED: i32 = 2;
+    //上传时指定不生成
+    pub const THUMBNAIL_SKIPPED: i32 = 3;
+
+    pub fn chunk_refs(&self) -> Vec<ChunkRef> {
+        self.items.iter().map(|item| item.chunk_ref()).collect()
+    }
+    pub fn digest(&self) -> FileDigest {
+        FileDigest::new(&self.sha256, &self.md5)
+    }
+    pub fn thumbnail(&self, size: u32) -> Option<&FileThumbnail> {
+        self.thumbnails.as_ref()?.iter().find(|thumbnail| thumbnail.size == size)
+    }
+    /// 文件占用的全部分片，包括缩略图，删除文件时一并释放
+    pub fn stored_items(&self) -> Vec<FileItemDto> {
+        let mut items = self.items.0.clone();
+        if let Some(thumbnails) = &self.thumbnails {
+            items.extend(thumbnails.iter().map(|thumbnail| thumbnail.item.clone()));
+        }
+        items
+    }
+}
+impl FileItemDto {
+    pub fn chunk_ref(&self) -> ChunkRef {
+        ChunkRef {
+            key: self.path.clone(),
+            size: self.size as u64,
+            hash: self.hash.clone(),
+        }
+    }
+}
 #[derive(Debug, Serialize, Deserialize, FromRow, Clone, Default)]
 pub struct PathInfo {
     pub id: i64,
     pub bucket_id: i64,
     pub root: bool,
     pub path: String,
-    pub parent: String,
+    pub parent: i64,
+    pub full_path: String,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadSession {
+    pub id: i64,
+    pub bucket_id: i64,
+    pub path_ref: i64,
     pub full_path: String,
+    pub name: String,
+    //0 上传中 1 已完成 2 已取消
+    pub status: i32,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+impl UploadSession {
+    pub const UPLOADING: i32 = 0;
+    pub const COMPLETED: i32 = 1;
+    pub const ABORTED: i32 = 2;
+}
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadPart {
+    pub id: i64,
+    pub upload_id: i64,
+    pub part_number: i32,
+    #[serde(skip_serializing)]
+    pub items: Json<Vec<FileItemDto>>,
+    pub size: u32,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+
+/// 目录授权，对目录及其全部子目录和文件生效
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PathGrant {
+    pub id: i64,
+    pub bucket_id: i64,
+    pub path_id: i64,
+    //0 用户 1 用户组
+    pub subject_type: i32,
+    pub subject_id: i64,
+    //0 允许 1 拒绝
+    pub effect: i32,
+    //权限集合，见 PermissionSet
+    pub permissions: i32,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+impl PathGrant {
+    pub const SUBJECT_USER: i32 = 0;
+    pub const SUBJECT_GROUP: i32 = 1;
+    pub const EFFECT_ALLOW: i32 = 0;
+    pub const EFFECT_DENY: i32 = 1;
+}
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroup {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserGroupMember {
+    pub id: i64,
+    pub group_id: i64,
+    pub user_id: i64,
+}
+
+/// 用户的 API 密钥，一个用户可以有多个，secret 只在创建时返回
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+    pub id: i64,
+    pub user_id: i64,
+    //备注，区分不同的用途
+    pub label: String,
+    pub access_key: String,
+    #[serde(skip_serializing)]
+    pub secret_key: String,
+    //限定只能访问的 bucket，0 为不限制
+    pub bucket_id: i64,
+    //只读密钥只能下载和列表
+    pub read_only: bool,
+    //为空时不过期
+    #[serde(with = "option_date_format")]
+    pub expire_time: Option<NaiveDateTime>,
+    #[serde(with = "option_date_format")]
+    pub last_used_time: Option<NaiveDateTime>,
+    pub revoked: bool,
+    #[serde(with = "date_format")]
+    pub create_time: NaiveDateTime,
+}
+
+/// 操作审计，只追加不修改
+#[derive(Debug, Serialize, Deserialize, FromRow, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLog {
+    pub id: i64,
+    //操作用户，0 为未登录
+    pub actor_id: i64,
+    pub action: String,
+    pub bucket_id: i64,
+    //文件、目录、用户等对象 id
+    pub target_id: i64,
+    //对象名称或路径
+    pub target: String,
+    pub client_ip: String,
+    //success / denied / failed
+    pub outcome: String,
+    //失败原因
+    pub detail: String,
     #[serde(with = "date_format")]
     pub create_time: NaiveDateTime,
 }
 
-pub async fn get_conn(url: &String) -> MySqlPool {
-    let pool = MySqlPoolOptions::new()
+pub async fn get_conn(url: &str) -> MySqlPool {
+    MySqlPoolOptions::new()
         .max_connections(20)
-        .connect(&url)
+        .connect(url)
         .await
-        .expect("Failed to connect to database");
-    return pool;
+        .expect("Failed to connect to database")
 }
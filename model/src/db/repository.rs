@@ -1,31 +1,22 @@
 use async_trait::async_trait;
 use common::{AppError, OrderType, Page, PageInfo};
 use sqlx::mysql::{MySqlArguments, MySqlRow};
-use sqlx::MySqlPool;
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, MySql, MySqlPool, Transaction};
 use sqlx::{Arguments, FromRow};
 use std::collections::HashMap;
+use std::future::Future;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use super::schema;
+/// 执行带 `?` 占位符的 SQL，`values` 按顺序绑定
 pub async fn query_by_sql<T: for<'r> FromRow<'r, sqlx::mysql::MySqlRow> + Clone + Send + Sync + Unpin>
 (
     pool: Arc<MySqlPool>,
     sql: &str,
-    params: Vec<QueryParam>,
+    values: Vec<String>,
 ) -> Result<Vec<T>, AppError> {
-    let (where_clause, values) = build_where_clause(&params);
-    // let mut sql_query = sqlx::query_as::<_, T>(sql);
-    // for value in values {
-    //     sql_query = sql_query.bind(value);
-    // }
-    //
-    // let result = sql_query.fetch_all(pool).await?;
-    // Ok(result)
-
-
-
-
-
-    let mut sql_query = sqlx::query_as::<_, T>(&sql);
+    let mut sql_query = sqlx::query_as::<_, T>(sql);
     for value in values {
         sql_query = sql_query.bind(value);
     }
@@ -34,26 +25,55 @@ pub async fn query_by_sql<T: for<'r> FromRow<'r, sqlx::mysql::MySqlRow> + Clone
     Ok(result)
 }
 /// 定义 Repository Trait，所有 Repository 都要实现这些方法
+///
+/// `*_with` 方法在调用方传入的连接上执行，可以是连接池、连接或事务（`&mut tx`），
+/// 不带后缀的方法在连接池上执行
 #[async_trait]
 pub trait Repository<T: for<'r> sqlx::FromRow<'r, MySqlRow>> {
     //query all
     async fn get_all(&self) -> Result<Vec<T>, AppError>;
+    async fn get_all_with<'c, A>(&self, db: A) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //find by id
     async fn find_by_id(&self, id: i64) -> Result<T, AppError>;
+    async fn find_by_id_with<'c, A>(&self, db: A, id: i64) -> Result<T, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //delete by id
     async fn del_by_id(&self, id: i64) -> Result<u64, AppError>;
+    async fn del_by_id_with<'c, A>(&self, db: A, id: i64) -> Result<u64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //query by params
     async fn query_by_params(&self,  params: Vec<QueryParam>,) -> Result<Vec<T>, AppError>;
+    async fn query_by_params_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //query count
     async fn query_by_count(&self, params: Vec<QueryParam>,) -> Result<i64, AppError>;
-    //query by sql
-    async fn query_by_sql(&self, sql: &String) -> Result<Vec<T>, AppError>;
+    async fn query_by_count_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<i64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
+    //query by sql，sql 由调用方拼接，不能包含请求参数
+    async fn query_by_sql(&self, sql: &str) -> Result<Vec<T>, AppError>;
+    async fn query_by_sql_with<'c, A>(&self, db: A, sql: &str) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //page query
     async fn query_by_page(
         &self,
         params: Vec<QueryParam>,
         page_info: &PageInfo,
     ) -> Result<Page<T>, AppError>;
+    async fn query_by_page_with<'c, A>(
+        &self,
+        db: A,
+        params: Vec<QueryParam>,
+        page_info: &PageInfo,
+    ) -> Result<Page<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     async fn query_by_max_id(
         &self,
         id: i64,
@@ -61,31 +81,93 @@ pub trait Repository<T: for<'r> sqlx::FromRow<'r, MySqlRow>> {
         order_type: OrderType,
         page_size: &i16,
     ) -> Result<Vec<T>, AppError>;
+    async fn query_by_max_id_with<'c, A>(
+        &self,
+        db: A,
+        id: i64,
+        params: Vec<QueryParam>,
+        order_type: OrderType,
+        page_size: &i16,
+    ) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //query one
     async fn find_by_one(&self,  params: Vec<QueryParam>,) -> Result<T, AppError>;
+    async fn find_by_one_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<T, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //insert
     async fn insert(&self,   params: HashMap<&str,String>,) -> Result<u64, AppError>;
+    async fn insert_with<'c, A>(&self, db: A, params: HashMap<&str, String>) -> Result<u64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
     //change data by id
     async fn change(&self, id: i64,  params: HashMap<&str,String>,) -> Result<(), AppError>;
+    async fn change_with<'c, A>(&self, db: A, id: i64, params: HashMap<&str, String>) -> Result<(), AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send;
+}
+
+/// 在一个事务中执行多步操作，`f` 成功时需要把事务交回，由这里提交
+///
+/// `f` 返回错误时事务随之丢弃，连接归还连接池前回滚
+pub async fn with_transaction<R, F, Fut>(pool: &MySqlPool, f: F) -> Result<R, AppError>
+where
+    F: FnOnce(Transaction<'static, MySql>) -> Fut,
+    Fut: Future<Output = Result<(R, Transaction<'static, MySql>), AppError>>,
+{
+    let tx = pool.begin().await?;
+    let (result, tx) = f(tx).await?;
+    tx.commit().await?;
+    Ok(result)
 }
 
 /// 泛型 BaseRepository，支持所有表
 ///
+/// 值一律绑定参数，列名必须在 `schema` 登记的已知列中
 #[allow(dead_code)]
 pub struct BaseRepository<T> {
     pub pool: Arc<MySqlPool>, // 线程安全的数据库连接池
     pub table_name: &'static str,
+    pub columns: &'static [&'static str],
     _marker: PhantomData<T>,
 }
 
 impl<T> BaseRepository<T> {
     pub fn new(pool: Arc<MySqlPool>, table_name: &'static str) -> Self {
+        let columns = schema::table_columns(table_name)
+            .unwrap_or_else(|| panic!("table {} is not registered in schema", table_name));
         Self {
             pool,
             table_name,
+            columns,
             _marker: Default::default(),
         }
     }
+
+    /// 未知列返回 InvalidInput，不会进入 SQL
+    pub fn column(&self, name: &str) -> Result<&'static str, AppError> {
+        match self.columns.iter().find(|column| **column == name) {
+            Some(column) => Ok(column),
+            None => Err(AppError::InvalidInput(format!("column.invalid:{}", name))),
+        }
+    }
+
+    fn where_clause(&self, params: &[QueryParam]) -> Result<(String, Vec<String>), AppError> {
+        build_where_clause(self.columns, params)
+    }
+
+    /// 排序列为空时按 id 排序，没有 id 列的表不排序
+    fn order_clause(&self, order_column: &str, order_type: &OrderType) -> Result<String, AppError> {
+        let column = match order_column.is_empty() {
+            true => match self.column("id") {
+                Ok(column) => column,
+                Err(_) => return Ok(String::new()),
+            },
+            false => self.column(order_column)?,
+        };
+        Ok(format!(" ORDER BY `{}` {}", column, order_type.as_ref()))
+    }
 }
 
 #[async_trait]
@@ -94,37 +176,70 @@ where
     T: for<'r> FromRow<'r, sqlx::mysql::MySqlRow> + Send + Sync + Unpin + Clone, // 需要实现 `FromRow`
 {
     async fn get_all(&self) -> Result<Vec<T>, AppError> {
+        self.get_all_with(&*self.pool).await
+    }
+
+    async fn get_all_with<'c, A>(&self, db: A) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
         let query = format!("SELECT * FROM {}", self.table_name);
         let vec = sqlx::query_as::<_, T>(&query)
-            .fetch_all(&*self.pool)
+            .fetch_all(&mut *conn)
             .await?;
         return Ok(vec);
     }
 
 
     async fn find_by_id(&self, id: i64) -> Result<T, AppError> {
-        let query = format!("SELECT * FROM {} WHERE id = {}", self.table_name, id);
+        self.find_by_id_with(&*self.pool, id).await
+    }
+
+    async fn find_by_id_with<'c, A>(&self, db: A, id: i64) -> Result<T, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {} WHERE id = ?", self.table_name);
         let option = sqlx::query_as::<_, T>(&query)
-            .fetch_one(&*self.pool)
+            .bind(id)
+            .fetch_one(&mut *conn)
             .await?;
         return Ok(option);
     }
 
     async fn del_by_id(&self, id: i64) -> Result<u64, AppError> {
-        let query = format!("DELETE FROM {} WHERE id = {}", self.table_name, id);
-        let result = sqlx::query(&query).bind(id).execute(&*self.pool).await?;
+        self.del_by_id_with(&*self.pool, id).await
+    }
+
+    async fn del_by_id_with<'c, A>(&self, db: A, id: i64) -> Result<u64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let query = format!("DELETE FROM {} WHERE id = ?", self.table_name);
+        let result = sqlx::query(&query).bind(id).execute(&mut *conn).await?;
         Ok(result.rows_affected())
     }
 
     async fn find_by_one(&self, params: Vec<QueryParam>) -> Result<T, AppError> {
-        let (where_clause, values) = build_where_clause(&params);
-        let mut query = format!("SELECT * FROM {}{} ", self.table_name,where_clause);
+        self.find_by_one_with(&*self.pool, params).await
+    }
+
+    async fn find_by_one_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<T, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let (where_clause, values) = self.where_clause(&params)?;
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {}{} ", self.table_name,where_clause);
         let mut sql_query = sqlx::query_as::<_, T>(&query);
         for value in values {
             sql_query = sql_query.bind(value);
         }
 
-        let result = sql_query.fetch_all(&*self.pool).await?;
+        let result = sql_query.fetch_all(&mut *conn).await?;
         if result.is_empty(){
             return Err(AppError::NotFound("NotFound".to_string()));
         }
@@ -133,32 +248,55 @@ where
         }
         Ok(result[0].clone())
     }
-    async fn query_by_sql(&self, sql: &String) -> Result<Vec<T>, AppError> {
+    async fn query_by_sql(&self, sql: &str) -> Result<Vec<T>, AppError> {
+        self.query_by_sql_with(&*self.pool, sql).await
+    }
+    async fn query_by_sql_with<'c, A>(&self, db: A, sql: &str) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
         let sql_query = sqlx::query_as::<_, T>(sql);
-        let result = sql_query.fetch_all(&*self.pool).await?;
+        let result = sql_query.fetch_all(&mut *conn).await?;
         Ok(result)
     }
     async fn query_by_params(&self, params: Vec<QueryParam>) -> Result<Vec<T>, AppError> {
-
-
-        let (where_clause, values) = build_where_clause(&params);
-        let mut query = format!("SELECT * FROM {}{} ", self.table_name,where_clause);
+        self.query_by_params_with(&*self.pool, params).await
+    }
+    async fn query_by_params_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let (where_clause, values) = self.where_clause(&params)?;
+        let mut conn = db.acquire().await?;
+        let query = format!("SELECT * FROM {}{} ", self.table_name,where_clause);
         let mut sql_query = sqlx::query_as::<_, T>(&query);
         for value in values {
             sql_query = sql_query.bind(value);
         }
 
-        let result = sql_query.fetch_all(&*self.pool).await?;
+        let result = sql_query.fetch_all(&mut *conn).await?;
         Ok(result)
     }
 
     async fn insert(&self, params: HashMap<&str,String>) -> Result<u64, AppError> {
+        self.insert_with(&*self.pool, params).await
+    }
+
+    async fn insert_with<'c, A>(&self, db: A, params: HashMap<&str, String>) -> Result<u64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
         let keys: Vec<&str> = params.keys().cloned().collect();
+        let mut columns = Vec::with_capacity(keys.len());
+        for key in &keys {
+            columns.push(format!("`{}`", self.column(key)?));
+        }
         let placeholders = vec!["?"; keys.len()].join(", ");
         let query = format!(
             "INSERT INTO {} ({}) VALUES ({})",
             self.table_name,
-            keys.join(", "),
+            columns.join(", "),
             placeholders
         );
 
@@ -166,15 +304,26 @@ where
         for key_item in keys {
             let value = params.get(key_item).unwrap();
             sql_query = sql_query.bind(value);
-            println!(" → Param[{}],value:{}", key_item, value);
+            // 密码、密钥等字段不输出
+            match schema::SECRET_COLUMNS.contains(&key_item) {
+                true => log::debug!(" → Param[{}],value:******", key_item),
+                false => log::debug!(" → Param[{}],value:{}", key_item, value),
+            }
         }
-        println!(" → sql:{}",&query);
-        let result = sql_query.execute(&*self.pool).await?;
+        log::debug!(" → sql:{}", &query);
+        let mut conn = db.acquire().await?;
+        let result = sql_query.execute(&mut *conn).await?;
         Ok(result.rows_affected())
     }
     async fn change(&self, id: i64,  params: HashMap<&str,String>) -> Result<(), AppError> {
+        self.change_with(&*self.pool, id, params).await
+    }
+    async fn change_with<'c, A>(&self, db: A, id: i64, params: HashMap<&str, String>) -> Result<(), AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
         let mut query = String::from("UPDATE ");
-        query.push_str(&*self.table_name);
+        query.push_str(self.table_name);
         query.push_str(" SET ");
         let mut args = MySqlArguments::default();
         let mut first = true;
@@ -183,12 +332,13 @@ where
                 query.push(',');
             }
             first = false;
-            query.push_str(&format!(" {} = ?", key));
-            args.add(value);
+            query.push_str(&format!(" `{}` = ?", self.column(key)?));
+            args.add(value).map_err(|e| AppError::InternalError(e.to_string()))?;
         }
         query.push_str(" WHERE id = ?");
-        args.add(id);
-        sqlx::query_with(&query, args).execute(&*self.pool).await?;
+        args.add(id).map_err(|e| AppError::InternalError(e.to_string()))?;
+        let mut conn = db.acquire().await?;
+        sqlx::query_with(&query, args).execute(&mut *conn).await?;
         Ok(())
     }
 
@@ -196,7 +346,14 @@ where
         &self,
         params: Vec<QueryParam>,
     ) -> Result<i64, AppError> {
-        let (where_clause, values) = build_where_clause(&params);
+        self.query_by_count_with(&*self.pool, params).await
+    }
+
+    async fn query_by_count_with<'c, A>(&self, db: A, params: Vec<QueryParam>) -> Result<i64, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let (where_clause, values) = self.where_clause(&params)?;
         let sql = format!("SELECT COUNT(1) FROM {}{}", self.table_name, where_clause);
 
         let mut query = sqlx::query_scalar::<_, i64>(&sql);
@@ -204,29 +361,36 @@ where
             query = query.bind(val);
         }
 
-        let count = query.fetch_one(&*self.pool).await?;
+        let mut conn = db.acquire().await?;
+        let count = query.fetch_one(&mut *conn).await?;
         Ok(count)
     }
 
     async fn query_by_max_id(&self, id: i64, params: Vec<QueryParam>, order_type: OrderType,
                              page_size: &i16) -> Result<Vec<T>, AppError> {
+        self.query_by_max_id_with(&*self.pool, id, params, order_type, page_size).await
+    }
+
+    async fn query_by_max_id_with<'c, A>(&self, db: A, id: i64, params: Vec<QueryParam>, order_type: OrderType,
+                                         page_size: &i16) -> Result<Vec<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
 
         let mut max_params=params.clone();
         max_params.push(QueryParam::gt("id", id.to_string().as_str()));
-        let (where_clause, values) = build_where_clause(&max_params);
+        let (where_clause, values) = self.where_clause(&max_params)?;
         let mut query = format!("SELECT * FROM  {}{}", self.table_name, where_clause);
 
-        let order_type = match order_type {
-            (order_type) => order_type.as_ref().to_string(),
-            _ => "ASC".to_string(),
-        };
-        let order_str = &format!(" ORDER BY id {} LIMIT {} ", order_type, page_size);
-        query.push_str(order_str);
+        query.push_str(&self.order_clause("id", &order_type)?);
+        query.push_str(" LIMIT ?");
         let mut sql_query = sqlx::query_as::<_, T>(&query);
         for value in values {
             sql_query = sql_query.bind(value);
         }
-        let vec1 = sql_query.fetch_all(&*self.pool).await?;
+        sql_query = sql_query.bind(*page_size as i64);
+        let mut conn = db.acquire().await?;
+        let vec1 = sql_query.fetch_all(&mut *conn).await?;
         return Ok(vec1);
     }
     async fn query_by_page(
@@ -234,30 +398,38 @@ where
         params: Vec<QueryParam>,
         page_info: &PageInfo,
     ) -> Result<Page<T>, AppError> {
-        let mut offset = match page_info.index {
-            index => index + 1
-        } * page_info.page_size;
+        self.query_by_page_with(&*self.pool, params, page_info).await
+    }
+
+    async fn query_by_page_with<'c, A>(
+        &self,
+        db: A,
+        params: Vec<QueryParam>,
+        page_info: &PageInfo,
+    ) -> Result<Page<T>, AppError>
+    where
+        A: Acquire<'c, Database = MySql> + Send,
+    {
+        let mut conn = db.acquire().await?;
+        let mut offset = (page_info.index + 1) * page_info.page_size;
         let mut limit = page_info.page_size;
-        let count = self.query_by_count(params.clone()).await?;
-        if (count < page_info.page_size) {
+        let count = self.query_by_count_with(&mut *conn, params.clone()).await?;
+        if count < page_info.page_size {
             offset = 0;
             limit = page_info.page_size;
         }
-        let (where_clause, values) = build_where_clause(&params);
+        let (where_clause, values) = self.where_clause(&params)?;
         let mut query = format!("SELECT * FROM {}{}", self.table_name, where_clause);
 
-        let order_type = match &page_info.order_type {
-            (order_type) => order_type.as_ref().to_string(),
-            _ => "ASC".to_string(),
-        };
-        let order_str = &format!(" ORDER BY {} {} LIMIT {} OFFSET {}", page_info.order_column, order_type, limit, offset);
-        query.push_str(order_str);
+        query.push_str(&self.order_clause(&page_info.order_column, &page_info.order_type)?);
+        query.push_str(" LIMIT ? OFFSET ?");
         let mut sql_query = sqlx::query_as::<_, T>(&query);
         for value in values {
             sql_query = sql_query.bind(value);
         }
+        sql_query = sql_query.bind(limit).bind(offset);
 
-        let list = sql_query.fetch_all(&*self.pool).await?;
+        let list = sql_query.fetch_all(&mut *conn).await?;
         Ok(Page {
             total: count,
             data: list,
@@ -270,134 +442,357 @@ where
         })
     }
 }
-#[derive(Debug)]
-enum PropertyType {
-    Text,
-    Number,
-    Date,
-}
-#[derive(Debug, Eq, PartialEq,Clone)]
+//条件树最多嵌套的层数
+const MAX_FILTER_DEPTH: usize = 4;
+//IN 最多的取值个数
+const MAX_IN_VALUES: usize = 1000;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum QueryType {
     Equal,
+    NotEqual,
     GreaterThan,
+    GreaterOrEqual,
     LessThan,
+    LessOrEqual,
     Between,
-    LikeEnd, // like '%xxx'
+    LikeEnd, // like 'xxx%'，通配符按普通字符匹配
+    Contains, // like '%xxx%'
+    In,
+    IsNull,
+    IsNotNull,
+    //children 全部满足
+    And,
+    //children 满足任意一个
+    Or,
 }
-#[derive(Debug,Clone)]
+
+/// 查询条件，And/Or 时为条件组，可以嵌套
+///
+/// JSON 形式：`{"field":"name","queryType":"contains","values":["a"]}`，
+/// `{"queryType":"or","children":[...]}`，值一律为字符串
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct QueryParam {
+    #[serde(default)]
     pub field: String,
     pub query_type: QueryType,
+    #[serde(default)]
     pub values: Vec<String>,
+    #[serde(default)]
+    pub children: Vec<QueryParam>,
 }
 impl QueryParam {
-    pub fn eq(field: &str, value: &str) -> Self {
+    fn single(field: &str, query_type: QueryType, value: &str) -> Self {
         Self {
             field: field.to_string(),
-            query_type: QueryType::Equal,
+            query_type,
             values: vec![value.to_string()],
+            children: vec![],
         }
     }
 
+    pub fn eq(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::Equal, value)
+    }
+
+    pub fn ne(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::NotEqual, value)
+    }
+
     pub fn gt(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::GreaterThan, value)
+    }
+
+    pub fn ge(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::GreaterOrEqual, value)
+    }
+
+    pub fn lt(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::LessThan, value)
+    }
+
+    pub fn le(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::LessOrEqual, value)
+    }
+
+    pub fn between(field: &str, from: &str, to: &str) -> Self {
         Self {
             field: field.to_string(),
-            query_type: QueryType::GreaterThan,
-            values: vec![value.to_string()],
+            query_type: QueryType::Between,
+            values: vec![from.to_string(), to.to_string()],
+            children: vec![],
         }
     }
 
-    pub fn lt(field: &str, value: &str) -> Self {
+    pub fn like_end(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::LikeEnd, value)
+    }
+
+    /// 包含查询，通配符按普通字符匹配
+    pub fn contains(field: &str, value: &str) -> Self {
+        Self::single(field, QueryType::Contains, value)
+    }
+
+    pub fn is_in(field: &str, values: &[&str]) -> Self {
         Self {
             field: field.to_string(),
-            query_type: QueryType::LessThan,
-            values: vec![value.to_string()],
+            query_type: QueryType::In,
+            values: values.iter().map(|value| value.to_string()).collect(),
+            children: vec![],
         }
     }
 
-    pub fn between(field: &str, from: &str, to: &str) -> Self {
+    pub fn is_null(field: &str) -> Self {
         Self {
             field: field.to_string(),
-            query_type: QueryType::Between,
-            values: vec![from.to_string(), to.to_string()],
+            query_type: QueryType::IsNull,
+            values: vec![],
+            children: vec![],
         }
     }
 
-    pub fn like_end(field: &str, value: &str) -> Self {
+    pub fn is_not_null(field: &str) -> Self {
         Self {
             field: field.to_string(),
-            query_type: QueryType::LikeEnd,
-            values: vec![value.to_string()],
+            query_type: QueryType::IsNotNull,
+            values: vec![],
+            children: vec![],
         }
     }
 
+    pub fn and(children: Vec<QueryParam>) -> Self {
+        Self {
+            field: String::new(),
+            query_type: QueryType::And,
+            values: vec![],
+            children,
+        }
+    }
+
+    pub fn or(children: Vec<QueryParam>) -> Self {
+        Self {
+            field: String::new(),
+            query_type: QueryType::Or,
+            values: vec![],
+            children,
+        }
+    }
+
+    /// 空条件不参与查询
     pub fn is_empty(&self) -> bool {
-        if self.field.is_empty() {
-            return true;
+        match self.query_type {
+            QueryType::And | QueryType::Or => self.children.iter().all(|child| child.is_empty()),
+            QueryType::IsNull | QueryType::IsNotNull => self.field.is_empty(),
+            QueryType::Between => self.field.is_empty() || self.values.len() != 2,
+            _ => self.field.is_empty() || self.values.is_empty(),
         }
+    }
 
-        if self.values.len() == 0 {
-            return true;
+    /// 请求传入的条件不能使用敏感列
+    pub fn check_public(&self) -> Result<(), AppError> {
+        if schema::SECRET_COLUMNS.contains(&self.field.as_str()) {
+            return Err(AppError::InvalidInput(format!("column.invalid:{}", self.field)));
         }
-        if self.query_type == QueryType::Between {
-            if self.values.len() != 2 {
-                return true;
-            }
-            if self.query_type == QueryType::LessThan || self.query_type == QueryType::GreaterThan {
-                if self.values.len() != 1 {
-                    return true;
-                }
-            }
+        for child in &self.children {
+            child.check_public()?;
         }
-        if self.values.len()==1{
-            if self.query_type== QueryType::LikeEnd && self.query_type==QueryType::Equal{
-                if self.values[0].is_empty(){
-                    return true;
-                }
-            }
+        Ok(())
+    }
+}
+
+/// 分页查询，`filter` 为请求传入的过滤条件，按 AND 组合
+#[derive(Debug, Clone, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PageQuery {
+    #[serde(flatten)]
+    pub page: PageInfo,
+    #[serde(default)]
+    pub filter: Vec<QueryParam>,
+}
+
+impl PageQuery {
+    /// 校验后的过滤条件，排序列同样不能是敏感列
+    pub fn filter(&self) -> Result<Vec<QueryParam>, AppError> {
+        if schema::SECRET_COLUMNS.contains(&self.page.order_column.as_str()) {
+            return Err(AppError::InvalidInput(format!("column.invalid:{}", self.page.order_column)));
         }
-        return false;
+        for param in &self.filter {
+            param.check_public()?;
+        }
+        Ok(self.filter.clone())
     }
 }
-fn build_where_clause(params: &[QueryParam]) -> (String, Vec<String>) {
-    let mut clauses = Vec::new();
+/// 转义 LIKE 通配符，配合默认的 `\` 转义符使用
+pub fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+fn build_where_clause(columns: &[&str], params: &[QueryParam]) -> Result<(String, Vec<String>), AppError> {
     let mut values = Vec::new();
+    let where_sql = match build_group(columns, params, " AND ", 0, &mut values)? {
+        Some(clause) => format!(" WHERE {}", clause),
+        None => "".to_string(),
+    };
+    Ok((where_sql, values))
+}
 
+/// 条件全部为空时返回 None
+fn build_group(
+    columns: &[&str],
+    params: &[QueryParam],
+    separator: &str,
+    depth: usize,
+    values: &mut Vec<String>,
+) -> Result<Option<String>, AppError> {
+    if depth > MAX_FILTER_DEPTH {
+        return Err(AppError::InvalidInput("filter.too.deep".to_owned()));
+    }
+    let mut clauses = Vec::new();
     for param in params {
         if param.is_empty() {
             continue; // 🧼 跳过空值条件
         }
-        let field = &param.field;
-        match param.query_type {
-            QueryType::Equal => {
-                clauses.push(format!("{} = ?", field));
-                values.push(param.values[0].clone());
-            }
-            QueryType::GreaterThan => {
-                clauses.push(format!("{} > ?", field));
-                values.push(param.values[0].clone());
-            }
-            QueryType::LessThan => {
-                clauses.push(format!("{} < ?", field));
-                values.push(param.values[0].clone());
-            }
-            QueryType::Between => {
-                clauses.push(format!("{} BETWEEN ? AND ?", field));
-                values.push(param.values[0].clone());
-                values.push(param.values[1].clone());
-            }
-            QueryType::LikeEnd => {
-                clauses.push(format!("{} LIKE ?", field));
-                values.push(format!("{}%", param.values[0]));
+        clauses.push(build_condition(columns, param, depth, values)?);
+    }
+    Ok(match clauses.is_empty() {
+        true => None,
+        false => Some(clauses.join(separator)),
+    })
+}
+
+fn build_condition(columns: &[&str], param: &QueryParam, depth: usize, values: &mut Vec<String>) -> Result<String, AppError> {
+    let separator = match param.query_type {
+        QueryType::And => Some(" AND "),
+        QueryType::Or => Some(" OR "),
+        _ => None,
+    };
+    if let Some(separator) = separator {
+        let clause = build_group(columns, &param.children, separator, depth + 1, values)?;
+        return Ok(format!("({})", clause.unwrap_or_default()));
+    }
+    let field = match columns.contains(&param.field.as_str()) {
+        true => format!("`{}`", param.field),
+        false => return Err(AppError::InvalidInput(format!("column.invalid:{}", param.field))),
+    };
+    let clause = match param.query_type {
+        QueryType::Equal => format!("{} = ?", field),
+        QueryType::NotEqual => format!("{} <> ?", field),
+        QueryType::GreaterThan => format!("{} > ?", field),
+        QueryType::GreaterOrEqual => format!("{} >= ?", field),
+        QueryType::LessThan => format!("{} < ?", field),
+        QueryType::LessOrEqual => format!("{} <= ?", field),
+        QueryType::Between => {
+            values.push(param.values[0].clone());
+            values.push(param.values[1].clone());
+            return Ok(format!("{} BETWEEN ? AND ?", field));
+        }
+        QueryType::LikeEnd => {
+            values.push(format!("{}%", escape_like(&param.values[0])));
+            return Ok(format!("{} LIKE ?", field));
+        }
+        QueryType::Contains => {
+            values.push(format!("%{}%", escape_like(&param.values[0])));
+            return Ok(format!("{} LIKE ?", field));
+        }
+        QueryType::In => {
+            if param.values.len() > MAX_IN_VALUES {
+                return Err(AppError::InvalidInput("filter.too.many.values".to_owned()));
             }
+            values.extend(param.values.iter().cloned());
+            return Ok(format!("{} IN ({})", field, vec!["?"; param.values.len()].join(", ")));
+        }
+        QueryType::IsNull => return Ok(format!("{} IS NULL", field)),
+        QueryType::IsNotNull => return Ok(format!("{} IS NOT NULL", field)),
+        QueryType::And | QueryType::Or => unreachable!(),
+    };
+    values.push(param.values[0].clone());
+    Ok(clause)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUMNS: &[&str] = &["id", "name", "path"];
+
+    fn invalid<T: std::fmt::Debug>(result: Result<T, AppError>) -> String {
+        match result {
+            Err(AppError::InvalidInput(msg)) => msg,
+            other => panic!("expected InvalidInput, got {:?}", other),
         }
     }
 
-    let where_sql = if clauses.is_empty() {
-        "".to_string()
-    } else {
-        format!(" WHERE {}", clauses.join(" AND "))
-    };
+    #[test]
+    fn where_clause_binds_values() {
+        let params = vec![
+            QueryParam::eq("id", "1"),
+            QueryParam::is_in("name", &["a", "b"]),
+            QueryParam::between("id", "1", "9"),
+            QueryParam::is_null("path"),
+        ];
+        let (sql, values) = build_where_clause(COLUMNS, &params).unwrap();
+        assert_eq!(sql, " WHERE `id` = ? AND `name` IN (?, ?) AND `id` BETWEEN ? AND ? AND `path` IS NULL");
+        assert_eq!(values, vec!["1", "a", "b", "1", "9"]);
+    }
+
+    #[test]
+    fn like_values_are_escaped() {
+        let params = vec![QueryParam::like_end("path", "a_b%"), QueryParam::contains("name", "50%\\")];
+        let (sql, values) = build_where_clause(COLUMNS, &params).unwrap();
+        assert_eq!(sql, " WHERE `path` LIKE ? AND `name` LIKE ?");
+        assert_eq!(values, vec!["a\\_b\\%%", "%50\\%\\\\%"]);
+    }
+
+    #[test]
+    fn empty_params_are_skipped() {
+        let params = vec![QueryParam::eq("", "1"), QueryParam::is_in("name", &[]), QueryParam::or(vec![])];
+        assert_eq!(build_where_clause(COLUMNS, &params).unwrap(), (String::new(), vec![]));
+        let params = vec![QueryParam::or(vec![QueryParam::eq("id", "1"), QueryParam::eq("id", "2")])];
+        assert_eq!(build_where_clause(COLUMNS, &params).unwrap().0, " WHERE (`id` = ? OR `id` = ?)");
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert_eq!(invalid(build_where_clause(COLUMNS, &[QueryParam::eq("x", "1")])), "column.invalid:x");
 
-    (where_sql, values)
+        let values: Vec<String> = (0..=MAX_IN_VALUES).map(|i| i.to_string()).collect();
+        let values: Vec<&str> = values.iter().map(|value| value.as_str()).collect();
+        assert_eq!(invalid(build_where_clause(COLUMNS, &[QueryParam::is_in("id", &values)])), "filter.too.many.values");
+
+        let mut param = QueryParam::eq("id", "1");
+        for _ in 0..MAX_FILTER_DEPTH {
+            param = QueryParam::and(vec![param]);
+        }
+        assert!(build_where_clause(COLUMNS, &[param.clone()]).is_ok());
+        assert_eq!(invalid(build_where_clause(COLUMNS, &[QueryParam::or(vec![param])])), "filter.too.deep");
+    }
+
+    #[test]
+    fn secret_columns_are_not_public() {
+        let nested = QueryParam::or(vec![QueryParam::eq("name", "a"), QueryParam::eq("password", "x")]);
+        assert_eq!(invalid(nested.check_public()), "column.invalid:password");
+        assert!(QueryParam::eq("name", "a").check_public().is_ok());
+
+        let query = PageQuery {
+            page: PageInfo {
+                order_column: "secret_key".to_owned(),
+                ..Default::default()
+            },
+            filter: vec![],
+        };
+        assert_eq!(invalid(query.filter()), "column.invalid:secret_key");
+        let query = PageQuery {
+            page: PageInfo::default(),
+            filter: vec![QueryParam::like_end("totp_secret", "a")],
+        };
+        assert_eq!(invalid(query.filter()), "column.invalid:totp_secret");
+    }
 }
@@ -0,0 +1,59 @@
+//! 各表的已知列，BaseRepository 拼接 SQL 时列名必须在这里
+
+pub const USER_INFO: &[&str] = &[
+    "id", "is_admin", "user_name", "password", "access_key", "secret_key", "totp_secret", "totp_enabled", "totp_last_step", "create_time",
+];
+pub const USER_RECOVERY_CODE: &[&str] = &["id", "user_id", "code_hash", "create_time"];
+pub const USER_BUCKET: &[&str] = &["id", "user_id", "bucket_id", "user_right", "permissions"];
+pub const USER_BUCKET_RIGHT: &[&str] = &["access_key", "secret_key", "bucket_name", "right"];
+pub const BUCKET: &[&str] = &["id", "name", "quota", "current_quota", "pub_read", "pub_write", "create_time"];
+pub const PATH_INFO: &[&str] = &["id", "bucket_id", "root", "path", "parent", "full_path", "create_time"];
+pub const FILE_INFO: &[&str] = &[
+    "id", "root", "bucket_id", "path_ref", "name", "full_path", "file_type", "items", "image_type", "size", "sha256", "md5",
+    "thumbnail_status", "thumbnails", "create_time",
+];
+pub const FILE_CHUNK: &[&str] = &["hash", "path", "size", "ref_count", "create_time"];
+pub const PATH_DEL_TASK: &[&str] = &[
+    "id", "path_id", "bucket_id", "full_path", "del_file_status", "del_path_status", "lock_time", "create_time",
+];
+pub const UPLOAD_SESSION: &[&str] = &["id", "bucket_id", "path_ref", "full_path", "name", "status", "create_time"];
+pub const UPLOAD_PART: &[&str] = &["id", "upload_id", "part_number", "items", "size", "create_time"];
+pub const PATH_GRANT: &[&str] = &[
+    "id", "bucket_id", "path_id", "subject_type", "subject_id", "effect", "permissions", "create_time",
+];
+pub const USER_GROUP: &[&str] = &["id", "name", "create_time"];
+pub const USER_GROUP_MEMBER: &[&str] = &["id", "group_id", "user_id"];
+pub const API_KEY: &[&str] = &[
+    "id", "user_id", "label", "access_key", "secret_key", "bucket_id", "read_only", "expire_time", "last_used_time",
+    "revoked", "create_time",
+];
+pub const AUDIT_LOG: &[&str] = &[
+    "id", "actor_id", "action", "bucket_id", "target_id", "target", "client_ip", "outcome", "detail", "create_time",
+];
+
+//密码、密钥等列不能出现在请求传入的过滤和排序条件中
+pub const SECRET_COLUMNS: &[&str] = &["password", "access_key", "secret_key", "totp_secret", "code_hash"];
+
+/// 新增表时需要在这里登记
+pub fn table_columns(table_name: &str) -> Option<&'static [&'static str]> {
+    let columns = match table_name {
+        "user_info" => USER_INFO,
+        "user_recovery_code" => USER_RECOVERY_CODE,
+        "user_bucket" => USER_BUCKET,
+        "user_bucket_right" => USER_BUCKET_RIGHT,
+        "bucket" => BUCKET,
+        "path_info" => PATH_INFO,
+        "file_info" => FILE_INFO,
+        "file_chunk" => FILE_CHUNK,
+        "path_del_task" => PATH_DEL_TASK,
+        "upload_session" => UPLOAD_SESSION,
+        "upload_part" => UPLOAD_PART,
+        "path_grant" => PATH_GRANT,
+        "user_group" => USER_GROUP,
+        "user_group_member" => USER_GROUP_MEMBER,
+        "api_key" => API_KEY,
+        "audit_log" => AUDIT_LOG,
+        _ => return None,
+    };
+    Some(columns)
+}